@@ -17,61 +17,214 @@ public unsafe class DestinyHavok
         public ulong Length;
     }
     
+    public enum ShapeType : uint
+    {
+        Unknown = 0,
+        ConvexVertices = 1,
+        CompressedMesh = 2,
+        Box = 3,
+        HeightField = 4,
+        StaticCompound = 5,
+        List = 6,
+    }
+
     [StructLayout(LayoutKind.Sequential)]
     private struct CShape
     {
         public CArray<Vector3> Vertices;
-        public CArray<ushort> Indices;
+        public CArray<uint> Indices;
+        public Vector3 AabbMin;
+        public Vector3 AabbMax;
+        public ShapeType Type;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    private struct CShapeInstance
+    {
+        public uint ShapeIndex;
+        public uint MeshIndex;
+        public fixed float Transform[16];
+        public uint FilterInfo;
+        public uint CollisionLayer;
+        public uint SystemGroup;
+        public ulong MaterialId;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    private struct CShapeHierarchy
+    {
+        public CArray<CShape> Meshes;
+        public CArray<CShapeInstance> Instances;
     }
     
+    [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_read_type_compendium")]
+    private static extern IntPtr ReadTypeCompendium(IntPtr data, ulong length);
+
+    [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_free_type_compendium")]
+    private static extern void FreeTypeCompendium(IntPtr p);
+
     [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_read_shape_collection")]
-    private static extern CArray<CShape>* ReadShapeCollection(IntPtr data, ulong length);
+    private static extern CArray<CShape>* ReadShapeCollection(IntPtr data, ulong length, IntPtr compendium);
     
     [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_free_shape_collection")]
     private static extern void FreeShapeCollection(CArray<CShape>* p);
 
+    [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_read_shape_hierarchy")]
+    private static extern CShapeHierarchy* ReadShapeHierarchy(IntPtr data, ulong length, IntPtr compendium);
+
+    [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_free_shape_hierarchy")]
+    private static extern void FreeShapeHierarchy(CShapeHierarchy* p);
+
+    [DllImport("destiny_havok.dll", EntryPoint = "destinyhavok_last_error")]
+    private static extern IntPtr LastError();
+
+    /// Type information for shape collections that ship without a TYPE section
+    public sealed class TypeCompendium : IDisposable
+    {
+        internal IntPtr Handle;
+
+        public TypeCompendium(byte[] data)
+        {
+            var bufferPtr = Marshal.AllocCoTaskMem(data.Length);
+            Marshal.Copy(data, 0, bufferPtr, data.Length);
+
+            Handle = ReadTypeCompendium(bufferPtr, (ulong)data.Length);
+            Marshal.FreeCoTaskMem(bufferPtr);
+            if (Handle == IntPtr.Zero)
+                throw new Exception($"Failed to read type compendium: {LastErrorMessage()}");
+        }
+
+        public void Dispose()
+        {
+            FreeTypeCompendium(Handle);
+            Handle = IntPtr.Zero;
+        }
+    }
+
     public struct HavokShape
     {
         public Vector3[] Vertices;
-        public ushort[] Indices;
+        public uint[] Indices;
+        public Vector3 AabbMin;
+        public Vector3 AabbMax;
+        public ShapeType Type;
+    }
+
+    public struct HavokShapeInstance
+    {
+        public uint ShapeIndex;
+        public uint MeshIndex;
+        /// Column-major transform from mesh space to collection space
+        public float[] Transform;
+        public uint FilterInfo;
+        public uint CollisionLayer;
+        public uint SystemGroup;
+        public ulong MaterialId;
+    }
+
+    public struct HavokShapeHierarchy
+    {
+        public HavokShape[] Meshes;
+        public HavokShapeInstance[] Instances;
+    }
+
+    private static string LastErrorMessage()
+    {
+        return Marshal.PtrToStringUTF8(LastError()) ?? "unknown error";
+    }
+
+    private static HavokShape ConvertShape(CShape shape)
+    {
+        var vertices = new Vector3[shape.Vertices.Length];
+        var indices = new uint[shape.Indices.Length];
+
+        for (ulong j = 0; j < shape.Vertices.Length; j++)
+        {
+            vertices[j] = shape.Vertices.Data[j];
+        }
+
+        for (ulong j = 0; j < shape.Indices.Length; j++)
+        {
+            indices[j] = shape.Indices.Data[j];
+        }
+
+        return new HavokShape
+        {
+            Vertices = vertices,
+            Indices = indices,
+            AabbMin = shape.AabbMin,
+            AabbMax = shape.AabbMax,
+            Type = shape.Type
+        };
     }
     
-    public static HavokShape[] ReadShapeCollection(byte[] data)
+    public static HavokShape[] ReadShapeCollection(byte[] data, TypeCompendium? compendium = null)
     {
         var bufferPtr = Marshal.AllocCoTaskMem(data.Length);
         Marshal.Copy(data, 0, bufferPtr, data.Length);
 
-        var shapeCollectionPtr = ReadShapeCollection(bufferPtr, (ulong)data.Length);
+        var shapeCollectionPtr = ReadShapeCollection(bufferPtr, (ulong)data.Length, compendium?.Handle ?? IntPtr.Zero);
+        Marshal.FreeCoTaskMem(bufferPtr);
         if (shapeCollectionPtr == null)
-            throw new Exception("Failed to read shape collection, destiny-havok returned null.");
+            throw new Exception($"Failed to read shape collection: {LastErrorMessage()}");
         
         var shapeCollection = *shapeCollectionPtr;
         
         var shapes = new HavokShape[shapeCollection.Length];
         for (ulong i = 0; i < shapeCollection.Length; i++)
         {
-            var shape = shapeCollection.Data[i];
-            var vertices = new Vector3[shape.Vertices.Length];
-            var indices = new ushort[shape.Indices.Length];
-            
-            for (ulong j = 0; j < shape.Vertices.Length; j++)
-            {
-                vertices[j] = shape.Vertices.Data[j];
-            }
-            
-            for (ulong j = 0; j < shape.Indices.Length; j++)
+            shapes[i] = ConvertShape(shapeCollection.Data[i]);
+        }
+
+        FreeShapeCollection(shapeCollectionPtr);
+        return shapes;
+    }
+
+    public static HavokShapeHierarchy ReadShapeHierarchy(byte[] data, TypeCompendium? compendium = null)
+    {
+        var bufferPtr = Marshal.AllocCoTaskMem(data.Length);
+        Marshal.Copy(data, 0, bufferPtr, data.Length);
+
+        var hierarchyPtr = ReadShapeHierarchy(bufferPtr, (ulong)data.Length, compendium?.Handle ?? IntPtr.Zero);
+        Marshal.FreeCoTaskMem(bufferPtr);
+        if (hierarchyPtr == null)
+            throw new Exception($"Failed to read shape hierarchy: {LastErrorMessage()}");
+
+        var hierarchy = *hierarchyPtr;
+
+        var meshes = new HavokShape[hierarchy.Meshes.Length];
+        for (ulong i = 0; i < hierarchy.Meshes.Length; i++)
+        {
+            meshes[i] = ConvertShape(hierarchy.Meshes.Data[i]);
+        }
+
+        var instances = new HavokShapeInstance[hierarchy.Instances.Length];
+        for (ulong i = 0; i < hierarchy.Instances.Length; i++)
+        {
+            var instance = hierarchy.Instances.Data[i];
+            var transform = new float[16];
+            for (var j = 0; j < 16; j++)
             {
-                indices[j] = shape.Indices.Data[j];
+                transform[j] = instance.Transform[j];
             }
 
-            shapes[i] = new HavokShape
+            instances[i] = new HavokShapeInstance
             {
-                Vertices = vertices,
-                Indices = indices
+                ShapeIndex = instance.ShapeIndex,
+                MeshIndex = instance.MeshIndex,
+                Transform = transform,
+                FilterInfo = instance.FilterInfo,
+                CollisionLayer = instance.CollisionLayer,
+                SystemGroup = instance.SystemGroup,
+                MaterialId = instance.MaterialId
             };
         }
 
-        FreeShapeCollection(shapeCollectionPtr);
-        return shapes;
+        FreeShapeHierarchy(hierarchyPtr);
+        return new HavokShapeHierarchy
+        {
+            Meshes = meshes,
+            Instances = instances
+        };
     }
 }
\ No newline at end of file
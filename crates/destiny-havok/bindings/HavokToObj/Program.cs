@@ -2,7 +2,9 @@
 using HavokToObj;
 
 var buffer = File.ReadAllBytes(args[0]);
-var shapeCollection = DestinyHavok.ReadShapeCollection(buffer);
+// Optional type compendium for files without a TYPE section
+using var compendium = args.Length > 1 ? new DestinyHavok.TypeCompendium(File.ReadAllBytes(args[1])) : null;
+var shapeCollection = DestinyHavok.ReadShapeCollection(buffer, compendium);
 
 Directory.CreateDirectory("shapes");
 int i = 0;
@@ -0,0 +1,177 @@
+#ifndef DESTINY_HAVOK_H
+#define DESTINY_HAVOK_H
+
+/* Generated by cbindgen from src/lib.rs, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Havok class a shape was read from
+ */
+enum CShapeType
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  C_SHAPE_TYPE_UNKNOWN = 0,
+  C_SHAPE_TYPE_CONVEX_VERTICES = 1,
+  C_SHAPE_TYPE_COMPRESSED_MESH = 2,
+  C_SHAPE_TYPE_BOX = 3,
+  C_SHAPE_TYPE_HEIGHT_FIELD = 4,
+  C_SHAPE_TYPE_STATIC_COMPOUND = 5,
+  C_SHAPE_TYPE_LIST = 6,
+};
+#ifndef __cplusplus
+typedef uint32_t CShapeType;
+#endif // __cplusplus
+
+/**
+ * Type information for tagfiles without a TYPE section, opaque to C
+ */
+typedef struct CTypeCompendium CTypeCompendium;
+
+typedef struct CVec3 {
+  float x;
+  float y;
+  float z;
+} CVec3;
+
+typedef struct CArrayCVec3 {
+  struct CVec3 *data;
+  uintptr_t len;
+} CArrayCVec3;
+
+typedef struct CArrayU32 {
+  uint32_t *data;
+  uintptr_t len;
+} CArrayU32;
+
+typedef struct CShape {
+  struct CArrayCVec3 vertices;
+  struct CArrayU32 indices;
+  struct CVec3 aabb_min;
+  struct CVec3 aabb_max;
+  CShapeType shape_type;
+} CShape;
+
+typedef struct CArrayCShape {
+  struct CShape *data;
+  uintptr_t len;
+} CArrayCShape;
+
+/**
+ * A placement of a mesh from `CShapeHierarchy::meshes`
+ */
+typedef struct CShapeInstance {
+  /**
+   * Index of the shape in the collection this instance belongs to
+   */
+  uint32_t shape_index;
+  /**
+   * Index into `CShapeHierarchy::meshes`
+   */
+  uint32_t mesh_index;
+  /**
+   * Column-major transform from mesh space to collection space
+   */
+  float transform[16];
+  /**
+   * Raw `hkpGroupFilter` collision filter info, 0 if the instance has none
+   */
+  uint32_t filter_info;
+  /**
+   * Collision layer, lower 5 bits of `filter_info`
+   */
+  uint32_t collision_layer;
+  /**
+   * System group, upper 16 bits of `filter_info`
+   */
+  uint32_t system_group;
+  uint64_t material_id;
+} CShapeInstance;
+
+typedef struct CArrayCShapeInstance {
+  struct CShapeInstance *data;
+  uintptr_t len;
+} CArrayCShapeInstance;
+
+typedef struct CShapeHierarchy {
+  /**
+   * Every distinct mesh, without any instance transform applied
+   */
+  struct CArrayCShape meshes;
+  struct CArrayCShapeInstance instances;
+} CShapeHierarchy;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns the error message of the last failed call on this thread, or null if it succeeded.
+ *
+ * The string is owned by the library and stays valid until the next call on the same thread.
+ */
+const char *destinyhavok_last_error(void);
+
+/**
+ * Reads a type compendium (TCM0), needed for shape collections that ship without a TYPE section.
+ * Returns null on failure, see `destinyhavok_last_error`.
+ *
+ * # Safety
+ * `data` must be null or point to `len` readable bytes
+ */
+struct CTypeCompendium *destinyhavok_read_type_compendium(uint8_t *data, uintptr_t len);
+
+/**
+ * # Safety
+ * `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+ */
+void destinyhavok_free_type_compendium(struct CTypeCompendium *compendium);
+
+/**
+ * Reads every shape in the collection, with child shapes flattened into their parent.
+ * `compendium` may be null if the file carries its own types.
+ * Returns null on failure, see `destinyhavok_last_error`.
+ *
+ * # Safety
+ * `data` must be null or point to `len` readable bytes,
+ * `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+ */
+struct CArrayCShape *destinyhavok_read_shape_collection(uint8_t *data,
+                                                        uintptr_t len,
+                                                        const struct CTypeCompendium *compendium);
+
+/**
+ * # Safety
+ * `array` must be null or a pointer returned by `destinyhavok_read_shape_collection`
+ */
+void destinyhavok_free_shape_collection(struct CArrayCShape *array);
+
+/**
+ * Reads the shape collection with instances kept separate, so shared meshes are only returned once.
+ * `compendium` may be null if the file carries its own types.
+ * Returns null on failure, see `destinyhavok_last_error`.
+ *
+ * # Safety
+ * `data` must be null or point to `len` readable bytes,
+ * `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+ */
+struct CShapeHierarchy *destinyhavok_read_shape_hierarchy(uint8_t *data,
+                                                          uintptr_t len,
+                                                          const struct CTypeCompendium *compendium);
+
+/**
+ * # Safety
+ * `hierarchy` must be null or a pointer returned by `destinyhavok_read_shape_hierarchy`
+ */
+void destinyhavok_free_shape_hierarchy(struct CShapeHierarchy *hierarchy);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* DESTINY_HAVOK_H */
@@ -15,8 +15,13 @@ impl<T> CArray<T> {
 
 impl<T> Drop for CArray<T> {
     fn drop(&mut self) {
+        // Empty boxed slices don't own an allocation
+        if self.data.is_null() || self.len == 0 {
+            return;
+        }
+
         unsafe {
-            let _ = Box::from_raw(self.data);
+            let _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.data, self.len));
         };
     }
 }
@@ -1,44 +1,406 @@
 pub mod array;
 
+use std::{cell::RefCell, ffi::CString, io::Cursor, os::raw::c_char};
+
+use destiny_havok::{
+    shape_collection::{Shape, ShapeHierarchy},
+    type_registry::TypeCompendium,
+    view::HavokTagFileView,
+};
+
+fn read_hierarchy(
+    data: &[u8],
+    compendium: Option<&TypeCompendium>,
+) -> anyhow::Result<ShapeHierarchy> {
+    HavokTagFileView::with_compendium(data, compendium)?.read_shape_hierarchy()
+}
+
+/// # Safety
+/// `data` must be null or point to `len` readable bytes
+unsafe fn input_slice<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        return &[];
+    }
+
+    unsafe { std::slice::from_raw_parts(data, len) }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(e: anyhow::Error) {
+    let message = CString::new(format!("{e:#}").replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Type information for tagfiles without a TYPE section, opaque to C
+pub struct CTypeCompendium(TypeCompendium);
+
+impl CTypeCompendium {
+    /// # Safety
+    /// `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+    unsafe fn as_ref<'a>(compendium: *const CTypeCompendium) -> Option<&'a TypeCompendium> {
+        unsafe { compendium.as_ref() }.map(|c| &c.0)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CVec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl From<glam::Vec3> for CVec3 {
+    fn from(v: glam::Vec3) -> Self {
+        Self {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+}
+
+/// Havok class a shape was read from
+#[repr(u32)]
+#[derive(Clone, Copy)]
+pub enum CShapeType {
+    Unknown = 0,
+    ConvexVertices = 1,
+    CompressedMesh = 2,
+    Box = 3,
+    HeightField = 4,
+    StaticCompound = 5,
+    List = 6,
+}
+
+impl CShapeType {
+    fn from_class_name(name: &str) -> Self {
+        match name {
+            "hkpConvexVerticesShape" => CShapeType::ConvexVertices,
+            "hkpBvCompressedMeshShape" => CShapeType::CompressedMesh,
+            "hkpBoxShape" => CShapeType::Box,
+            "hkpCompressedSampledHeightFieldShape"
+            | "hkpStorageSampledHeightFieldShape"
+            | "hkpTriSampledHeightFieldCollection"
+            | "hkpTriSampledHeightFieldBvTreeShape" => CShapeType::HeightField,
+            "hkpStaticCompoundShape" => CShapeType::StaticCompound,
+            "hkpListShape" | "Unk81" => CShapeType::List,
+            _ => CShapeType::Unknown,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct CShape {
-    pub vertices: array::CArray<[f32; 3]>,
-    pub indices: array::CArray<u16>,
+    pub vertices: array::CArray<CVec3>,
+    pub indices: array::CArray<u32>,
+    pub aabb_min: CVec3,
+    pub aabb_max: CVec3,
+    pub shape_type: CShapeType,
+}
+
+impl CShape {
+    fn new(shape: Shape, class_name: &str) -> Self {
+        let (min, max) = shape.min_max();
+        CShape {
+            vertices: array::CArray::new(
+                shape
+                    .vertices
+                    .into_iter()
+                    .map(CVec3::from)
+                    .collect::<Vec<_>>()
+                    .into_boxed_slice(),
+            ),
+            indices: array::CArray::new(shape.indices.into_boxed_slice()),
+            aabb_min: min.into(),
+            aabb_max: max.into(),
+            shape_type: CShapeType::from_class_name(class_name),
+        }
+    }
+}
+
+/// A placement of a mesh from `CShapeHierarchy::meshes`
+#[repr(C)]
+pub struct CShapeInstance {
+    /// Index of the shape in the collection this instance belongs to
+    pub shape_index: u32,
+    /// Index into `CShapeHierarchy::meshes`
+    pub mesh_index: u32,
+    /// Column-major transform from mesh space to collection space
+    pub transform: [f32; 16],
+    /// Raw `hkpGroupFilter` collision filter info, 0 if the instance has none
+    pub filter_info: u32,
+    /// Collision layer, lower 5 bits of `filter_info`
+    pub collision_layer: u32,
+    /// System group, upper 16 bits of `filter_info`
+    pub system_group: u32,
+    pub material_id: u64,
 }
 
+#[repr(C)]
+pub struct CShapeHierarchy {
+    /// Every distinct mesh, without any instance transform applied
+    pub meshes: array::CArray<CShape>,
+    pub instances: array::CArray<CShapeInstance>,
+}
+
+/// Returns the error message of the last failed call on this thread, or null if it succeeded.
+///
+/// The string is owned by the library and stays valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn destinyhavok_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |s| s.as_ptr())
+    })
+}
+
+/// Reads a type compendium (TCM0), needed for shape collections that ship without a TYPE section.
+/// Returns null on failure, see `destinyhavok_last_error`.
+///
+/// # Safety
+/// `data` must be null or point to `len` readable bytes
 #[no_mangle]
-pub extern "C" fn destinyhavok_read_shape_collection(
+pub unsafe extern "C" fn destinyhavok_read_type_compendium(
     data: *mut u8,
     len: usize,
+) -> *mut CTypeCompendium {
+    let data = unsafe { input_slice(data, len) };
+
+    match TypeCompendium::read(&mut Cursor::new(data)) {
+        Ok(compendium) => {
+            clear_last_error();
+            Box::into_raw(Box::new(CTypeCompendium(compendium)))
+        }
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+#[no_mangle]
+pub unsafe extern "C" fn destinyhavok_free_type_compendium(compendium: *mut CTypeCompendium) {
+    if !compendium.is_null() {
+        let _ = unsafe { Box::from_raw(compendium) };
+    }
+}
+
+/// Reads every shape in the collection, with child shapes flattened into their parent.
+/// `compendium` may be null if the file carries its own types.
+/// Returns null on failure, see `destinyhavok_last_error`.
+///
+/// # Safety
+/// `data` must be null or point to `len` readable bytes,
+/// `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+#[no_mangle]
+pub unsafe extern "C" fn destinyhavok_read_shape_collection(
+    data: *mut u8,
+    len: usize,
+    compendium: *const CTypeCompendium,
 ) -> *mut array::CArray<CShape> {
-    let data = unsafe { std::slice::from_raw_parts_mut(data, len) };
-    let mut cursor = std::io::Cursor::new(data);
-    let data = destiny_havok::shape_collection::read_shape_collection(&mut cursor);
-
-    if let Ok(data) = data {
-        let data_converted = data
-            .into_iter()
-            .map(|shape| CShape {
-                vertices: array::CArray::new(
-                    shape
-                        .vertices
-                        .into_iter()
-                        .map(|v| v.to_array())
-                        .collect::<Vec<[f32; 3]>>()
-                        .into_boxed_slice(),
-                ),
-                indices: array::CArray::new(shape.indices.into_boxed_slice()),
-            })
-            .collect::<Vec<_>>();
+    let data = unsafe { input_slice(data, len) };
+
+    let result = read_hierarchy(data, unsafe { CTypeCompendium::as_ref(compendium) });
+
+    match result {
+        Ok(hierarchy) => {
+            clear_last_error();
+            let data_converted = hierarchy
+                .roots
+                .iter()
+                .map(|&root| CShape::new(hierarchy.flatten(root), &hierarchy.node(root).class_name))
+                .collect::<Vec<_>>();
 
-        let result = array::CArray::new(data_converted.into_boxed_slice());
-        Box::into_raw(Box::new(result))
-    } else {
-        std::ptr::null_mut()
+            let result = array::CArray::new(data_converted.into_boxed_slice());
+            Box::into_raw(Box::new(result))
+        }
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
     }
 }
 
+/// # Safety
+/// `array` must be null or a pointer returned by `destinyhavok_read_shape_collection`
 #[no_mangle]
-pub extern "C" fn destinyhavok_free_shape_collection(array: *mut array::CArray<CShape>) {
-    let _ = unsafe { Box::from_raw(array) };
+pub unsafe extern "C" fn destinyhavok_free_shape_collection(array: *mut array::CArray<CShape>) {
+    if !array.is_null() {
+        let _ = unsafe { Box::from_raw(array) };
+    }
+}
+
+/// Reads the shape collection with instances kept separate, so shared meshes are only returned once.
+/// `compendium` may be null if the file carries its own types.
+/// Returns null on failure, see `destinyhavok_last_error`.
+///
+/// # Safety
+/// `data` must be null or point to `len` readable bytes,
+/// `compendium` must be null or a pointer returned by `destinyhavok_read_type_compendium`
+#[no_mangle]
+pub unsafe extern "C" fn destinyhavok_read_shape_hierarchy(
+    data: *mut u8,
+    len: usize,
+    compendium: *const CTypeCompendium,
+) -> *mut CShapeHierarchy {
+    let data = unsafe { input_slice(data, len) };
+
+    let hierarchy = match read_hierarchy(data, unsafe { CTypeCompendium::as_ref(compendium) }) {
+        Ok(hierarchy) => hierarchy,
+        Err(e) => {
+            set_last_error(e);
+            return std::ptr::null_mut();
+        }
+    };
+    clear_last_error();
+
+    // Maps hierarchy nodes to mesh indices
+    let mut mesh_indices = vec![None; hierarchy.nodes.len()];
+    let mut meshes = vec![];
+    let mut instances = vec![];
+    for (shape_index, &root) in hierarchy.roots.iter().enumerate() {
+        for leaf in hierarchy.leaves(root) {
+            let node = hierarchy.node(leaf.node);
+            let Some(mesh) = node.mesh() else {
+                continue;
+            };
+
+            let collision = leaf.collision.unwrap_or_default();
+            let mesh_index = *mesh_indices[leaf.node].get_or_insert_with(|| {
+                meshes.push(CShape::new(mesh.clone(), &node.class_name));
+                meshes.len() as u32 - 1
+            });
+
+            instances.push(CShapeInstance {
+                shape_index: shape_index as u32,
+                mesh_index,
+                transform: leaf.transform.to_cols_array(),
+                filter_info: collision.filter_info.0,
+                collision_layer: collision.filter_info.layer(),
+                system_group: collision.filter_info.system_group(),
+                material_id: collision.material_id,
+            });
+        }
+    }
+
+    Box::into_raw(Box::new(CShapeHierarchy {
+        meshes: array::CArray::new(meshes.into_boxed_slice()),
+        instances: array::CArray::new(instances.into_boxed_slice()),
+    }))
+}
+
+/// # Safety
+/// `hierarchy` must be null or a pointer returned by `destinyhavok_read_shape_hierarchy`
+#[no_mangle]
+pub unsafe extern "C" fn destinyhavok_free_shape_hierarchy(hierarchy: *mut CShapeHierarchy) {
+    if !hierarchy.is_null() {
+        let _ = unsafe { Box::from_raw(hierarchy) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use destiny_havok::writer::{write_shape_collection, ShapeSource};
+    use glam::Vec3;
+
+    use super::*;
+
+    fn last_error() -> Option<String> {
+        let message = destinyhavok_last_error();
+        (!message.is_null()).then(|| {
+            unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .to_string()
+        })
+    }
+
+    fn cube_collection() -> Vec<u8> {
+        let corners = (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32))
+            .collect::<Vec<_>>();
+
+        let mut data = vec![];
+        write_shape_collection(&mut data, &[ShapeSource::ConvexVertices(&corners)]).unwrap();
+        data
+    }
+
+    #[test]
+    fn invalid_input() {
+        let mut empty = [];
+        unsafe {
+            let collection =
+                destinyhavok_read_shape_collection(std::ptr::null_mut(), 0, std::ptr::null());
+            assert!(collection.is_null());
+            assert!(last_error().is_some());
+
+            let hierarchy =
+                destinyhavok_read_shape_hierarchy(empty.as_mut_ptr(), 0, std::ptr::null());
+            assert!(hierarchy.is_null());
+            assert!(last_error().is_some());
+
+            let mut garbage = *b"not a compendium";
+            let compendium = destinyhavok_read_type_compendium(garbage.as_mut_ptr(), garbage.len());
+            assert!(compendium.is_null());
+            assert!(last_error().is_some());
+
+            // Freeing null pointers does nothing
+            destinyhavok_free_shape_collection(collection);
+            destinyhavok_free_shape_hierarchy(hierarchy);
+            destinyhavok_free_type_compendium(compendium);
+        }
+    }
+
+    #[test]
+    fn read_and_free() {
+        let mut data = cube_collection();
+        unsafe {
+            let collection =
+                destinyhavok_read_shape_collection(data.as_mut_ptr(), data.len(), std::ptr::null());
+            assert!(!collection.is_null());
+            assert_eq!(last_error(), None);
+
+            let shapes = std::slice::from_raw_parts((*collection).data, (*collection).len);
+            assert_eq!(shapes.len(), 1);
+            assert_eq!(shapes[0].vertices.len, 8);
+            assert_eq!(shapes[0].indices.len, 12 * 3);
+            assert_eq!(
+                shapes[0].shape_type as u32,
+                CShapeType::ConvexVertices as u32
+            );
+            assert_eq!(
+                [
+                    shapes[0].aabb_max.x,
+                    shapes[0].aabb_max.y,
+                    shapes[0].aabb_max.z
+                ],
+                [1.0; 3]
+            );
+            destinyhavok_free_shape_collection(collection);
+
+            let hierarchy =
+                destinyhavok_read_shape_hierarchy(data.as_mut_ptr(), data.len(), std::ptr::null());
+            assert!(!hierarchy.is_null());
+            assert_eq!(last_error(), None);
+            assert_eq!((*hierarchy).meshes.len, 1);
+            assert_eq!((*hierarchy).instances.len, 1);
+
+            let instance = &*(*hierarchy).instances.data;
+            assert_eq!((instance.shape_index, instance.mesh_index), (0, 0));
+            assert_eq!(instance.transform, glam::Mat4::IDENTITY.to_cols_array());
+            destinyhavok_free_shape_hierarchy(hierarchy);
+        }
+    }
 }
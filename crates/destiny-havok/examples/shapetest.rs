@@ -10,7 +10,7 @@ use destiny_havok::{
     index::IndexItem,
     section::{TagSection, TagSectionSignature},
 };
-use glam::{Mat4, Quat, Vec3, Vec4};
+use glam::{Mat4, Vec3, Vec4};
 use itertools::Itertools;
 
 fn main() -> anyhow::Result<()> {
@@ -39,11 +39,6 @@ fn main() -> anyhow::Result<()> {
     while f.stream_position()? < tag0.end() {
         match f.read_be::<TagSection>() {
             Ok(section) => {
-                let endian = if section.is_le {
-                    Endian::Little
-                } else {
-                    Endian::Big
-                };
                 println!("{section:#x?}");
 
                 match section.signature {
@@ -66,7 +61,7 @@ fn main() -> anyhow::Result<()> {
 
                         let mut points: Vec<Vec3> = vec![];
                         // let mut base_transform = Mat4::IDENTITY;
-                        let mut current_transform = Mat4::IDENTITY;
+                        let current_transform = Mat4::IDENTITY;
                         for it in &items {
                             println!("{it:x?} 0x{:x}", data_offset + it.offset as u64);
 
@@ -76,8 +71,8 @@ fn main() -> anyhow::Result<()> {
                                         f.seek(SeekFrom::Start(
                                             data_offset + it.offset as u64 + 0x50,
                                         ))?;
-                                        let scale: [f32; 4] = f.read_type(endian)?;
-                                        let translation: [f32; 4] = f.read_type(endian)?;
+                                        let _scale: [f32; 4] = f.read_type(endian)?;
+                                        let _translation: [f32; 4] = f.read_type(endian)?;
                                         // dbg!(&scale);
                                         // dbg!(&translation);
 
@@ -279,6 +274,7 @@ trait SeekSaveExt: Seek {
 
 impl<T: Read + Seek> SeekSaveExt for T {}
 
+#[allow(dead_code)]
 const CUBE_VERTICES: [Vec3; 8] = [
     Vec3::new(-1.0, -1.0, -1.0),
     Vec3::new(-1.0, -1.0, 1.0),
@@ -290,6 +286,7 @@ const CUBE_VERTICES: [Vec3; 8] = [
     Vec3::new(1.0, 1.0, 1.0),
 ];
 
+#[allow(dead_code)]
 const CUBE_INDICES: [u32; 36] = [
     0, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7, 0, 2, 4, 4, 2, 6, 1, 5, 3, 3, 5, 7, 0, 4, 1, 1, 4, 5, 2, 3,
     6, 6, 3, 7,
@@ -0,0 +1,25 @@
+#![no_main]
+
+use destiny_havok::view::HavokTagFileView;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(view) = HavokTagFileView::new(data) else {
+        return;
+    };
+
+    if let Ok(hierarchy) = view.read_shape_hierarchy() {
+        for &root in &hierarchy.roots {
+            hierarchy.flatten(root);
+        }
+    }
+
+    let _ = view.read_navmeshes();
+
+    for index in 1..view.tagfile.items.len() as u64 {
+        let _ = view.item_data(index);
+        if view.tagfile.types.has_layouts {
+            let _ = view.read_item_reflected(index);
+        }
+    }
+});
@@ -0,0 +1,464 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::Parser;
+use destiny_havok::{type_registry::TypeCompendium, view::HavokTagFileView};
+use serde::Serialize;
+
+/// Lists the contents of havok tagfiles
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    /// Files to inspect. Directories are searched recursively
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Type compendium for files that reference their types through TCRF
+    #[arg(short, long)]
+    compendium: Option<PathBuf>,
+
+    /// Print a JSON array with one entry per file instead of text
+    #[arg(long)]
+    json: bool,
+
+    /// List every type in the registry, instead of just the ones used by items
+    #[arg(long)]
+    all_types: bool,
+
+    /// Skip resolving the shape collection and navmeshes
+    #[arg(long)]
+    no_shapes: bool,
+}
+
+#[derive(Serialize, Default)]
+struct FileReport {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+
+    sections: Vec<SectionReport>,
+    sdk_version: Option<String>,
+    compendium_id: Option<u64>,
+    data_offset: u64,
+    data_size: usize,
+    items: Vec<ItemReport>,
+    patches: Vec<PatchReport>,
+    types: Vec<TypeReport>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shapes: Option<Vec<ShapeReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shape_error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    navmeshes: Option<Vec<NavMeshReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    navmesh_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SectionReport {
+    signature: String,
+    offset: u64,
+    size: usize,
+    little_endian: bool,
+}
+
+#[derive(Serialize)]
+struct ItemReport {
+    index: u64,
+    type_id: u32,
+    type_name: Option<String>,
+    flags: u32,
+    offset: u32,
+    count: u32,
+    /// Items referenced through patched pointers
+    references: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct PatchReport {
+    type_id: u32,
+    type_name: Option<String>,
+    pointers: usize,
+}
+
+#[derive(Serialize)]
+struct TypeReport {
+    id: u32,
+    name: String,
+    parent: Option<u32>,
+    size: Option<u32>,
+    members: Vec<MemberReport>,
+}
+
+#[derive(Serialize)]
+struct MemberReport {
+    name: String,
+    offset: u32,
+    type_name: String,
+}
+
+#[derive(Serialize)]
+struct ShapeReport {
+    index: usize,
+    item: u64,
+    class_name: String,
+    leaves: usize,
+    /// Distinct collision layers of the leaves
+    collision_layers: Vec<u32>,
+    vertices: usize,
+    triangles: usize,
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+#[derive(Serialize)]
+struct NavMeshReport {
+    item: u64,
+    vertices: usize,
+    faces: usize,
+    edges: usize,
+    face_data_striding: usize,
+    edge_data_striding: usize,
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let compendium = match &args.compendium {
+        Some(path) => Some(
+            TypeCompendium::read(&mut BufReader::new(File::open(path)?))
+                .with_context(|| format!("Failed to read type compendium {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let mut files = vec![];
+    for path in &args.paths {
+        collect_files(path, &mut files)?;
+    }
+
+    let reports = files
+        .iter()
+        .map(|path| inspect_file(&args, path, compendium.as_ref()))
+        .collect::<Vec<_>>();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for report in &reports {
+            print_report(report);
+        }
+    }
+
+    let failed = reports.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 {
+        eprintln!("{failed}/{} files could not be read", reports.len());
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory {}", path.display()))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    Ok(())
+}
+
+fn inspect_file(args: &Args, path: &Path, compendium: Option<&TypeCompendium>) -> FileReport {
+    let mut report = FileReport {
+        path: path.display().to_string(),
+        ..Default::default()
+    };
+
+    let result = std::fs::read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| inspect_tagfile(args, &data, compendium, &mut report));
+
+    if let Err(e) = result {
+        report.error = Some(format!("{e:#}"));
+    }
+
+    report
+}
+
+fn inspect_tagfile(
+    args: &Args,
+    data: &[u8],
+    compendium: Option<&TypeCompendium>,
+    report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let view = HavokTagFileView::with_compendium(data, compendium)?;
+    let tagfile = &view.tagfile;
+
+    report.sections = tagfile
+        .sections
+        .iter()
+        .map(|s| SectionReport {
+            signature: String::from_utf8_lossy(&s.signature.fourcc()).to_string(),
+            offset: s.offset,
+            size: s.size,
+            little_endian: s.is_le,
+        })
+        .collect();
+    report.sdk_version = tagfile.sdk_version.clone();
+    report.compendium_id = tagfile.compendium_id;
+    report.data_offset = tagfile.data_offset;
+    report.data_size = tagfile.data_size;
+
+    report.items = tagfile
+        .items
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(index, item)| ItemReport {
+            index: index as u64,
+            type_id: item.typ,
+            type_name: tagfile
+                .types
+                .name(item.typ)
+                .map(|_| tagfile.types.full_name(item.typ)),
+            flags: item.flags.bits(),
+            offset: item.offset,
+            count: item.count,
+            references: tagfile
+                .item_references(index as u64)
+                .into_iter()
+                .map(|(_, target)| target)
+                .collect(),
+        })
+        .collect();
+
+    report.patches = tagfile
+        .patches
+        .iter()
+        .map(|p| PatchReport {
+            type_id: p.typ,
+            type_name: tagfile
+                .types
+                .name(p.typ)
+                .map(|_| tagfile.types.full_name(p.typ)),
+            pointers: p.offsets.len(),
+        })
+        .collect();
+
+    let type_ids: BTreeSet<u32> = if args.all_types {
+        (1..tagfile.types.types.len() as u32).collect()
+    } else {
+        tagfile.items.iter().skip(1).map(|i| i.typ).collect()
+    };
+
+    report.types = type_ids
+        .into_iter()
+        .filter_map(|id| {
+            let typ = tagfile.types.get(id)?;
+            Some(TypeReport {
+                id,
+                name: tagfile.types.full_name(id),
+                parent: typ.parent,
+                size: tagfile.types.size(id),
+                members: typ
+                    .members
+                    .iter()
+                    .map(|m| MemberReport {
+                        name: m.name.clone(),
+                        offset: m.offset,
+                        type_name: tagfile.types.full_name(m.typ),
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+
+    if !args.no_shapes && tagfile.find_item("s_hkpShape_array").is_some() {
+        match view.read_shape_hierarchy() {
+            Ok(hierarchy) => {
+                report.shapes = Some(
+                    hierarchy
+                        .roots
+                        .iter()
+                        .enumerate()
+                        .map(|(index, &root)| {
+                            let node = hierarchy.node(root);
+                            let shape = hierarchy.flatten(root);
+                            let leaves = hierarchy.leaves(root);
+                            let collision_layers = leaves
+                                .iter()
+                                .filter_map(|l| l.collision)
+                                .map(|c| c.filter_info.layer())
+                                .collect::<BTreeSet<_>>();
+                            let (min, max) = shape.min_max();
+                            ShapeReport {
+                                index,
+                                item: node.item,
+                                class_name: node.class_name.clone(),
+                                leaves: leaves.len(),
+                                collision_layers: collision_layers.into_iter().collect(),
+                                vertices: shape.vertices.len(),
+                                triangles: shape.indices.len() / 3,
+                                min: min.to_array(),
+                                max: max.to_array(),
+                            }
+                        })
+                        .collect(),
+                )
+            }
+            Err(e) => report.shape_error = Some(format!("{e:#}")),
+        }
+    }
+
+    if !args.no_shapes && tagfile.find_item("hkaiNavMesh").is_some() {
+        match view.read_navmeshes() {
+            Ok(navmeshes) => {
+                report.navmeshes = Some(
+                    navmeshes
+                        .iter()
+                        .map(|n| NavMeshReport {
+                            item: n.item,
+                            vertices: n.vertices.len(),
+                            faces: n.faces.len(),
+                            edges: n.edges.len(),
+                            face_data_striding: n.face_data_striding,
+                            edge_data_striding: n.edge_data_striding,
+                            min: n.aabb_min.to_array(),
+                            max: n.aabb_max.to_array(),
+                        })
+                        .collect(),
+                )
+            }
+            Err(e) => report.navmesh_error = Some(format!("{e:#}")),
+        }
+    }
+
+    Ok(())
+}
+
+fn print_report(report: &FileReport) {
+    println!("{}", report.path);
+    if let Some(e) = &report.error {
+        println!("  Error: {e}");
+        println!();
+        return;
+    }
+
+    println!("  Sections:");
+    for s in &report.sections {
+        println!(
+            "    {} 0x{:x} ({} bytes{})",
+            s.signature,
+            s.offset,
+            s.size,
+            if s.little_endian { ", LE" } else { "" }
+        );
+    }
+
+    if let Some(version) = &report.sdk_version {
+        println!("  SDK Version: {version}");
+    }
+    println!(
+        "  Data: {0}/0x{0:X} bytes @ 0x{1:X}",
+        report.data_size, report.data_offset
+    );
+    if let Some(id) = report.compendium_id {
+        println!("  Compendium ID: 0x{id:016x}");
+    }
+
+    println!("  Items:");
+    for item in &report.items {
+        println!(
+            "    {}: {} (0x{:x}) flags=0x{:x} count={} @ 0x{:x}",
+            item.index,
+            item.type_name.as_deref().unwrap_or("<unknown>"),
+            item.type_id,
+            item.flags,
+            item.count,
+            item.offset
+        );
+        if !item.references.is_empty() {
+            println!("      -> {:?}", item.references);
+        }
+    }
+
+    if !report.patches.is_empty() {
+        println!("  Patches:");
+        for p in &report.patches {
+            println!(
+                "    {} (0x{:x}): {} pointers",
+                p.type_name.as_deref().unwrap_or("<unknown>"),
+                p.type_id,
+                p.pointers
+            );
+        }
+    }
+
+    println!("  Types:");
+    for typ in &report.types {
+        match typ.size {
+            Some(size) => println!("    0x{:x}: {} (0x{size:x} bytes)", typ.id, typ.name),
+            None => println!("    0x{:x}: {}", typ.id, typ.name),
+        }
+
+        for m in &typ.members {
+            println!("      +0x{:x} {}: {}", m.offset, m.name, m.type_name);
+        }
+    }
+
+    if let Some(shapes) = &report.shapes {
+        println!("  Shapes:");
+        for s in shapes {
+            println!(
+                "    {}: {} (item {}) {} leaves, {} vertices, {} triangles, bounds {:?} - {:?}, layers {:?}",
+                s.index,
+                s.class_name,
+                s.item,
+                s.leaves,
+                s.vertices,
+                s.triangles,
+                s.min,
+                s.max,
+                s.collision_layers
+            );
+        }
+    }
+
+    if let Some(e) = &report.shape_error {
+        println!("  Failed to read shapes: {e}");
+    }
+
+    if let Some(navmeshes) = &report.navmeshes {
+        println!("  Navmeshes:");
+        for n in navmeshes {
+            println!(
+                "    item {}: {} vertices, {} faces, {} edges, bounds {:?} - {:?}",
+                n.item, n.vertices, n.faces, n.edges, n.min, n.max
+            );
+        }
+    }
+
+    if let Some(e) = &report.navmesh_error {
+        println!("  Failed to read navmeshes: {e}");
+    }
+
+    println!();
+}
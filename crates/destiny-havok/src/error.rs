@@ -0,0 +1,50 @@
+use thiserror::Error;
+
+/// Structural problems found while reading a tagfile.
+///
+/// Functions in this crate return `anyhow` errors, use `downcast_ref::<HavokError>()` to inspect these.
+#[derive(Debug, Error)]
+pub enum HavokError {
+    /// An item, vertex or element index that is out of range
+    #[error("{context} references invalid index {index}")]
+    InvalidIndex { context: &'static str, index: u64 },
+
+    #[error("No {0} item found")]
+    MissingItem(&'static str),
+
+    #[error(
+        "Item {item} has unknown type 0x{typ:x}, files without a TYPE section need a type compendium"
+    )]
+    UnknownType { item: u64, typ: u32 },
+
+    #[error("{context} has {actual} elements, expected {expected}")]
+    CountMismatch {
+        context: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error(
+        "Pointer at 0x{offset:x} holds item index {index}, but is not listed in the patch table"
+    )]
+    UnpatchedPointer { offset: u64, index: u64 },
+
+    #[error("Unhandled {context} type {name}")]
+    UnhandledType { context: &'static str, name: String },
+
+    #[error("Item {0} references itself through its children")]
+    CycleDetected(u64),
+
+    #[error("Item {item} is nested deeper than the maximum depth of {limit}")]
+    DepthLimitExceeded { item: u64, limit: usize },
+
+    #[error(
+        "{section} at 0x{offset:x} needs 0x{size:x} bytes, but only 0x{available:x} are available"
+    )]
+    TruncatedSection {
+        section: String,
+        offset: u64,
+        size: u64,
+        available: u64,
+    },
+}
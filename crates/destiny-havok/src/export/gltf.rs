@@ -0,0 +1,361 @@
+use std::{collections::HashMap, io::Write};
+
+use glam::Mat4;
+use serde_json::{json, Value};
+
+use super::ExportObject;
+use crate::shape_collection::{Shape, ShapeHierarchy, ShapeNodeKind};
+
+const GLB_MAGIC: u32 = 0x46546c67;
+const CHUNK_JSON: u32 = 0x4e4f534a;
+const CHUNK_BIN: u32 = 0x004e4942;
+
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Writes the objects as a binary glTF (GLB) file, with one node and mesh per object
+pub fn write_glb(w: &mut impl Write, objects: &[ExportObject]) -> anyhow::Result<()> {
+    let mut scene = Scene::default();
+    for object in objects {
+        let mesh = scene.add_mesh(&object.name, &object.shape);
+        let node = scene.add_node(GltfNode {
+            name: object.name.clone(),
+            mesh,
+            matrix: None,
+            children: vec![],
+        });
+        scene.roots.push(node);
+    }
+
+    scene.write(w)
+}
+
+/// Writes the shape hierarchy as a binary glTF (GLB) file.
+///
+/// Every shape in the collection becomes a root node, with compound shape instances as child nodes carrying the
+/// instance transform. Meshes used by multiple instances are only stored once.
+pub fn write_glb_hierarchy(w: &mut impl Write, hierarchy: &ShapeHierarchy) -> anyhow::Result<()> {
+    let mut scene = Scene::default();
+    let mut meshes = HashMap::new();
+    for (i, &root) in hierarchy.roots.iter().enumerate() {
+        let node = scene.add_hierarchy_node(hierarchy, root, None, &mut meshes);
+        scene.nodes[node].name = format!("shape_{i}_{}", scene.nodes[node].name);
+        scene.roots.push(node);
+    }
+
+    scene.write(w)
+}
+
+struct GltfNode {
+    name: String,
+    mesh: Option<usize>,
+    matrix: Option<Mat4>,
+    children: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Scene {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<GltfNode>,
+    roots: Vec<usize>,
+}
+
+impl Scene {
+    /// Adds a mesh, returning `None` if the shape has no triangles to store
+    fn add_mesh(&mut self, name: &str, shape: &Shape) -> Option<usize> {
+        if shape.indices.len() < 3 || shape.vertices.is_empty() {
+            return None;
+        }
+
+        let (min, max) = shape.min_max();
+        let positions = self.add_buffer_view(
+            shape.vertices.iter().flat_map(|v| v.to_array()),
+            TARGET_ARRAY_BUFFER,
+        );
+        let mut accessor = json!({
+            "bufferView": positions,
+            "componentType": COMPONENT_FLOAT,
+            "count": shape.vertices.len(),
+            "type": "VEC3",
+        });
+        // Bounds are required by the spec, but a single NaN or infinite vertex would make them invalid JSON numbers
+        if min.is_finite() && max.is_finite() {
+            accessor["min"] = json!(min.to_array());
+            accessor["max"] = json!(max.to_array());
+        }
+        self.accessors.push(accessor);
+        let position_accessor = self.accessors.len() - 1;
+
+        let triangle_indices = &shape.indices[..shape.indices.len() / 3 * 3];
+        let indices = self.add_buffer_view(
+            triangle_indices.iter().copied(),
+            TARGET_ELEMENT_ARRAY_BUFFER,
+        );
+        self.accessors.push(json!({
+            "bufferView": indices,
+            "componentType": COMPONENT_UNSIGNED_INT,
+            "count": triangle_indices.len(),
+            "type": "SCALAR",
+        }));
+        let index_accessor = self.accessors.len() - 1;
+
+        self.meshes.push(json!({
+            "name": name,
+            "primitives": [{
+                "attributes": { "POSITION": position_accessor },
+                "indices": index_accessor,
+            }],
+        }));
+
+        Some(self.meshes.len() - 1)
+    }
+
+    fn add_buffer_view<T: ToLeBytes>(
+        &mut self,
+        data: impl Iterator<Item = T>,
+        target: u32,
+    ) -> usize {
+        let offset = self.buffer.len();
+        for v in data {
+            self.buffer.extend_from_slice(&v.to_le_bytes());
+        }
+
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.buffer.len() - offset,
+            "target": target,
+        }));
+
+        self.buffer_views.len() - 1
+    }
+
+    fn add_node(&mut self, node: GltfNode) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn add_hierarchy_node(
+        &mut self,
+        hierarchy: &ShapeHierarchy,
+        node_index: usize,
+        matrix: Option<Mat4>,
+        meshes: &mut HashMap<usize, Option<usize>>,
+    ) -> usize {
+        let node = hierarchy.node(node_index);
+        let name = format!("{}_{}", node.class_name, node.item);
+
+        let (mesh, children) = match &node.kind {
+            ShapeNodeKind::Mesh(shape) => {
+                let mesh = match meshes.get(&node_index) {
+                    Some(&mesh) => mesh,
+                    None => {
+                        let mesh = self.add_mesh(&name, shape);
+                        meshes.insert(node_index, mesh);
+                        mesh
+                    }
+                };
+
+                (mesh, vec![])
+            }
+            ShapeNodeKind::StaticCompound(instances) => (
+                None,
+                instances
+                    .iter()
+                    .map(|instance| {
+                        self.add_hierarchy_node(
+                            hierarchy,
+                            instance.node,
+                            Some(instance.transform.to_mat4()),
+                            meshes,
+                        )
+                    })
+                    .collect(),
+            ),
+            ShapeNodeKind::List(list) => (
+                None,
+                list.iter()
+                    .map(|child| self.add_hierarchy_node(hierarchy, child.node, None, meshes))
+                    .collect(),
+            ),
+        };
+
+        self.add_node(GltfNode {
+            name,
+            mesh,
+            matrix,
+            children,
+        })
+    }
+
+    fn json(&self) -> Value {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|n| {
+                let mut node = json!({ "name": n.name });
+                if let Some(mesh) = n.mesh {
+                    node["mesh"] = json!(mesh);
+                }
+                if let Some(matrix) = n.matrix.filter(|m| *m != Mat4::IDENTITY) {
+                    node["matrix"] = json!(matrix.to_cols_array());
+                }
+                if !n.children.is_empty() {
+                    node["children"] = json!(n.children);
+                }
+                node
+            })
+            .collect::<Vec<_>>();
+
+        let mut json = json!({
+            "asset": { "version": "2.0", "generator": "destiny-havok" },
+            "scene": 0,
+            "scenes": [{ "nodes": self.roots }],
+            "nodes": nodes,
+        });
+
+        if !self.meshes.is_empty() {
+            json["meshes"] = json!(self.meshes);
+            json["accessors"] = json!(self.accessors);
+            json["bufferViews"] = json!(self.buffer_views);
+            json["buffers"] = json!([{ "byteLength": self.buffer.len() }]);
+        }
+
+        json
+    }
+
+    fn write(&self, w: &mut impl Write) -> anyhow::Result<()> {
+        let mut json = serde_json::to_vec(&self.json())?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+
+        let mut bin = self.buffer.clone();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut total_length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            total_length += 8 + bin.len();
+        }
+
+        w.write_all(&GLB_MAGIC.to_le_bytes())?;
+        w.write_all(&2u32.to_le_bytes())?;
+        w.write_all(&(total_length as u32).to_le_bytes())?;
+
+        w.write_all(&(json.len() as u32).to_le_bytes())?;
+        w.write_all(&CHUNK_JSON.to_le_bytes())?;
+        w.write_all(&json)?;
+
+        if !bin.is_empty() {
+            w.write_all(&(bin.len() as u32).to_le_bytes())?;
+            w.write_all(&CHUNK_BIN.to_le_bytes())?;
+            w.write_all(&bin)?;
+        }
+
+        Ok(())
+    }
+}
+
+trait ToLeBytes {
+    fn to_le_bytes(self) -> [u8; 4];
+}
+
+impl ToLeBytes for f32 {
+    fn to_le_bytes(self) -> [u8; 4] {
+        f32::to_le_bytes(self)
+    }
+}
+
+impl ToLeBytes for u32 {
+    fn to_le_bytes(self) -> [u8; 4] {
+        u32::to_le_bytes(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    fn parse(glb: &[u8]) -> (Value, &[u8]) {
+        let u32_at = |o: usize| u32::from_le_bytes(glb[o..o + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), GLB_MAGIC);
+        assert_eq!(u32_at(8) as usize, glb.len());
+
+        let json_len = u32_at(12) as usize;
+        assert_eq!(u32_at(16), CHUNK_JSON);
+        let json = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+
+        let bin = &glb[20 + json_len..];
+        if bin.is_empty() {
+            return (json, bin);
+        }
+
+        assert_eq!(u32_at(20 + json_len + 4), CHUNK_BIN);
+        (json, &bin[8..])
+    }
+
+    #[test]
+    fn parse_back() {
+        let objects = [ExportObject {
+            name: "a \"quoted\" name".to_string(),
+            shape: Shape {
+                vertices: vec![Vec3::ZERO, Vec3::X, Vec3::new(0.0, 2.0, -1.0)],
+                indices: vec![0, 1, 2],
+            },
+        }];
+
+        let mut out = vec![];
+        write_glb(&mut out, &objects).unwrap();
+        let (json, bin) = parse(&out);
+
+        assert_eq!(json["nodes"][0]["name"], "a \"quoted\" name");
+        assert_eq!(json["nodes"][0]["mesh"], 0);
+        assert_eq!(json["accessors"][0]["count"], 3);
+        assert_eq!(json["accessors"][0]["min"], json!([0.0, 0.0, -1.0]));
+        assert_eq!(json["accessors"][0]["max"], json!([1.0, 2.0, 0.0]));
+        assert_eq!(json["accessors"][1]["count"], 3);
+        assert_eq!(json["buffers"][0]["byteLength"], 3 * 12 + 3 * 4);
+        assert!(bin.len() >= 3 * 12 + 3 * 4);
+
+        let indices = json["bufferViews"][1]["byteOffset"].as_u64().unwrap() as usize;
+        assert_eq!(
+            &bin[indices..indices + 12],
+            [0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn non_finite_bounds() {
+        let objects = [ExportObject {
+            name: "broken".to_string(),
+            shape: Shape {
+                vertices: vec![Vec3::ZERO, Vec3::X, Vec3::new(f32::NAN, 0.0, f32::INFINITY)],
+                indices: vec![0, 1, 2],
+            },
+        }];
+
+        let mut out = vec![];
+        write_glb(&mut out, &objects).unwrap();
+        let (json, _) = parse(&out);
+
+        assert_eq!(json["accessors"][0]["count"], 3);
+        assert!(json["accessors"][0].get("min").is_none());
+        assert!(json["accessors"][0].get("max").is_none());
+    }
+
+    #[test]
+    fn empty_scene() {
+        let mut out = vec![];
+        write_glb(&mut out, &[]).unwrap();
+        let (json, bin) = parse(&out);
+
+        assert_eq!(json["nodes"], json!([]));
+        assert!(json.get("buffers").is_none());
+        assert!(bin.is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+//! Writers for getting shapes into 3D tools.
+//!
+//! Coordinates are written as-is, without converting Destiny's Z-up space to the Y-up convention some formats
+//! expect.
+
+mod gltf;
+mod obj;
+mod ply;
+
+pub use gltf::{write_glb, write_glb_hierarchy};
+pub use obj::write_obj;
+pub use ply::write_ply;
+
+use crate::{
+    navmesh::NavMesh,
+    shape_collection::{Shape, ShapeHierarchy},
+};
+
+/// A named shape, written as a separate object by the exporters
+#[derive(Clone)]
+pub struct ExportObject {
+    pub name: String,
+    pub shape: Shape,
+}
+
+impl ExportObject {
+    /// One object per shape, as returned by `read_shape_collection`
+    pub fn from_shapes(shapes: &[Shape]) -> Vec<Self> {
+        shapes
+            .iter()
+            .enumerate()
+            .map(|(i, shape)| ExportObject {
+                name: format!("shape_{i}"),
+                shape: shape.clone(),
+            })
+            .collect()
+    }
+
+    /// One object per mesh instance in the hierarchy, with the instance transform applied
+    pub fn from_hierarchy(hierarchy: &ShapeHierarchy) -> Vec<Self> {
+        let mut objects = vec![];
+        for (i, &root) in hierarchy.roots.iter().enumerate() {
+            for (leaf_index, leaf) in hierarchy.leaves(root).into_iter().enumerate() {
+                let node = hierarchy.node(leaf.node);
+                let Some(mesh) = node.mesh() else {
+                    continue;
+                };
+
+                let mut shape = mesh.clone();
+                shape.apply_transform(leaf.transform);
+                objects.push(ExportObject {
+                    name: format!("shape_{i}_{leaf_index}_{}", node.class_name),
+                    shape,
+                });
+            }
+        }
+
+        objects
+    }
+
+    /// One object per navmesh, with faces triangulated
+    pub fn from_navmeshes(navmeshes: &[NavMesh]) -> Vec<Self> {
+        navmeshes
+            .iter()
+            .map(|navmesh| ExportObject {
+                name: format!("navmesh_{}", navmesh.item),
+                shape: navmesh.to_shape(),
+            })
+            .collect()
+    }
+}
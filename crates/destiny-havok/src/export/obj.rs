@@ -0,0 +1,80 @@
+use std::io::Write;
+
+use super::ExportObject;
+
+/// Writes the objects as a Wavefront OBJ file
+pub fn write_obj(w: &mut impl Write, objects: &[ExportObject]) -> anyhow::Result<()> {
+    let mut index_offset = 1;
+    for object in objects {
+        writeln!(w, "o {}", object.name.replace(char::is_whitespace, "_"))?;
+        for v in &object.shape.vertices {
+            writeln!(w, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+
+        for t in object.shape.indices.chunks_exact(3) {
+            writeln!(
+                w,
+                "f {} {} {}",
+                t[0] + index_offset,
+                t[1] + index_offset,
+                t[2] + index_offset
+            )?;
+        }
+
+        index_offset += object.shape.vertices.len() as u32;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::shape_collection::Shape;
+
+    #[test]
+    fn parse_back() {
+        let triangle = Shape {
+            vertices: vec![Vec3::ZERO, Vec3::X, Vec3::new(0.0, 0.5, -2.0)],
+            indices: vec![0, 1, 2],
+        };
+        let objects = [
+            ExportObject {
+                name: "first shape".to_string(),
+                shape: triangle.clone(),
+            },
+            ExportObject {
+                name: "second".to_string(),
+                shape: triangle,
+            },
+        ];
+
+        let mut out = vec![];
+        write_obj(&mut out, &objects).unwrap();
+
+        let (mut names, mut vertices, mut faces) = (vec![], vec![], vec![]);
+        for line in String::from_utf8(out).unwrap().lines() {
+            let mut parts = line.split(' ');
+            match parts.next() {
+                Some("o") => names.push(parts.next().unwrap().to_string()),
+                Some("v") => {
+                    vertices.push(parts.map(|p| p.parse::<f32>().unwrap()).collect::<Vec<_>>())
+                }
+                Some("f") => faces.push(
+                    parts
+                        .map(|p| p.parse::<usize>().unwrap())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => panic!("Unexpected line {line:?}"),
+            }
+        }
+
+        assert_eq!(names, ["first_shape", "second"]);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(vertices[5], [0.0, 0.5, -2.0]);
+        // Indices are 1-based and continue across objects
+        assert_eq!(faces, [[1, 2, 3], [4, 5, 6]]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::Write;
+
+use super::ExportObject;
+
+/// Writes the objects as a single binary little-endian PLY mesh.
+///
+/// PLY has no notion of separate objects, so every face carries an `object` property with the index of the
+/// object it belongs to. The object names are listed in the header comments.
+pub fn write_ply(w: &mut impl Write, objects: &[ExportObject]) -> anyhow::Result<()> {
+    let vertex_count: usize = objects.iter().map(|o| o.shape.vertices.len()).sum();
+    let face_count: usize = objects.iter().map(|o| o.shape.indices.len() / 3).sum();
+
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    for (i, object) in objects.iter().enumerate() {
+        writeln!(w, "comment object {i} {}", object.name)?;
+    }
+    writeln!(w, "element vertex {vertex_count}")?;
+    writeln!(w, "property float x")?;
+    writeln!(w, "property float y")?;
+    writeln!(w, "property float z")?;
+    writeln!(w, "element face {face_count}")?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "property uint object")?;
+    writeln!(w, "end_header")?;
+
+    for object in objects {
+        for v in &object.shape.vertices {
+            for c in v.to_array() {
+                w.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+
+    let mut index_offset = 0;
+    for (i, object) in objects.iter().enumerate() {
+        for t in object.shape.indices.chunks_exact(3) {
+            w.write_all(&[3])?;
+            for index in t {
+                w.write_all(&(index + index_offset).to_le_bytes())?;
+            }
+            w.write_all(&(i as u32).to_le_bytes())?;
+        }
+
+        index_offset += object.shape.vertices.len() as u32;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::shape_collection::Shape;
+
+    #[test]
+    fn parse_back() {
+        let objects = [
+            ExportObject {
+                name: "quad".to_string(),
+                shape: Shape {
+                    vertices: vec![Vec3::ZERO, Vec3::X, Vec3::ONE, Vec3::Y],
+                    indices: vec![0, 1, 2, 0, 2, 3],
+                },
+            },
+            ExportObject {
+                name: "triangle".to_string(),
+                shape: Shape {
+                    vertices: vec![Vec3::ZERO, Vec3::Z, Vec3::new(1.0, 2.0, 3.0)],
+                    indices: vec![2, 1, 0],
+                },
+            },
+        ];
+
+        let mut out = vec![];
+        write_ply(&mut out, &objects).unwrap();
+
+        let header_end = out.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        let header = std::str::from_utf8(&out[..header_end]).unwrap();
+        assert!(header.contains("comment object 1 triangle\n"));
+        assert!(header.contains("element vertex 7\n"));
+        assert!(header.contains("element face 3\n"));
+
+        let u32_at = |o: usize| u32::from_le_bytes(out[o..o + 4].try_into().unwrap());
+        let f32_at = |o: usize| f32::from_le_bytes(out[o..o + 4].try_into().unwrap());
+
+        let vertices = header_end;
+        assert_eq!(
+            [
+                f32_at(vertices + 6 * 12),
+                f32_at(vertices + 6 * 12 + 4),
+                f32_at(vertices + 6 * 12 + 8)
+            ],
+            [1.0, 2.0, 3.0]
+        );
+
+        let faces = vertices + 7 * 12;
+        let face_size = 1 + 3 * 4 + 4;
+        assert_eq!(out.len(), faces + 3 * face_size);
+
+        let face = |i: usize| {
+            let o = faces + i * face_size;
+            assert_eq!(out[o], 3);
+            (
+                [u32_at(o + 1), u32_at(o + 5), u32_at(o + 9)],
+                u32_at(o + 13),
+            )
+        };
+        assert_eq!(face(0), ([0, 1, 2], 0));
+        assert_eq!(face(1), ([0, 2, 3], 0));
+        // Indices of the second object are offset by the vertices of the first
+        assert_eq!(face(2), ([6, 5, 4], 1));
+    }
+}
@@ -1,7 +1,7 @@
 use binrw::binread;
 use bitflags::bitflags;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[binread]
 pub struct IndexItem {
     pub type_and_flags: u32,
@@ -17,7 +17,7 @@ pub struct IndexItem {
 }
 
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     pub struct ItemFlags: u32 {
         const POINTER = 0x10;
         const ARRAY = 0x20;
@@ -1,5 +1,14 @@
+pub mod error;
+pub mod export;
 pub mod index;
+pub mod reflection;
 pub mod section;
+pub mod tagfile;
+pub mod type_registry;
 pub mod types;
+pub mod view;
+pub mod writer;
 
+pub mod navmesh;
+pub mod query;
 pub mod shape_collection;
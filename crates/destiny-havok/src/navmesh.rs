@@ -0,0 +1,426 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use anyhow::Context;
+use binrw::BinReaderExt;
+use glam::Vec3;
+
+use crate::{
+    error::HavokError,
+    shape_collection::Shape,
+    tagfile::HavokTagFile,
+    type_registry::TypeCompendium,
+    types::navmesh::{hkaiNavMesh, NavMeshEdgeFlags},
+};
+
+/// A decoded `hkaiNavMesh`, a polygon mesh of walkable faces with edge connectivity between them
+#[derive(Debug, Clone)]
+pub struct NavMesh {
+    /// Index of the item this navmesh was read from
+    pub item: u64,
+
+    pub vertices: Vec<Vec3>,
+    pub faces: Vec<NavMeshFace>,
+    pub edges: Vec<NavMeshEdge>,
+
+    /// Per-face user data, `face_data_striding` values per face
+    pub face_data: Vec<i32>,
+    pub face_data_striding: usize,
+    /// Per-edge user data, `edge_data_striding` values per edge
+    pub edge_data: Vec<i32>,
+    pub edge_data_striding: usize,
+
+    pub aabb_min: Vec3,
+    pub aabb_max: Vec3,
+    pub erosion_radius: f32,
+    pub user_data: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NavMeshFace {
+    /// Boundary edges of the face, in winding order
+    pub edges: Range<usize>,
+    /// Additional edges added at runtime, empty for baked navmeshes
+    pub user_edges: Range<usize>,
+    pub cluster_index: i16,
+}
+
+#[derive(Debug, Clone)]
+pub struct NavMeshEdge {
+    /// Start and end vertex
+    pub a: u32,
+    pub b: u32,
+    /// Matching edge of the neighbouring face, `None` on the boundary of the navmesh
+    pub opposite_edge: Option<u32>,
+    /// Neighbouring face, `None` on the boundary of the navmesh.
+    /// Only an index into `faces` if the edge isn't flagged as [`NavMeshEdgeFlags::EXTERNAL_OPPOSITE`]
+    pub opposite_face: Option<u32>,
+    pub flags: NavMeshEdgeFlags,
+    pub user_edge_cost: f32,
+}
+
+impl NavMeshEdge {
+    /// Face on the other side of this edge that can be walked to, if any
+    pub fn traversable_face(&self) -> Option<usize> {
+        if self
+            .flags
+            .intersects(NavMeshEdgeFlags::BLOCKED | NavMeshEdgeFlags::EXTERNAL_OPPOSITE)
+        {
+            return None;
+        }
+
+        self.opposite_face.map(|f| f as usize)
+    }
+}
+
+/// Reads every navmesh in a tagfile.
+///
+/// `compendium` provides the type information for files without a TYPE section, see
+/// [`HavokTagFile::read_with_compendium`].
+pub fn read_navmeshes(
+    f: &mut (impl Read + Seek),
+    compendium: Option<&TypeCompendium>,
+) -> anyhow::Result<Vec<NavMesh>> {
+    let tagfile = HavokTagFile::read_with_compendium(f, compendium)?;
+    NavMesh::read_all(&tagfile, f)
+}
+
+impl NavMesh {
+    /// Reads every navmesh in an already parsed tagfile.
+    ///
+    /// Fails with [`HavokError::UnknownType`] if there are none, but the file has items of unknown type that
+    /// could be navmeshes.
+    pub fn read_all(
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+    ) -> anyhow::Result<Vec<Self>> {
+        let items = tagfile.find_items("hkaiNavMesh");
+        if items.is_empty() {
+            if let Some(item) = tagfile.first_unnamed_item() {
+                return Err(HavokError::UnknownType {
+                    item,
+                    typ: tagfile.items[item as usize].typ,
+                }
+                .into());
+            }
+        }
+
+        items
+            .into_iter()
+            .map(|item| {
+                NavMesh::read(tagfile, f, item)
+                    .with_context(|| format!("Failed to read navmesh {item}"))
+            })
+            .collect()
+    }
+
+    pub fn read(
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+        item_index: u64,
+    ) -> anyhow::Result<Self> {
+        let item = tagfile.require_item(item_index, "Navmesh")?;
+        if !tagfile.types.is_a(item.typ, "hkaiNavMesh") {
+            return Err(HavokError::UnhandledType {
+                context: "navmesh",
+                name: tagfile
+                    .item_type_name(item_index)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("0x{:x}", item.typ)),
+            }
+            .into());
+        }
+
+        f.seek(SeekFrom::Start(item.offset as u64))?;
+        let navmesh: hkaiNavMesh = f.read_type(tagfile.endian)?;
+
+        let vertices: Vec<Vec3> = navmesh
+            .vertices
+            .read(tagfile, f)
+            .context("Failed to read navmesh vertices")?
+            .into_iter()
+            .map(|v: [f32; 4]| Vec3::from_slice(&v))
+            .collect();
+
+        let edges = navmesh
+            .edges
+            .read(tagfile, f)
+            .context("Failed to read navmesh edges")?
+            .into_iter()
+            .map(|e| -> anyhow::Result<NavMeshEdge> {
+                let vertex = |v: i32| -> anyhow::Result<u32> {
+                    anyhow::ensure!(
+                        (0..vertices.len() as i64).contains(&(v as i64)),
+                        "Navmesh edge references invalid vertex {v}"
+                    );
+                    Ok(v as u32)
+                };
+
+                Ok(NavMeshEdge {
+                    a: vertex(e.a)?,
+                    b: vertex(e.b)?,
+                    opposite_edge: Some(e.opposite_edge).filter(|&i| i != u32::MAX),
+                    opposite_face: Some(e.opposite_face).filter(|&i| i != u32::MAX),
+                    flags: e.flags,
+                    // hkHalf16 stores the upper 16 bits of an f32
+                    user_edge_cost: f32::from_bits((e.user_edge_cost as u32) << 16),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let faces = navmesh
+            .faces
+            .read(tagfile, f)
+            .context("Failed to read navmesh faces")?
+            .into_iter()
+            .map(|face| -> anyhow::Result<NavMeshFace> {
+                let range = |start: i32, count: i16| -> anyhow::Result<Range<usize>> {
+                    if count <= 0 {
+                        return Ok(0..0);
+                    }
+
+                    let start =
+                        usize::try_from(start).context("Navmesh face has a negative edge index")?;
+                    let range = start..start + count as usize;
+                    anyhow::ensure!(
+                        range.end <= edges.len(),
+                        "Navmesh face references edges {range:?}, but there are only {}",
+                        edges.len()
+                    );
+                    Ok(range)
+                };
+
+                Ok(NavMeshFace {
+                    edges: range(face.start_edge_index, face.num_edges)?,
+                    user_edges: range(face.start_user_edge_index, face.num_user_edges)?,
+                    cluster_index: face.cluster_index,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let face_data = navmesh
+            .face_data
+            .read(tagfile, f)
+            .context("Failed to read navmesh face data")?;
+        let edge_data = navmesh
+            .edge_data
+            .read(tagfile, f)
+            .context("Failed to read navmesh edge data")?;
+
+        Ok(NavMesh {
+            item: item_index,
+            vertices,
+            faces,
+            edges,
+            face_data,
+            face_data_striding: navmesh.face_data_striding.max(0) as usize,
+            edge_data,
+            edge_data_striding: navmesh.edge_data_striding.max(0) as usize,
+            aabb_min: navmesh.aabb_min.truncate(),
+            aabb_max: navmesh.aabb_max.truncate(),
+            erosion_radius: navmesh.erosion_radius,
+            user_data: navmesh.user_data,
+        })
+    }
+
+    /// Vertex indices of the face, in winding order
+    pub fn face_vertices(&self, face: usize) -> Vec<u32> {
+        self.edges[self.faces[face].edges.clone()]
+            .iter()
+            .map(|e| e.a)
+            .collect()
+    }
+
+    /// Average of the face vertices
+    pub fn face_center(&self, face: usize) -> Vec3 {
+        let vertices = self.face_vertices(face);
+        if vertices.is_empty() {
+            return Vec3::ZERO;
+        }
+
+        vertices
+            .iter()
+            .map(|&v| self.vertices[v as usize])
+            .sum::<Vec3>()
+            / vertices.len() as f32
+    }
+
+    /// Faces that can be walked to from the given face, along with the edge crossed to get there
+    pub fn face_neighbours(&self, face: usize) -> Vec<(usize, usize)> {
+        self.faces[face]
+            .edges
+            .clone()
+            .filter_map(|edge| {
+                let opposite = self.edges[edge].traversable_face()?;
+                (opposite < self.faces.len()).then_some((edge, opposite))
+            })
+            .collect()
+    }
+
+    /// User data of the face, empty if the navmesh has none
+    pub fn face_user_data(&self, face: usize) -> &[i32] {
+        let start = face * self.face_data_striding;
+        self.face_data
+            .get(start..start + self.face_data_striding)
+            .unwrap_or_default()
+    }
+
+    /// User data of the edge, empty if the navmesh has none
+    pub fn edge_user_data(&self, edge: usize) -> &[i32] {
+        let start = edge * self.edge_data_striding;
+        self.edge_data
+            .get(start..start + self.edge_data_striding)
+            .unwrap_or_default()
+    }
+
+    /// Triangulates every face into a fan, keeping the winding of the navmesh
+    pub fn to_shape(&self) -> Shape {
+        let mut shape = Shape {
+            vertices: self.vertices.clone(),
+            indices: vec![],
+        };
+
+        for face in 0..self.faces.len() {
+            let vertices = self.face_vertices(face);
+            let Some((&first, rest)) = vertices.split_first() else {
+                continue;
+            };
+
+            for pair in rest.windows(2) {
+                shape.indices.extend([first, pair[0], pair[1]]);
+            }
+        }
+
+        shape
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::writer::{ItemData, TagFileWriter};
+
+    /// A unit square split into two triangles along the diagonal from vertex 0 to 2
+    fn write_square(writer: &mut TagFileWriter) -> Vec<u8> {
+        let mut vertices = ItemData::new();
+        for v in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+            vertices.vec4(glam::Vec4::new(v[0], 0.0, v[1], 0.0));
+        }
+
+        let mut faces = ItemData::new();
+        for (start, cluster) in [(0, 1), (3, 2)] {
+            faces.i32(start).i32(-1).u16(3).u16(0).u16(cluster).u16(0);
+        }
+
+        // (a, b, opposite edge, opposite face, flags)
+        let edges_raw = [
+            (0, 1, -1, -1, 0),
+            (1, 2, -1, -1, 0),
+            (2, 0, 3, 1, 0),
+            (0, 2, 2, 0, 0),
+            (2, 3, -1, -1, 0),
+            (3, 0, -1, -1, NavMeshEdgeFlags::USER.bits()),
+        ];
+        let mut edges = ItemData::new();
+        for (a, b, opposite_edge, opposite_face, flags) in edges_raw {
+            edges
+                .i32(a)
+                .i32(b)
+                .i32(opposite_edge)
+                .i32(opposite_face)
+                .u8(flags)
+                .u8(0)
+                // 1.0 as hkHalf16
+                .u16(0x3f80);
+        }
+
+        let mut face_data = ItemData::new();
+        face_data.i32(7).i32(8);
+
+        let faces = writer.add_array("hkaiNavMesh::Face", 2, &faces).unwrap();
+        let edges = writer
+            .add_array("hkaiNavMesh::Edge", edges_raw.len(), &edges)
+            .unwrap();
+        let vertices = writer.add_array("hkVector4", 4, &vertices).unwrap();
+        let face_data = writer.add_array("hkInt32", 2, &face_data).unwrap();
+
+        let mut navmesh = ItemData::new();
+        navmesh
+            .zeros(16)
+            .array(faces, 2)
+            .array(edges, edges_raw.len())
+            .array(vertices, 4)
+            .array(None, 0)
+            .array(face_data, 2)
+            .array(None, 0)
+            .i32(1)
+            .i32(0)
+            .zeros(8)
+            .vec4(glam::Vec4::ZERO)
+            .vec4(glam::Vec4::new(1.0, 0.0, 1.0, 0.0))
+            .f32(0.25)
+            .u32(0)
+            .u64(0x1234);
+        writer.add_item("hkaiNavMesh", &navmesh).unwrap();
+
+        let mut data = vec![];
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn square_navmesh() {
+        let data = write_square(&mut TagFileWriter::new());
+        let navmeshes = read_navmeshes(&mut Cursor::new(&data), None).unwrap();
+        assert_eq!(navmeshes.len(), 1);
+
+        let navmesh = &navmeshes[0];
+        assert_eq!(navmesh.vertices.len(), 4);
+        assert_eq!(navmesh.vertices[2], Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!(navmesh.aabb_max, Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!((navmesh.erosion_radius, navmesh.user_data), (0.25, 0x1234));
+
+        assert_eq!(navmesh.faces.len(), 2);
+        assert_eq!(navmesh.faces[1].edges, 3..6);
+        assert_eq!(navmesh.faces[1].user_edges, 0..0);
+        assert_eq!(navmesh.faces[1].cluster_index, 2);
+        assert_eq!(navmesh.face_vertices(1), [0, 2, 3]);
+        assert_eq!(navmesh.face_user_data(1), [8]);
+        assert!(navmesh.edge_user_data(0).is_empty());
+
+        let edge = &navmesh.edges[5];
+        assert_eq!((edge.opposite_edge, edge.opposite_face), (None, None));
+        assert_eq!(edge.flags, NavMeshEdgeFlags::USER);
+        assert_eq!(edge.user_edge_cost, 1.0);
+
+        // The triangles only connect through the diagonal
+        assert_eq!(navmesh.face_neighbours(0), [(2, 1)]);
+        assert_eq!(navmesh.face_neighbours(1), [(3, 0)]);
+
+        assert_eq!(navmesh.to_shape().indices, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn navmesh_without_type_section() {
+        let mut writer = TagFileWriter::new();
+        writer.compendium_id = Some(1);
+        let data = write_square(&mut writer);
+
+        let err = read_navmeshes(&mut Cursor::new(&data), None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(HavokError::UnknownType { .. })
+        ));
+
+        let compendium = TypeCompendium {
+            ids: vec![1],
+            types: writer.types().clone(),
+        };
+        let navmeshes = read_navmeshes(&mut Cursor::new(&data), Some(&compendium)).unwrap();
+        assert_eq!(navmeshes[0].faces.len(), 2);
+    }
+}
@@ -0,0 +1,470 @@
+use glam::Vec3;
+use parry3d::{
+    bounding_volume::{Aabb, BoundingVolume, SimdAabb},
+    math::{Isometry, Real, SimdBool, SimdReal, SIMD_WIDTH},
+    na::{Point3, Vector3},
+    partitioning::{Qbvh, SimdBestFirstVisitStatus, SimdBestFirstVisitor},
+    query::{
+        visitors::{PointIntersectionsVisitor, RayIntersectionsVisitor},
+        PointProjection, PointQuery, Ray, RayCast,
+    },
+    shape::{Cuboid, TriMesh, TriMeshFlags},
+    simba::simd::{SimdBool as _, SimdPartialOrd, SimdValue},
+};
+
+use crate::shape_collection::{Shape, ShapeHierarchy, ShapeLeaf};
+
+/// Identifies the shape a query result belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QueryShapeId {
+    /// Index of the shape in the collection
+    pub shape: usize,
+    /// Mesh node in the shape hierarchy, if the query was built from one
+    pub node: Option<usize>,
+    /// Index of the leaf within the shape, if the query was built from a hierarchy
+    pub leaf: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub id: QueryShapeId,
+    /// Distance along the ray, in multiples of the direction vector
+    pub toi: f32,
+    pub point: Vec3,
+    pub normal: Vec3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClosestPoint {
+    pub id: QueryShapeId,
+    pub point: Vec3,
+    pub distance: f32,
+    /// Whether the query point is inside the shape. Only meaningful for closed meshes
+    pub is_inside: bool,
+}
+
+struct QueryPiece {
+    id: QueryShapeId,
+    mesh: TriMesh,
+}
+
+/// Acceleration structure for ray casts and proximity queries over a parsed shape collection.
+///
+/// Point containment relies on the meshes being closed and outward facing, which holds for convex shapes
+/// but not for open mesh shapes such as terrain.
+pub struct CollisionQuery {
+    pieces: Vec<QueryPiece>,
+    qbvh: Qbvh<u32>,
+}
+
+impl CollisionQuery {
+    /// Builds a query structure from the shapes returned by `read_shape_collection`
+    pub fn from_shapes(shapes: &[Shape]) -> Self {
+        let pieces = shapes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, shape)| {
+                Some(QueryPiece {
+                    id: QueryShapeId {
+                        shape: i,
+                        node: None,
+                        leaf: None,
+                    },
+                    mesh: build_trimesh(&shape.vertices, &shape.indices)?,
+                })
+            })
+            .collect();
+
+        Self::from_pieces(pieces)
+    }
+
+    /// Builds a query structure from a shape hierarchy, keeping every instanced mesh separate
+    pub fn from_hierarchy(hierarchy: &ShapeHierarchy) -> Self {
+        Self::from_hierarchy_filtered(hierarchy, |_| true)
+    }
+
+    /// Like [`Self::from_hierarchy`], but only includes leaves accepted by `filter`.
+    ///
+    /// Useful for limiting queries to certain collision layers, see [`ShapeLeaf::collision`].
+    pub fn from_hierarchy_filtered(
+        hierarchy: &ShapeHierarchy,
+        filter: impl Fn(&ShapeLeaf) -> bool,
+    ) -> Self {
+        let mut pieces = vec![];
+        for (shape, &root) in hierarchy.roots.iter().enumerate() {
+            for (leaf_index, leaf) in hierarchy.leaves(root).into_iter().enumerate() {
+                if !filter(&leaf) {
+                    continue;
+                }
+
+                let Some(mesh) = hierarchy.node(leaf.node).mesh() else {
+                    continue;
+                };
+
+                let vertices = mesh
+                    .vertices
+                    .iter()
+                    .map(|v| leaf.transform.transform_point3(*v))
+                    .collect::<Vec<_>>();
+
+                if let Some(mesh) = build_trimesh(&vertices, &mesh.indices) {
+                    pieces.push(QueryPiece {
+                        id: QueryShapeId {
+                            shape,
+                            node: Some(leaf.node),
+                            leaf: Some(leaf_index),
+                        },
+                        mesh,
+                    });
+                }
+            }
+        }
+
+        Self::from_pieces(pieces)
+    }
+
+    fn from_pieces(pieces: Vec<QueryPiece>) -> Self {
+        let mut qbvh = Qbvh::new();
+        qbvh.clear_and_rebuild(
+            pieces
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (i as u32, *p.mesh.local_aabb())),
+            0.0,
+        );
+
+        Self { pieces, qbvh }
+    }
+
+    /// Returns the closest hit along the ray, if any
+    pub fn cast_ray(&self, origin: Vec3, direction: Vec3, max_toi: f32) -> Option<RayHit> {
+        self.cast_ray_all(origin, direction, max_toi)
+            .into_iter()
+            .next()
+    }
+
+    /// Returns the first hit on every shape along the ray, sorted by distance
+    pub fn cast_ray_all(&self, origin: Vec3, direction: Vec3, max_toi: f32) -> Vec<RayHit> {
+        let ray = Ray::new(to_point(origin), to_vector(direction));
+
+        let mut hits = vec![];
+        let mut visit = |&i: &u32| {
+            let piece = &self.pieces[i as usize];
+            if let Some(hit) = piece
+                .mesh
+                .cast_local_ray_and_get_normal(&ray, max_toi, false)
+            {
+                hits.push(RayHit {
+                    id: piece.id,
+                    toi: hit.toi,
+                    point: from_point(ray.point_at(hit.toi)),
+                    normal: Vec3::new(hit.normal.x, hit.normal.y, hit.normal.z),
+                });
+            }
+            true
+        };
+        self.qbvh
+            .traverse_depth_first(&mut RayIntersectionsVisitor::new(&ray, max_toi, &mut visit));
+
+        hits.sort_by(|a, b| a.toi.total_cmp(&b.toi));
+        hits
+    }
+
+    /// Returns every shape containing the given point
+    pub fn shapes_containing_point(&self, point: Vec3) -> Vec<QueryShapeId> {
+        let p = to_point(point);
+        let mut candidates = vec![];
+        let mut visit = |&i: &u32| {
+            candidates.push(i);
+            true
+        };
+        self.qbvh
+            .traverse_depth_first(&mut PointIntersectionsVisitor::new(&p, &mut visit));
+        candidates.sort_unstable();
+
+        candidates
+            .into_iter()
+            .map(|i| &self.pieces[i as usize])
+            .filter(|piece| piece.mesh.contains_local_point(&p))
+            .map(|piece| piece.id)
+            .collect()
+    }
+
+    /// Returns true if the point is inside the shape at the given collection index
+    pub fn shape_contains_point(&self, shape: usize, point: Vec3) -> bool {
+        self.shapes_containing_point(point)
+            .iter()
+            .any(|id| id.shape == shape)
+    }
+
+    /// Returns the closest point on any shape
+    pub fn closest_point(&self, point: Vec3) -> Option<ClosestPoint> {
+        let p = to_point(point);
+        let (_, (i, projection)) = self.qbvh.traverse_best_first(&mut ClosestPieceVisitor {
+            pieces: &self.pieces,
+            point: p,
+            simd_point: Point3::splat(p),
+        })?;
+
+        Some(ClosestPoint {
+            id: self.pieces[i as usize].id,
+            point: from_point(projection.point),
+            distance: parry3d::na::distance(&p, &projection.point),
+            is_inside: projection.is_inside,
+        })
+    }
+
+    /// Returns every shape with at least one triangle overlapping the given box, or that fully encloses it
+    pub fn shapes_intersecting_aabb(&self, min: Vec3, max: Vec3) -> Vec<QueryShapeId> {
+        let aabb = Aabb::new(to_point(min), to_point(max));
+        let cuboid = Cuboid::new(aabb.half_extents());
+        let cuboid_pos = Isometry::translation(aabb.center().x, aabb.center().y, aabb.center().z);
+
+        let mut candidates = vec![];
+        self.qbvh.intersect_aabb(&aabb, &mut candidates);
+        candidates.sort_unstable();
+
+        candidates
+            .into_iter()
+            .map(|i| &self.pieces[i as usize])
+            .filter(|piece| {
+                let mut triangles = vec![];
+                piece.mesh.qbvh().intersect_aabb(&aabb, &mut triangles);
+                triangles.into_iter().any(|t| {
+                    parry3d::query::intersection_test(
+                        &cuboid_pos,
+                        &cuboid,
+                        &Isometry::identity(),
+                        &piece.mesh.triangle(t),
+                    )
+                    .unwrap_or(false)
+                }) || piece.mesh.contains_local_point(&aabb.center())
+            })
+            .map(|piece| piece.id)
+            .collect()
+    }
+
+    /// Bounding box of all shapes
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let aabb = self
+            .pieces
+            .iter()
+            .map(|p| *p.mesh.local_aabb())
+            .reduce(|a, b| a.merged(&b))
+            .unwrap_or_else(Aabb::new_invalid);
+
+        (from_point(aabb.mins), from_point(aabb.maxs))
+    }
+}
+
+/// Best-first search for the piece closest to a point, skipping subtrees farther away than the best hit so far
+struct ClosestPieceVisitor<'a> {
+    pieces: &'a [QueryPiece],
+    point: Point3<Real>,
+    simd_point: Point3<SimdReal>,
+}
+
+impl SimdBestFirstVisitor<u32, SimdAabb> for ClosestPieceVisitor<'_> {
+    type Result = (u32, PointProjection);
+
+    fn visit(
+        &mut self,
+        best: Real,
+        aabb: &SimdAabb,
+        data: Option<[Option<&u32>; SIMD_WIDTH]>,
+    ) -> SimdBestFirstVisitStatus<Self::Result> {
+        let dist = aabb.distance_to_local_point(&self.simd_point);
+        let mask = dist.simd_lt(SimdReal::splat(best));
+
+        let Some(data) = data else {
+            return SimdBestFirstVisitStatus::MaybeContinue {
+                weights: dist,
+                mask,
+                results: [None; SIMD_WIDTH],
+            };
+        };
+
+        let bitmask = mask.bitmask();
+        let mut weights = [0.0; SIMD_WIDTH];
+        let mut hits = [false; SIMD_WIDTH];
+        let mut results = [None; SIMD_WIDTH];
+        for (ii, &i) in data.iter().enumerate() {
+            let Some(&i) = i.filter(|_| bitmask & (1 << ii) != 0) else {
+                continue;
+            };
+
+            let projection = self.pieces[i as usize]
+                .mesh
+                .project_local_point(&self.point, false);
+            weights[ii] = parry3d::na::distance(&self.point, &projection.point);
+            hits[ii] = true;
+            results[ii] = Some((i, projection));
+        }
+
+        SimdBestFirstVisitStatus::MaybeContinue {
+            weights: SimdReal::from(weights),
+            mask: SimdBool::from(hits),
+            results,
+        }
+    }
+}
+
+fn build_trimesh(vertices: &[Vec3], indices: &[u32]) -> Option<TriMesh> {
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .filter(|t| t.iter().all(|&i| (i as usize) < vertices.len()))
+        .collect::<Vec<_>>();
+
+    if triangles.is_empty() {
+        return None;
+    }
+
+    Some(TriMesh::with_flags(
+        vertices.iter().map(|v| to_point(*v)).collect(),
+        triangles,
+        TriMeshFlags::ORIENTED,
+    ))
+}
+
+fn to_point(v: Vec3) -> Point3<f32> {
+    Point3::new(v.x, v.y, v.z)
+}
+
+fn to_vector(v: Vec3) -> Vector3<f32> {
+    Vector3::new(v.x, v.y, v.z)
+}
+
+fn from_point(p: Point3<f32>) -> Vec3 {
+    Vec3::new(p.x, p.y, p.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Closed, outward facing box
+    fn cuboid(min: Vec3, max: Vec3) -> Shape {
+        Shape {
+            vertices: (0..8)
+                .map(|i| {
+                    Vec3::select(
+                        glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                        max,
+                        min,
+                    )
+                })
+                .collect(),
+            indices: vec![
+                0, 4, 6, 0, 6, 2, // -x
+                1, 3, 7, 1, 7, 5, // +x
+                0, 1, 5, 0, 5, 4, // -y
+                2, 6, 7, 2, 7, 3, // +y
+                0, 2, 3, 0, 3, 1, // -z
+                4, 5, 7, 4, 7, 6, // +z
+            ],
+        }
+    }
+
+    /// Open, upward facing quad at y = 0 spanning x 10..12 and z 0..2
+    fn ground() -> Shape {
+        Shape {
+            vertices: vec![
+                Vec3::new(10.0, 0.0, 0.0),
+                Vec3::new(12.0, 0.0, 0.0),
+                Vec3::new(12.0, 0.0, 2.0),
+                Vec3::new(10.0, 0.0, 2.0),
+            ],
+            indices: vec![0, 2, 1, 0, 3, 2],
+        }
+    }
+
+    fn query() -> CollisionQuery {
+        CollisionQuery::from_shapes(&[
+            cuboid(Vec3::ZERO, Vec3::ONE),
+            ground(),
+            cuboid(Vec3::new(5.0, 0.0, 0.0), Vec3::new(6.0, 1.0, 1.0)),
+        ])
+    }
+
+    #[test]
+    fn ray_hits() {
+        let query = query();
+
+        let hit = query
+            .cast_ray(Vec3::new(-5.0, 0.5, 0.5), Vec3::X, 100.0)
+            .unwrap();
+        assert_eq!(hit.id.shape, 0);
+        assert_eq!(hit.toi, 5.0);
+        assert_eq!(hit.point, Vec3::new(0.0, 0.5, 0.5));
+        assert_eq!(hit.normal, Vec3::NEG_X);
+
+        let hit = query
+            .cast_ray(Vec3::new(11.0, 5.0, 1.0), Vec3::NEG_Y, 100.0)
+            .unwrap();
+        assert_eq!(hit.id.shape, 1);
+        assert_eq!(hit.toi, 5.0);
+        assert_eq!(hit.point, Vec3::new(11.0, 0.0, 1.0));
+
+        let hits = query.cast_ray_all(Vec3::new(-5.0, 0.5, 0.5), Vec3::X, 100.0);
+        assert_eq!(hits.iter().map(|h| h.id.shape).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(hits[1].toi, 10.0);
+    }
+
+    #[test]
+    fn ray_misses() {
+        let query = query();
+
+        assert!(query
+            .cast_ray(Vec3::new(-5.0, 0.5, 0.5), Vec3::Y, 100.0)
+            .is_none());
+        // Out of range
+        assert!(query
+            .cast_ray(Vec3::new(-5.0, 0.5, 0.5), Vec3::X, 4.0)
+            .is_none());
+        assert!(query
+            .cast_ray(Vec3::new(11.0, 5.0, 5.0), Vec3::NEG_Y, 100.0)
+            .is_none());
+    }
+
+    #[test]
+    fn point_containment() {
+        let query = query();
+
+        let inside = query.shapes_containing_point(Vec3::splat(0.5));
+        assert_eq!(inside.len(), 1);
+        assert_eq!(inside[0].shape, 0);
+        assert!(query.shape_contains_point(0, Vec3::splat(0.5)));
+
+        assert!(query.shapes_containing_point(Vec3::splat(5.0)).is_empty());
+        assert!(query
+            .shapes_containing_point(Vec3::new(11.0, 0.0, 1.0))
+            .iter()
+            .all(|id| id.shape != 0));
+    }
+
+    #[test]
+    fn closest_points() {
+        let query = query();
+
+        let closest = query.closest_point(Vec3::new(0.5, 3.0, 0.5)).unwrap();
+        assert_eq!(closest.id.shape, 0);
+        assert_eq!(closest.point, Vec3::new(0.5, 1.0, 0.5));
+        assert_eq!(closest.distance, 2.0);
+        assert!(!closest.is_inside);
+
+        let closest = query.closest_point(Vec3::new(11.0, 0.5, 1.0)).unwrap();
+        assert_eq!(closest.id.shape, 1);
+        assert_eq!(closest.point, Vec3::new(11.0, 0.0, 1.0));
+        assert_eq!(closest.distance, 0.5);
+
+        let closest = query.closest_point(Vec3::new(0.5, 0.5, 0.25)).unwrap();
+        assert_eq!(closest.id.shape, 0);
+        assert_eq!(closest.point, Vec3::new(0.5, 0.5, 0.0));
+        assert!(closest.is_inside);
+
+        assert!(CollisionQuery::from_shapes(&[])
+            .closest_point(Vec3::ZERO)
+            .is_none());
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Context;
+use binrw::{BinReaderExt, Endian};
+
+use crate::{
+    index::ItemFlags,
+    tagfile::HavokTagFile,
+    type_registry::{TypeKind, FORMAT_SIGNED},
+    types::hkPointer,
+};
+
+/// Maximum nesting depth for records, arrays and tuples before giving up
+const MAX_DEPTH: usize = 64;
+
+/// A dynamically read value, laid out according to the type information of the tagfile
+#[derive(Debug, Clone)]
+pub enum ReflectedValue {
+    Void,
+    Opaque(Vec<u8>),
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(Option<String>),
+    /// Index of the referenced item, `None` for null pointers
+    Pointer(Option<u64>),
+    Array(Vec<ReflectedValue>),
+    Object(ReflectedObject),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReflectedObject {
+    pub class_name: String,
+    pub fields: Vec<(String, ReflectedValue)>,
+}
+
+impl ReflectedObject {
+    pub fn get(&self, name: &str) -> Option<&ReflectedValue> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+
+impl HavokTagFile {
+    /// Reads the contents of an item using the type information of this file.
+    ///
+    /// Items flagged as arrays (or with a count other than 1) are returned as [`ReflectedValue::Array`]
+    pub fn read_item_reflected(
+        &self,
+        f: &mut (impl Read + Seek),
+        index: u64,
+    ) -> anyhow::Result<ReflectedValue> {
+        anyhow::ensure!(
+            self.types.has_layouts,
+            "Tagfile has no type layouts, a type compendium is required for reflection"
+        );
+
+        let item = self
+            .item(index)
+            .with_context(|| format!("Invalid item index {index}"))?;
+
+        if item.flags.contains(ItemFlags::ARRAY) || item.count != 1 {
+            self.read_array_reflected(f, item.typ, item.offset as u64, item.count as usize, 0)
+        } else {
+            self.read_value_reflected(f, item.typ, item.offset as u64, 0)
+        }
+    }
+
+    fn read_array_reflected(
+        &self,
+        f: &mut (impl Read + Seek),
+        typ: u32,
+        offset: u64,
+        count: usize,
+        depth: usize,
+    ) -> anyhow::Result<ReflectedValue> {
+        let stride = self
+            .types
+            .size(typ)
+            .with_context(|| format!("Type {} has no size", self.types.full_name(typ)))?
+            as u64;
+        // Zero-sized elements are still limited to one per byte, so bogus counts can't run away
+        self.ensure_data_range(offset, (count as u64).saturating_mul(stride.max(1)))?;
+
+        let mut values = Vec::with_capacity(count.min(0x10000));
+        for i in 0..count as u64 {
+            values.push(self.read_value_reflected(f, typ, offset + i * stride, depth + 1)?);
+        }
+
+        Ok(ReflectedValue::Array(values))
+    }
+
+    /// Reads a single value of type `typ` at the given absolute offset
+    pub fn read_value_reflected(
+        &self,
+        f: &mut (impl Read + Seek),
+        typ: u32,
+        offset: u64,
+        depth: usize,
+    ) -> anyhow::Result<ReflectedValue> {
+        anyhow::ensure!(
+            depth < MAX_DEPTH,
+            "Maximum reflection depth exceeded while reading {}",
+            self.types.full_name(typ)
+        );
+
+        let size = self.types.size(typ).unwrap_or(0);
+        let format = self.types.format(typ).unwrap_or(0);
+        let endian = self.endian;
+
+        f.seek(SeekFrom::Start(offset))?;
+        Ok(match self.types.kind(typ) {
+            TypeKind::Void => ReflectedValue::Void,
+            TypeKind::Opaque | TypeKind::Unknown(_) => {
+                self.ensure_data_range(offset, size as u64)?;
+                let mut data = vec![0u8; size as usize];
+                f.read_exact(&mut data)?;
+                ReflectedValue::Opaque(data)
+            }
+            TypeKind::Bool => ReflectedValue::Bool(read_uint(f, endian, size)? != 0),
+            TypeKind::Int => {
+                let v = read_uint(f, endian, size)?;
+                if format & FORMAT_SIGNED != 0 {
+                    // Sign-extend from the size of the integer
+                    let shift = 64 - size.clamp(1, 8) * 8;
+                    ReflectedValue::Int(((v << shift) as i64) >> shift)
+                } else {
+                    ReflectedValue::UInt(v)
+                }
+            }
+            TypeKind::Float => match size {
+                8 => ReflectedValue::Float(f.read_type::<f64>(endian)?),
+                2 => {
+                    // hkHalf16 stores the upper 16 bits of an f32
+                    let bits = f.read_type::<u16>(endian)? as u32;
+                    ReflectedValue::Float(f32::from_bits(bits << 16) as f64)
+                }
+                _ => ReflectedValue::Float(f.read_type::<f32>(endian)? as f64),
+            },
+            TypeKind::String => {
+                let pointer: hkPointer = f.read_type(endian)?;
+                match pointer.resolve(self)?.and_then(|i| self.item(i)) {
+                    Some(item) => {
+                        self.ensure_data_range(item.offset as u64, item.count as u64)?;
+                        let mut data = vec![0u8; item.count as usize];
+                        f.seek(SeekFrom::Start(item.offset as u64))?;
+                        f.read_exact(&mut data)?;
+                        ReflectedValue::String(Some(
+                            String::from_utf8_lossy(&data)
+                                .trim_end_matches('\0')
+                                .to_string(),
+                        ))
+                    }
+                    None => ReflectedValue::String(None),
+                }
+            }
+            TypeKind::Pointer => {
+                let pointer: hkPointer = f.read_type(endian)?;
+                ReflectedValue::Pointer(pointer.resolve(self)?)
+            }
+            TypeKind::Array => {
+                let pointer: hkPointer = f.read_type(endian)?;
+                match pointer.resolve(self)?.and_then(|i| self.item(i)) {
+                    Some(item) => self.read_array_reflected(
+                        f,
+                        item.typ,
+                        item.offset as u64,
+                        item.count as usize,
+                        depth,
+                    )?,
+                    None => ReflectedValue::Array(vec![]),
+                }
+            }
+            TypeKind::Tuple => {
+                let count = format >> 8;
+                let element_type = self.types.subtype(typ).with_context(|| {
+                    format!(
+                        "Tuple type {} has no element type",
+                        self.types.full_name(typ)
+                    )
+                })?;
+                self.read_array_reflected(f, element_type, offset, count as usize, depth)?
+            }
+            TypeKind::Record => {
+                let mut fields = vec![];
+                for m in self.types.members(typ) {
+                    fields.push((
+                        m.name.clone(),
+                        self.read_value_reflected(f, m.typ, offset + m.offset as u64, depth + 1)?,
+                    ));
+                }
+
+                ReflectedValue::Object(ReflectedObject {
+                    class_name: self.types.full_name(typ),
+                    fields,
+                })
+            }
+        })
+    }
+}
+
+fn read_uint(f: &mut (impl Read + Seek), endian: Endian, size: u32) -> anyhow::Result<u64> {
+    Ok(match size {
+        1 => f.read_type::<u8>(endian)? as u64,
+        2 => f.read_type::<u16>(endian)? as u64,
+        4 => f.read_type::<u32>(endian)? as u64,
+        8 => f.read_type::<u64>(endian)?,
+        s => anyhow::bail!("Unsupported integer size {s}"),
+    })
+}
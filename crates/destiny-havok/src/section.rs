@@ -1,11 +1,13 @@
 use binrw::binread;
 
-#[derive(Debug)]
+use crate::error::HavokError;
+
+#[derive(Debug, Clone)]
 #[binread(big)]
 pub struct TagSection {
     pub flags_and_size: u32,
 
-    #[br(calc((flags_and_size as usize & 0x3fffffff) - 8))]
+    #[br(calc((flags_and_size as usize & 0x3fffffff).saturating_sub(8)))]
     pub size: usize,
 
     #[br(calc((flags_and_size & 0x40000000) != 0))]
@@ -22,9 +24,52 @@ impl TagSection {
     pub fn end(&self) -> u64 {
         self.offset + self.size as u64
     }
+
+    /// Appends a section with the given contents to `out`, flagged as little endian if `is_le` is set
+    pub fn write(
+        out: &mut Vec<u8>,
+        signature: &TagSectionSignature,
+        is_le: bool,
+        contents: &[u8],
+    ) -> anyhow::Result<()> {
+        let size = contents.len() + 8;
+        anyhow::ensure!(
+            size <= 0x3fffffff,
+            "{} section is too large ({size} bytes)",
+            String::from_utf8_lossy(&signature.fourcc())
+        );
+
+        let mut flags_and_size = size as u32;
+        if is_le {
+            flags_and_size |= 0x40000000;
+        }
+
+        out.extend_from_slice(&flags_and_size.to_be_bytes());
+        out.extend_from_slice(&signature.fourcc());
+        out.extend_from_slice(contents);
+
+        Ok(())
+    }
+
+    /// Fails if this section extends past the end of its parent (or the file)
+    pub fn ensure_within(&self, parent_end: u64) -> Result<(), HavokError> {
+        if self.end() > parent_end {
+            return Err(HavokError::TruncatedSection {
+                section: format!(
+                    "{} section",
+                    String::from_utf8_lossy(&self.signature.fourcc())
+                ),
+                offset: self.offset,
+                size: self.size as u64,
+                available: parent_end.saturating_sub(self.offset),
+            });
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[binread(big)]
 pub enum TagSectionSignature {
     #[br(magic = b"TAG0")]
@@ -47,4 +92,89 @@ pub enum TagSectionSignature {
 
     #[br(magic = b"TCRF")]
     Tcrf,
+
+    #[br(magic = b"TYPE")]
+    Type,
+
+    #[br(magic = b"TPTR")]
+    TypePointers,
+
+    #[br(magic = b"TSTR")]
+    TypeStrings,
+
+    #[br(magic = b"TST1")]
+    TypeStrings1,
+
+    #[br(magic = b"TNAM")]
+    TypeNames,
+
+    #[br(magic = b"TNA1")]
+    TypeNames1,
+
+    #[br(magic = b"FSTR")]
+    FieldStrings,
+
+    #[br(magic = b"FST1")]
+    FieldStrings1,
+
+    #[br(magic = b"TBOD")]
+    TypeBodies,
+
+    #[br(magic = b"TBDY")]
+    TypeBodies1,
+
+    #[br(magic = b"THSH")]
+    TypeHashes,
+
+    #[br(magic = b"TPAD")]
+    TypePadding,
+
+    #[br(magic = b"TCM0")]
+    Compendium,
+
+    #[br(magic = b"TCID")]
+    CompendiumIds,
+
+    Unknown([u8; 4]),
+}
+
+impl TagSectionSignature {
+    /// The four character code identifying this section in a file
+    pub fn fourcc(&self) -> [u8; 4] {
+        match self {
+            TagSectionSignature::Tag0 => *b"TAG0",
+            TagSectionSignature::SdkVersion => *b"SDKV",
+            TagSectionSignature::Data => *b"DATA",
+            TagSectionSignature::Index => *b"INDX",
+            TagSectionSignature::IndexItem => *b"ITEM",
+            TagSectionSignature::Ptch => *b"PTCH",
+            TagSectionSignature::Tcrf => *b"TCRF",
+            TagSectionSignature::Type => *b"TYPE",
+            TagSectionSignature::TypePointers => *b"TPTR",
+            TagSectionSignature::TypeStrings => *b"TSTR",
+            TagSectionSignature::TypeStrings1 => *b"TST1",
+            TagSectionSignature::TypeNames => *b"TNAM",
+            TagSectionSignature::TypeNames1 => *b"TNA1",
+            TagSectionSignature::FieldStrings => *b"FSTR",
+            TagSectionSignature::FieldStrings1 => *b"FST1",
+            TagSectionSignature::TypeBodies => *b"TBOD",
+            TagSectionSignature::TypeBodies1 => *b"TBDY",
+            TagSectionSignature::TypeHashes => *b"THSH",
+            TagSectionSignature::TypePadding => *b"TPAD",
+            TagSectionSignature::Compendium => *b"TCM0",
+            TagSectionSignature::CompendiumIds => *b"TCID",
+            TagSectionSignature::Unknown(fourcc) => *fourcc,
+        }
+    }
+
+    /// Does this section contain other sections?
+    pub fn is_container(&self) -> bool {
+        matches!(
+            self,
+            TagSectionSignature::Tag0
+                | TagSectionSignature::Index
+                | TagSectionSignature::Type
+                | TagSectionSignature::Compendium
+        )
+    }
 }
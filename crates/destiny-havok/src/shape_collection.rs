@@ -1,45 +1,75 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+};
 
 use anyhow::Context;
-use binrw::{binread, BinReaderExt, Endian, VecArgs};
-use glam::Vec3;
+use binrw::{binread, BinReaderExt};
+use glam::{Mat4, Vec3, Vec4};
 use parry3d::na::Point3;
 
 use crate::{
-    index::IndexItem,
-    section::{TagSection, TagSectionSignature},
+    error::HavokError,
+    tagfile::HavokTagFile,
+    type_registry::TypeCompendium,
     types::{
-        compound_shape::{hkpStaticCompoundShape, hkpStaticCompoundShapeInstance},
-        convex_vertices::{hkFourTransposedPoints, hkpConvexVerticesShape},
-        hkArrayIndex, hkPointerIndex,
+        box_shape::{hkpBoxShape, BOX_INDICES},
+        collision_filter::CollisionFilterInfo,
+        compound_shape::{hkQsTransform, hkpStaticCompoundShape, hkpStaticCompoundShapeInstance},
+        compressed_mesh::{
+            hkcdStaticMeshTreeBasePrimitive, hkcdStaticMeshTreeBaseSection,
+            hkpBvCompressedMeshShape, hkpBvCompressedMeshShapeTree,
+        },
+        convex_vertices::{
+            hkFourTransposedPoints, hkpConvexVerticesConnectivity, hkpConvexVerticesShape,
+        },
+        heightfield::{
+            hkpCompressedSampledHeightFieldShape, hkpStorageSampledHeightFieldShape,
+            hkpTriSampledHeightFieldBvTreeShape, hkpTriSampledHeightFieldCollection, HeightField,
+        },
+        hkArray, hkPointer,
         unknown::{Unk81, Unk84},
     },
 };
 
+/// Maximum number of nested list and compound shapes
+pub const MAX_SHAPE_DEPTH: usize = 32;
+
 #[binread]
 #[derive(Debug)]
 pub struct UnkShapeArrayParent {
-    pub shapes: hkArrayIndex,
+    pub shapes: hkArray<UnkShapeArrayEntry>,
 }
 
 #[binread]
 #[derive(Debug)]
 pub struct UnkShapeArrayEntry {
-    pub shape: hkPointerIndex,
+    pub shape: hkPointer,
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Default, Clone)]
 pub struct Shape {
     pub vertices: Vec<Vec3>,
-    pub indices: Vec<u16>,
+    pub indices: Vec<u32>,
 }
 
 impl Shape {
     pub fn combine(&mut self, other: &Self) {
-        let offset = self.vertices.len();
+        let offset = self.vertices.len() as u32;
         self.vertices.extend(other.vertices.iter().cloned());
         self.indices
-            .extend(other.indices.iter().map(|i| i + offset as u16));
+            .extend(other.indices.iter().map(|i| i + offset));
+    }
+
+    /// Returns the indices as 16-bit integers, failing if the shape has too many vertices for them to fit
+    pub fn indices_u16(&self) -> anyhow::Result<Vec<u16>> {
+        anyhow::ensure!(
+            self.vertices.len() <= u16::MAX as usize + 1,
+            "Shape has {} vertices, which does not fit in 16-bit indices",
+            self.vertices.len()
+        );
+
+        Ok(self.indices.iter().map(|&i| i as u16).collect())
     }
 
     pub fn apply_transform(&mut self, transform: glam::Mat4) {
@@ -66,168 +96,383 @@ impl Shape {
     }
 }
 
-pub fn read_shape_collection(f: &mut (impl Read + Seek)) -> anyhow::Result<Vec<Shape>> {
-    // Destiny's havok files have 16 bytes of padding (?) at the start
-    if f.read_be::<u32>()? == 0 {
-        f.seek(SeekFrom::Start(0x10))?;
-    } else {
-        f.seek(SeekFrom::Start(0x0))?;
+/// Reads every shape in the collection, with child shapes flattened into their parent.
+///
+/// `compendium` provides the type information for files without a TYPE section, see
+/// [`HavokTagFile::read_with_compendium`].
+pub fn read_shape_collection(
+    f: &mut (impl Read + Seek),
+    compendium: Option<&TypeCompendium>,
+) -> anyhow::Result<Vec<Shape>> {
+    let hierarchy = read_shape_hierarchy(f, compendium)?;
+
+    Ok(hierarchy
+        .roots
+        .iter()
+        .map(|&root| hierarchy.flatten(root))
+        .collect())
+}
+
+/// Reads the shape collection while keeping the instance hierarchy intact
+pub fn read_shape_hierarchy(
+    f: &mut (impl Read + Seek),
+    compendium: Option<&TypeCompendium>,
+) -> anyhow::Result<ShapeHierarchy> {
+    let tagfile = HavokTagFile::read_with_compendium(f, compendium)?;
+    ShapeHierarchy::read_collection(&tagfile, f)
+}
+
+/// Reads a single shape, flattening any child shapes into it
+pub fn read_shape(
+    tagfile: &HavokTagFile,
+    f: &mut (impl Read + Seek),
+    item: u64,
+) -> anyhow::Result<Shape> {
+    let mut hierarchy = ShapeHierarchy::default();
+    let node = hierarchy.read_node(tagfile, f, item)?;
+    Ok(hierarchy.flatten(node))
+}
+
+/// Shapes in a collection with their instance hierarchy intact.
+///
+/// Every item is only read once, so child shapes shared between multiple instances point to the same node.
+#[derive(Default, Clone)]
+pub struct ShapeHierarchy {
+    pub nodes: Vec<ShapeNode>,
+
+    /// Node index for each entry in the shape collection
+    pub roots: Vec<usize>,
+
+    /// Maps item indices to nodes
+    pub item_nodes: HashMap<u64, usize>,
+}
+
+#[derive(Clone)]
+pub struct ShapeNode {
+    /// Index of the item this node was read from
+    pub item: u64,
+    pub class_name: String,
+    pub kind: ShapeNodeKind,
+}
+
+impl ShapeNode {
+    pub fn mesh(&self) -> Option<&Shape> {
+        match &self.kind {
+            ShapeNodeKind::Mesh(shape) => Some(shape),
+            _ => None,
+        }
     }
+}
 
-    let tag0: TagSection = f.read_be()?;
-    anyhow::ensure!(
-        tag0.signature == TagSectionSignature::Tag0,
-        "First tag must be TAG0",
-    );
-
-    f.seek(SeekFrom::Start(tag0.offset))?;
-
-    let mut data_offset = 0_u64;
-    while f.stream_position()? < tag0.end() {
-        match f.read_be::<TagSection>() {
-            Ok(section) => {
-                match section.signature {
-                    TagSectionSignature::Index => {
-                        f.seek(SeekFrom::Start(section.offset))?;
-                        let item = f.read_be::<TagSection>()?;
-                        let endian = if item.is_le {
-                            Endian::Little
-                        } else {
-                            Endian::Big
-                        };
-                        f.seek(SeekFrom::Start(item.offset))?;
-
-                        let mut items = vec![];
-                        while f.stream_position()? < item.end() {
-                            let mut it: IndexItem = f.read_type(endian)?;
-                            it.offset += data_offset as u32;
-                            items.push(it);
-                        }
-
-                        for item in items.iter().skip(1) {
-                            if item.typ == 0x74 {
-                                f.seek(SeekFrom::Start(item.offset as u64))?;
-                                let shape_array_index: hkArrayIndex = f.read_type(endian)?;
-                                let shape_array_item = items
-                                    .get(shape_array_index as usize)
-                                    .context("Shape array parent references invalid index")?;
-
-                                let shape_indices: Vec<UnkShapeArrayEntry> = f.save_pos_seek(
-                                    SeekFrom::Start(shape_array_item.offset as u64),
-                                    |f| {
-                                        f.read_type_args(
-                                            endian,
-                                            VecArgs {
-                                                count: shape_array_item.count as _,
-                                                inner: (),
-                                            },
-                                        )
-                                        .context("Failed to read shape array")
-                                    },
-                                )?;
-
-                                let mut shapes = vec![];
-                                for s in shape_indices {
-                                    let shape = read_shape(&items, f, s.shape, endian)?;
-                                    shapes.push(shape);
-                                }
-
-                                return Ok(shapes);
-                            }
-                        }
-                    }
-                    // TagSectionSignature::SdkVersion => {
-                    //     f.seek(SeekFrom::Start(section.offset))?;
-                    //     let mut data = vec![0u8; section.size];
-                    //     f.read_exact(&mut data)?;
-                    //     let version = String::from_utf8_lossy(&data);
-                    // }
-                    TagSectionSignature::Data => {
-                        data_offset = section.offset;
-                    }
-                    _ => {}
-                }
+#[derive(Clone)]
+pub enum ShapeNodeKind {
+    Mesh(Shape),
+    StaticCompound(Vec<ShapeInstance>),
+    /// Child nodes, which all share the transform of the list
+    List(Vec<ShapeListChild>),
+}
+
+#[derive(Clone)]
+pub struct ShapeInstance {
+    pub transform: hkQsTransform,
+    pub node: usize,
+    pub collision: ShapeCollisionInfo,
+}
+
+#[derive(Clone)]
+pub struct ShapeListChild {
+    pub node: usize,
+    pub collision: ShapeCollisionInfo,
+}
+
+/// Collision filtering and material of a compound shape instance or list shape child
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShapeCollisionInfo {
+    pub filter_info: CollisionFilterInfo,
+    /// Bits of the filter info that child shapes may override, always 0 for list children
+    pub child_filter_info_mask: u32,
+    pub material_id: u64,
+}
 
-                f.seek(SeekFrom::Start(section.offset + section.size as u64))?;
+/// A mesh reachable from a node, along with the accumulated transform of all instances leading to it
+#[derive(Clone)]
+pub struct ShapeLeaf {
+    pub node: usize,
+    pub transform: Mat4,
+    /// Node indices from the starting node down to (and including) the leaf
+    pub path: Vec<usize>,
+    /// Collision info of the innermost instance or list child along the path, `None` if the leaf is a root
+    pub collision: Option<ShapeCollisionInfo>,
+}
+
+impl ShapeHierarchy {
+    /// Reads the shape collection (`s_hkpShape_array`) of an already parsed tagfile
+    pub fn read_collection(
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+    ) -> anyhow::Result<Self> {
+        let shape_array = tagfile
+            .find_item("s_hkpShape_array")
+            .ok_or(HavokError::MissingItem("s_hkpShape_array"))?;
+        let shape_array_item = tagfile.item(shape_array).unwrap();
+
+        f.seek(SeekFrom::Start(shape_array_item.offset as u64))?;
+        let parent: UnkShapeArrayParent = f.read_type(tagfile.endian)?;
+        let shape_indices = parent
+            .shapes
+            .read(tagfile, f)
+            .context("Failed to read shape array")?;
+
+        let mut hierarchy = ShapeHierarchy::default();
+        for s in shape_indices {
+            let shape = resolve_required(tagfile, &s.shape, "Shape array entry")?;
+            let node = hierarchy.read_node(tagfile, f, shape)?;
+            hierarchy.roots.push(node);
+        }
+
+        Ok(hierarchy)
+    }
+
+    pub fn node(&self, index: usize) -> &ShapeNode {
+        &self.nodes[index]
+    }
+
+    /// Returns every mesh reachable from the given node
+    pub fn leaves(&self, node: usize) -> Vec<ShapeLeaf> {
+        let mut leaves = vec![];
+        self.collect_leaves(node, Mat4::IDENTITY, None, &mut vec![], &mut leaves);
+        leaves
+    }
+
+    fn collect_leaves(
+        &self,
+        node: usize,
+        transform: Mat4,
+        collision: Option<ShapeCollisionInfo>,
+        path: &mut Vec<usize>,
+        leaves: &mut Vec<ShapeLeaf>,
+    ) {
+        path.push(node);
+        match &self.nodes[node].kind {
+            ShapeNodeKind::Mesh(_) => leaves.push(ShapeLeaf {
+                node,
+                transform,
+                path: path.clone(),
+                collision,
+            }),
+            ShapeNodeKind::StaticCompound(instances) => {
+                for instance in instances {
+                    self.collect_leaves(
+                        instance.node,
+                        transform * instance.transform.to_mat4(),
+                        Some(instance.collision),
+                        path,
+                        leaves,
+                    );
+                }
             }
-            Err(e) => {
-                anyhow::bail!("Failed to read section: {}", e);
+            ShapeNodeKind::List(children) => {
+                for child in children {
+                    self.collect_leaves(child.node, transform, Some(child.collision), path, leaves);
+                }
             }
         }
+        path.pop();
     }
 
-    Err(anyhow::anyhow!(
-        "No shape collections found in the given havok file"
-    ))
-}
+    /// Merges every mesh reachable from the given node into a single shape
+    pub fn flatten(&self, node: usize) -> Shape {
+        let mut shape = Shape::default();
+        for leaf in self.leaves(node) {
+            if let ShapeNodeKind::Mesh(mesh) = &self.nodes[leaf.node].kind {
+                let mut s = mesh.clone();
+                s.apply_transform(leaf.transform);
+                shape.combine(&s);
+            }
+        }
 
-pub fn read_shape(
-    items: &[IndexItem],
-    f: &mut (impl Read + Seek),
-    item: hkPointerIndex,
-    endian: Endian,
-) -> anyhow::Result<Shape> {
-    let item = items
-        .get(item as usize)
-        .context("Shape references invalid index")?;
+        shape
+    }
 
-    match item.typ {
-        // Some alternative kind of static compound shape?
-        0x81 => {
-            f.seek(SeekFrom::Start(item.offset as u64))?;
+    /// Reads the shape at the given item, returning the index of its node.
+    ///
+    /// Fails with [`HavokError::CycleDetected`] if a shape contains itself, or [`HavokError::DepthLimitExceeded`]
+    /// if shapes are nested more than [`MAX_SHAPE_DEPTH`] levels deep.
+    pub fn read_node(
+        &mut self,
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+        item_index: u64,
+    ) -> anyhow::Result<usize> {
+        self.read_node_nested(tagfile, f, item_index, &mut vec![])
+    }
+
+    /// `parents` holds the items currently being read, from the outermost shape inwards
+    fn read_node_nested(
+        &mut self,
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+        item_index: u64,
+        parents: &mut Vec<u64>,
+    ) -> anyhow::Result<usize> {
+        if let Some(&node) = self.item_nodes.get(&item_index) {
+            return Ok(node);
+        }
+
+        if parents.contains(&item_index) {
+            return Err(HavokError::CycleDetected(item_index).into());
+        }
+
+        if parents.len() >= MAX_SHAPE_DEPTH {
+            return Err(HavokError::DepthLimitExceeded {
+                item: item_index,
+                limit: MAX_SHAPE_DEPTH,
+            }
+            .into());
+        }
+
+        let endian = tagfile.endian;
+        let type_name = tagfile.item_type_name(item_index);
+        let item = tagfile.require_item(item_index, "Shape")?;
+
+        parents.push(item_index);
+
+        let kind = match type_name {
+            // Unk81 is the fallback name for Destiny 2 files without type information
+            Some("hkpListShape" | "Unk81") => {
+                f.seek(SeekFrom::Start(item.offset as u64))?;
 
-            let unk81: Unk81 = f.read_type(endian)?;
+                let unk81: Unk81 = f.read_type(endian)?;
 
-            let unk84_item = items
-                .get(unk81.unk38 as usize)
-                .context("unk81 references invalid index")?;
+                let unk84: Vec<Unk84> = unk81
+                    .unk38
+                    .read(tagfile, f)
+                    .context("Failed to read list shape children array")?;
 
-            let unk84: Vec<Unk84> =
-                f.save_pos_seek(SeekFrom::Start(unk84_item.offset as u64), |f| {
-                    f.read_type_args(
-                        endian,
-                        VecArgs {
-                            count: unk84_item.count as _,
-                            inner: (),
+                let mut children = vec![];
+                for v in unk84 {
+                    let child = resolve_required(tagfile, &v.shape, "List shape child")?;
+                    children.push(ShapeListChild {
+                        node: self.read_node_nested(tagfile, f, child, parents)?,
+                        collision: ShapeCollisionInfo {
+                            filter_info: v.collision_filter_info,
+                            child_filter_info_mask: 0,
+                            material_id: v.shape_info as u64,
                         },
-                    )
-                    .context("Failed to read compound shape instances array")
-                })?;
+                    });
+                }
 
-            let mut shape = Shape::default();
-            for v in unk84 {
-                let s = read_shape(items, f, v.shape, endian)?;
-                shape.combine(&s);
+                ShapeNodeKind::List(children)
             }
+            Some("hkpStaticCompoundShape") => {
+                f.seek(SeekFrom::Start(item.offset as u64))?;
 
-            Ok(shape)
-        }
-        0x88 => {
+                let compound_shape: hkpStaticCompoundShape = f.read_type(endian)?;
+
+                let instances: Vec<hkpStaticCompoundShapeInstance> = compound_shape
+                    .instances
+                    .read(tagfile, f)
+                    .context("Failed to read compound shape instances array")?;
+
+                let mut children = vec![];
+                for instance in instances {
+                    let child =
+                        resolve_required(tagfile, &instance.shape, "Compound shape instance")?;
+                    children.push(ShapeInstance {
+                        node: self.read_node_nested(tagfile, f, child, parents)?,
+                        transform: instance.transform,
+                        collision: ShapeCollisionInfo {
+                            filter_info: instance.filter_info,
+                            child_filter_info_mask: instance.child_filter_info_mask,
+                            material_id: instance.user_data,
+                        },
+                    });
+                }
+
+                ShapeNodeKind::StaticCompound(children)
+            }
+            _ => ShapeNodeKind::Mesh(read_mesh(tagfile, f, item_index)?),
+        };
+        parents.pop();
+
+        let node = self.nodes.len();
+        self.nodes.push(ShapeNode {
+            item: item_index,
+            class_name: type_name
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("0x{:x}", item.typ)),
+            kind,
+        });
+        self.item_nodes.insert(item_index, node);
+
+        Ok(node)
+    }
+}
+
+/// Reads a shape that contains geometry of its own
+fn read_mesh(
+    tagfile: &HavokTagFile,
+    f: &mut (impl Read + Seek),
+    item_index: u64,
+) -> anyhow::Result<Shape> {
+    let endian = tagfile.endian;
+    let type_name = tagfile.item_type_name(item_index);
+    let item = tagfile.require_item(item_index, "Shape")?;
+
+    match type_name {
+        Some("hkpConvexVerticesShape") => {
             f.seek(SeekFrom::Start(item.offset as u64))?;
 
             let convex_shape: hkpConvexVerticesShape = f.read_type(endian)?;
 
-            let vertices_item = items
-                .get(convex_shape.rotated_vertices as usize)
-                .context("convex shape references invalid index")?;
-
-            let vertices: Vec<hkFourTransposedPoints> =
-                f.save_pos_seek(SeekFrom::Start(vertices_item.offset as u64), |f| {
-                    f.read_type_args(
-                        endian,
-                        VecArgs {
-                            count: vertices_item.count as _,
-                            inner: (),
-                        },
-                    )
-                    .context("Failed to read compound shape instances array")
-                })?;
+            let vertices: Vec<hkFourTransposedPoints> = convex_shape
+                .rotated_vertices
+                .read(tagfile, f)
+                .context("Failed to read convex vertices array")?;
 
-            let vertices_corrected = vertices
+            let mut vertices_corrected = vertices
                 .iter()
                 .flat_map(|v| v.transpose())
                 .collect::<Vec<_>>();
 
+            // Drop the padding of the last block
+            if convex_shape.num_vertices > 0 {
+                vertices_corrected.truncate(convex_shape.num_vertices as usize);
+            }
+
+            let connectivity: Option<hkpConvexVerticesConnectivity> = convex_shape
+                .connectivity
+                .read(tagfile, f)
+                .context("Failed to read convex connectivity")?;
+            if let Some(connectivity) = connectivity {
+                let planes: Vec<Vec4> = convex_shape
+                    .plane_equations
+                    .read(tagfile, f)
+                    .context("Failed to read convex plane equations")?
+                    .into_iter()
+                    .map(Vec4::from_array)
+                    .collect();
+
+                let vertex_indices = connectivity
+                    .vertex_indices
+                    .read(tagfile, f)
+                    .context("Failed to read convex connectivity vertex indices")?;
+                let num_vertices_per_face = connectivity
+                    .num_vertices_per_face
+                    .read(tagfile, f)
+                    .context("Failed to read convex connectivity face sizes")?;
+
+                if !num_vertices_per_face.is_empty() {
+                    return triangulate_convex_faces(
+                        vertices_corrected,
+                        &planes,
+                        &vertex_indices,
+                        &num_vertices_per_face,
+                    );
+                }
+            }
+
+            // No authored faces, reconstruct them from the vertices
             let points_na: Vec<Point3<f32>> = vertices_corrected
                 .iter()
                 .map(|v| v.to_array().into())
@@ -240,72 +485,656 @@ pub fn read_shape(
                     .into_iter()
                     .map(|v| Vec3::from_array(v.into()))
                     .collect(),
-                indices: indices.into_iter().flatten().map(|i| i as u16).collect(),
+                indices: indices.into_iter().flatten().collect(),
             };
 
             Ok(shape)
         }
-        0xaf => {
+        Some("hkpBvCompressedMeshShape") => {
             f.seek(SeekFrom::Start(item.offset as u64))?;
 
-            let compound_shape: hkpStaticCompoundShape = f.read_type(endian)?;
+            let mesh_shape: hkpBvCompressedMeshShape = f.read_type(endian)?;
+            read_compressed_mesh(tagfile, f, &mesh_shape.tree)
+        }
+        Some("hkpBoxShape") => {
+            f.seek(SeekFrom::Start(item.offset as u64))?;
 
-            let instances_item = items
-                .get(compound_shape.instances as usize)
-                .context("compound shape references invalid index")?;
+            let box_shape: hkpBoxShape = f.read_type(endian)?;
+            Ok(Shape {
+                vertices: box_shape.vertices().to_vec(),
+                indices: BOX_INDICES.to_vec(),
+            })
+        }
+        Some(
+            "hkpCompressedSampledHeightFieldShape"
+            | "hkpStorageSampledHeightFieldShape"
+            | "hkpTriSampledHeightFieldCollection"
+            | "hkpTriSampledHeightFieldBvTreeShape",
+        ) => Ok(read_heightfield(tagfile, f, item_index)?.to_shape()),
+        name => Err(HavokError::UnhandledType {
+            context: "shape",
+            name: name
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("0x{:x}", item.typ)),
+        }
+        .into()),
+    }
+}
 
-            let instances: Vec<hkpStaticCompoundShapeInstance> =
-                f.save_pos_seek(SeekFrom::Start(instances_item.offset as u64), |f| {
-                    f.read_type_args(
-                        endian,
-                        VecArgs {
-                            count: instances_item.count as _,
-                            inner: (),
-                        },
-                    )
-                    .context("Failed to read compound shape instances array")
-                })?;
+/// Reads the samples of a heightfield shape, following the tri-sampled wrappers down to the heightfield itself
+pub fn read_heightfield(
+    tagfile: &HavokTagFile,
+    f: &mut (impl Read + Seek),
+    item_index: u64,
+) -> anyhow::Result<HeightField> {
+    let endian = tagfile.endian;
 
-            let mut shape = Shape::default();
-            for instance in instances {
-                let mut s = read_shape(items, f, instance.shape, endian)?;
-                s.apply_transform(instance.transform.to_mat4());
-                shape.combine(&s);
+    // Items of the tri-sampled wrappers walked through so far
+    let mut wrappers = vec![];
+    let mut item_index = item_index;
+    let (base, heights, triangle_flip) = loop {
+        if wrappers.contains(&item_index) {
+            return Err(HavokError::CycleDetected(item_index).into());
+        }
+
+        let type_name = tagfile.item_type_name(item_index);
+        let item = tagfile.require_item(item_index, "Heightfield")?;
+
+        f.seek(SeekFrom::Start(item.offset as u64))?;
+        match type_name {
+            Some("hkpTriSampledHeightFieldBvTreeShape") => {
+                let bvtree: hkpTriSampledHeightFieldBvTreeShape = f.read_type(endian)?;
+                wrappers.push(item_index);
+                item_index = resolve_required(tagfile, &bvtree.child, "Heightfield BV tree")?;
+            }
+            Some("hkpTriSampledHeightFieldCollection") => {
+                let collection: hkpTriSampledHeightFieldCollection = f.read_type(endian)?;
+                wrappers.push(item_index);
+                item_index =
+                    resolve_required(tagfile, &collection.heightfield, "Heightfield collection")?;
             }
+            Some("hkpCompressedSampledHeightFieldShape") => {
+                let heightfield: hkpCompressedSampledHeightFieldShape = f.read_type(endian)?;
+                let storage = heightfield
+                    .storage
+                    .read(tagfile, f)
+                    .context("Failed to read compressed heightfield samples")?;
 
-            Ok(shape)
+                let heights = storage
+                    .into_iter()
+                    .map(|v| v as f32 * heightfield.scale + heightfield.offset)
+                    .collect::<Vec<_>>();
+
+                break (heightfield.base, heights, heightfield.triangle_flip != 0);
+            }
+            Some("hkpStorageSampledHeightFieldShape") => {
+                let heightfield: hkpStorageSampledHeightFieldShape = f.read_type(endian)?;
+                let heights = heightfield
+                    .storage
+                    .read(tagfile, f)
+                    .context("Failed to read heightfield samples")?;
+
+                break (heightfield.base, heights, heightfield.triangle_flip != 0);
+            }
+            name => {
+                return Err(HavokError::UnhandledType {
+                    context: "heightfield",
+                    name: name
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("0x{:x}", item.typ)),
+                }
+                .into())
+            }
         }
-        u => anyhow::bail!("read_shape: Unhandled shape type 0x{u:x}"),
+    };
+
+    let x_res = base.x_res.max(0) as usize;
+    let z_res = base.z_res.max(0) as usize;
+    if heights.len() < x_res.saturating_mul(z_res) {
+        return Err(HavokError::CountMismatch {
+            context: "Heightfield samples",
+            expected: x_res.saturating_mul(z_res) as u64,
+            actual: heights.len() as u64,
+        }
+        .into());
     }
+
+    Ok(HeightField {
+        x_res,
+        z_res,
+        heights,
+        scale: base.int_to_float_scale.truncate(),
+        triangle_flip,
+    })
 }
 
-trait SeekSaveExt: Seek {
-    fn save_pos<F, T>(&mut self, inner: F) -> anyhow::Result<T>
-    where
-        F: FnOnce(&mut Self) -> anyhow::Result<T>,
-    {
-        let pos = self.stream_position()?;
+/// Turns the faces of a convex shape into triangle fans, wound so they face along the plane of their face
+fn triangulate_convex_faces(
+    vertices: Vec<Vec3>,
+    planes: &[Vec4],
+    vertex_indices: &[u16],
+    num_vertices_per_face: &[u8],
+) -> anyhow::Result<Shape> {
+    let faces = hkpConvexVerticesConnectivity::faces(vertex_indices, num_vertices_per_face).ok_or(
+        HavokError::CountMismatch {
+            context: "Convex connectivity vertex indices",
+            expected: num_vertices_per_face.iter().map(|&c| c as u64).sum(),
+            actual: vertex_indices.len() as u64,
+        },
+    )?;
+
+    let mut indices = vec![];
+    for (face_index, face) in faces.into_iter().enumerate() {
+        if let Some(&i) = face.iter().find(|&&i| i as usize >= vertices.len()) {
+            return Err(HavokError::InvalidIndex {
+                context: "Convex connectivity face",
+                index: i as u64,
+            }
+            .into());
+        }
 
-        let r = inner(self)?;
+        let Some((&first, rest)) = face.split_first() else {
+            continue;
+        };
 
-        self.seek(SeekFrom::Start(pos))?;
+        let mut face_indices = vec![];
+        for pair in rest.windows(2) {
+            face_indices.extend([first as u32, pair[0] as u32, pair[1] as u32]);
+        }
+
+        // Keep the stored winding if there is no plane to compare against
+        if let Some(plane) = planes.get(face_index) {
+            let face_normal = face_indices
+                .chunks_exact(3)
+                .map(|t| {
+                    let [a, b, c] = [t[0], t[1], t[2]].map(|i| vertices[i as usize]);
+                    (b - a).cross(c - a)
+                })
+                .sum::<Vec3>();
+
+            if face_normal.dot(plane.truncate()) < 0.0 {
+                for t in face_indices.chunks_exact_mut(3) {
+                    t.swap(1, 2);
+                }
+            }
+        }
 
-        Ok(r)
+        indices.extend(face_indices);
     }
 
-    fn save_pos_seek<F, T>(&mut self, seek: SeekFrom, inner: F) -> anyhow::Result<T>
-    where
-        F: FnOnce(&mut Self) -> anyhow::Result<T>,
-    {
-        let pos = self.stream_position()?;
+    Ok(Shape { vertices, indices })
+}
 
-        self.seek(seek)?;
-        let r = inner(self)?;
+fn read_compressed_mesh(
+    tagfile: &HavokTagFile,
+    f: &mut (impl Read + Seek),
+    tree: &hkpBvCompressedMeshShapeTree,
+) -> anyhow::Result<Shape> {
+    let sections: Vec<hkcdStaticMeshTreeBaseSection> = tree
+        .sections
+        .read(tagfile, f)
+        .context("Failed to read compressed mesh sections")?;
+    let primitives: Vec<hkcdStaticMeshTreeBasePrimitive> = tree
+        .primitives
+        .read(tagfile, f)
+        .context("Failed to read compressed mesh primitives")?;
+    let shared_vertices_index: Vec<u16> = tree
+        .shared_vertices_index
+        .read(tagfile, f)
+        .context("Failed to read compressed mesh shared vertex indices")?;
+    let packed_vertices: Vec<u32> = tree
+        .packed_vertices
+        .read(tagfile, f)
+        .context("Failed to read compressed mesh packed vertices")?;
+    let shared_vertices: Vec<u64> = tree
+        .shared_vertices
+        .read(tagfile, f)
+        .context("Failed to read compressed mesh shared vertices")?;
 
-        self.seek(SeekFrom::Start(pos))?;
+    let mut shape = Shape::default();
+    // Shared vertices are referenced by multiple sections, only emit them once
+    let mut shared_vertex_map: HashMap<u16, u32> = HashMap::new();
+    for section in &sections {
+        let packed_base = shape.vertices.len();
+        let first_packed = section.first_packed_vertex as usize;
+        for i in 0..section.num_packed_vertices as usize {
+            let v = packed_vertices
+                .get(first_packed + i)
+                .ok_or(HavokError::InvalidIndex {
+                    context: "Compressed mesh section packed vertex",
+                    index: (first_packed + i) as u64,
+                })?;
+            shape.vertices.push(section.decompress_packed_vertex(*v));
+        }
 
-        Ok(r)
+        let mut local_index = |i: u8, shape: &mut Shape| -> anyhow::Result<u32> {
+            if i < section.num_packed_vertices {
+                return Ok((packed_base + i as usize) as u32);
+            }
+
+            let shared_index_offset =
+                section.shared_vertex_offset() + (i - section.num_packed_vertices) as usize;
+            let shared_index = *shared_vertices_index.get(shared_index_offset).ok_or(
+                HavokError::InvalidIndex {
+                    context: "Compressed mesh section shared vertex index",
+                    index: shared_index_offset as u64,
+                },
+            )?;
+
+            if let Some(&index) = shared_vertex_map.get(&shared_index) {
+                return Ok(index);
+            }
+
+            let v = shared_vertices
+                .get(shared_index as usize)
+                .ok_or(HavokError::InvalidIndex {
+                    context: "Compressed mesh shared vertex",
+                    index: shared_index as u64,
+                })?;
+            let index = shape.vertices.len() as u32;
+            shape.vertices.push(tree.decompress_shared_vertex(*v));
+            shared_vertex_map.insert(shared_index, index);
+
+            Ok(index)
+        };
+
+        for primitive_index in section.primitive_range() {
+            let primitive = primitives
+                .get(primitive_index)
+                .ok_or(HavokError::InvalidIndex {
+                    context: "Compressed mesh section primitive",
+                    index: primitive_index as u64,
+                })?;
+
+            for triangle in primitive.triangles() {
+                for i in triangle {
+                    let index = local_index(i, &mut shape)?;
+                    shape.indices.push(index);
+                }
+            }
+        }
     }
+
+    Ok(shape)
 }
 
-impl<T: Read + Seek> SeekSaveExt for T {}
+/// Item referenced by a pointer that must not be null
+fn resolve_required(
+    tagfile: &HavokTagFile,
+    pointer: &hkPointer,
+    context: &'static str,
+) -> Result<u64, HavokError> {
+    pointer.resolve(tagfile)?.ok_or(HavokError::InvalidIndex {
+        context,
+        index: pointer.index,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::writer::{ItemData, TagFileWriter};
+
+    fn add_box(writer: &mut TagFileWriter, half_extents: Vec3) -> u64 {
+        let mut data = ItemData::new();
+        data.zeros(40)
+            .f32(0.05)
+            .u32(0)
+            .vec4(half_extents.extend(0.0));
+        writer.add_item("hkpBoxShape", &data).unwrap()
+    }
+
+    fn transform(translation: Vec3, rotation: glam::Quat, scale: Vec3) -> hkQsTransform {
+        hkQsTransform {
+            translation: translation.extend(0.0),
+            rotation: Vec4::from(rotation),
+            scale: scale.extend(0.0),
+        }
+    }
+
+    /// Adds a `hkpStaticCompoundShape` with `(shape, transform, filter info, material)` instances
+    fn add_compound(
+        writer: &mut TagFileWriter,
+        instances: &[(u64, hkQsTransform, u32, u64)],
+    ) -> u64 {
+        let mut data = ItemData::new();
+        for &(shape, transform, filter_info, material) in instances {
+            data.vec4(transform.translation)
+                .vec4(transform.rotation)
+                .vec4(transform.scale)
+                .pointer(Some(shape))
+                .u32(filter_info)
+                .u32(0xffff)
+                .u64(material)
+                .u64(0);
+        }
+        let instances_item = writer
+            .add_array("hkpStaticCompoundShapeInstance", instances.len(), &data)
+            .unwrap();
+
+        let mut compound = ItemData::new();
+        compound
+            .zeros(40)
+            .u64(2)
+            .zeros(16)
+            .array(instances_item, instances.len())
+            .zeros(32)
+            .array(None, 0)
+            .zeros(32);
+        writer
+            .add_item("hkpStaticCompoundShape", &compound)
+            .unwrap()
+    }
+
+    /// Adds a list shape (`Unk81`) with `(shape, filter info, material)` children
+    fn add_list(writer: &mut TagFileWriter, children: &[(u64, u32, u16)]) -> u64 {
+        let mut data = ItemData::new();
+        for &(shape, filter_info, material) in children {
+            data.pointer(Some(shape))
+                .u32(filter_info)
+                .u16(material)
+                .u16(0)
+                .i32(1)
+                .zeros(12);
+        }
+        let children_item = writer
+            .add_array("hkpListShape::ChildInfo", children.len(), &data)
+            .unwrap();
+
+        let mut list = ItemData::new();
+        list.zeros(56)
+            .array(children_item, children.len())
+            .zeros(8 + 32 + 32);
+        writer.add_item("Unk81", &list).unwrap()
+    }
+
+    /// Adds the `s_hkpShape_array` root and writes the file
+    fn write_collection(writer: &mut TagFileWriter, shapes: &[u64]) -> Vec<u8> {
+        let mut entries = ItemData::new();
+        for &shape in shapes {
+            entries.pointer(Some(shape));
+        }
+        let entries = writer
+            .add_array("s_hkpShape_array_data", shapes.len(), &entries)
+            .unwrap();
+
+        let mut collection = ItemData::new();
+        collection.array(entries, shapes.len());
+        writer.add_item("s_hkpShape_array", &collection).unwrap();
+
+        let mut data = vec![];
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn shapes_without_type_section() {
+        let mut writer = TagFileWriter::new();
+        writer.compendium_id = Some(0x1234_5678_9abc_def0);
+        let shape = add_box(&mut writer, Vec3::new(1.0, 2.0, 3.0));
+        let data = write_collection(&mut writer, &[shape]);
+
+        // The fallback registry doesn't know the types of this file
+        let err = read_shape_collection(&mut Cursor::new(&data), None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(HavokError::MissingItem("s_hkpShape_array"))
+        ));
+
+        let other = TypeCompendium {
+            ids: vec![1],
+            types: writer.types().clone(),
+        };
+        assert!(read_shape_collection(&mut Cursor::new(&data), Some(&other)).is_err());
+
+        let compendium = TypeCompendium {
+            ids: vec![0x1234_5678_9abc_def0],
+            types: writer.types().clone(),
+        };
+        let hierarchy = read_shape_hierarchy(&mut Cursor::new(&data), Some(&compendium)).unwrap();
+        assert_eq!(hierarchy.roots.len(), 1);
+
+        let node = hierarchy.node(hierarchy.roots[0]);
+        assert_eq!(node.class_name, "hkpBoxShape");
+
+        let mesh = node.mesh().unwrap();
+        assert_eq!(mesh.indices, BOX_INDICES);
+        assert_eq!(
+            mesh.min_max(),
+            (Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0))
+        );
+    }
+
+    #[test]
+    fn compound_hierarchy() {
+        let mut writer = TagFileWriter::new();
+        let unit_box = add_box(&mut writer, Vec3::ONE);
+        let inner_transform = transform(Vec3::new(0.0, 10.0, 0.0), glam::Quat::IDENTITY, Vec3::ONE);
+        let inner = add_compound(&mut writer, &[(unit_box, inner_transform, 0, 0)]);
+
+        let first = transform(Vec3::new(5.0, 0.0, 0.0), glam::Quat::IDENTITY, Vec3::ONE);
+        let second = transform(
+            Vec3::new(0.0, 0.0, -5.0),
+            glam::Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+            Vec3::splat(2.0),
+        );
+        let outer = add_compound(
+            &mut writer,
+            &[(unit_box, first, 0, 0), (inner, second, 0, 0)],
+        );
+        let data = write_collection(&mut writer, &[outer]);
+
+        let hierarchy = read_shape_hierarchy(&mut Cursor::new(&data), None).unwrap();
+        let root = hierarchy.roots[0];
+        let ShapeNodeKind::StaticCompound(instances) = &hierarchy.node(root).kind else {
+            panic!("Expected a static compound");
+        };
+        assert_eq!(instances.len(), 2);
+        assert_eq!(
+            instances[1].transform.to_mat4(),
+            second.to_mat4(),
+            "Instance transforms are kept as stored"
+        );
+
+        // The box is shared between both paths, but only read once
+        let box_node = hierarchy.item_nodes[&unit_box];
+        let inner_node = hierarchy.item_nodes[&inner];
+        assert_eq!(instances[0].node, box_node);
+        assert_eq!(instances[1].node, inner_node);
+        assert_eq!(hierarchy.nodes.len(), 3);
+
+        let leaves = hierarchy.leaves(root);
+        assert_eq!(leaves.len(), 2);
+        assert_eq!(leaves[0].path, [root, box_node]);
+        assert_eq!(leaves[0].transform, first.to_mat4());
+        assert_eq!(leaves[1].path, [root, inner_node, box_node]);
+        assert_eq!(
+            leaves[1].transform,
+            second.to_mat4() * inner_transform.to_mat4()
+        );
+
+        let flattened = hierarchy.flatten(root);
+        assert_eq!(flattened.vertices.len(), 16);
+        assert_eq!(flattened.indices.len(), BOX_INDICES.len() * 2);
+
+        // Second instance: offset 10 up in the inner compound, then rotated a quarter turn, scaled and moved
+        let (min, max) = Shape {
+            vertices: flattened.vertices[8..].to_vec(),
+            indices: vec![],
+        }
+        .min_max();
+        assert!(min.abs_diff_eq(Vec3::new(-22.0, -2.0, -7.0), 1e-4), "{min}");
+        assert!(max.abs_diff_eq(Vec3::new(-18.0, 2.0, -3.0), 1e-4), "{max}");
+    }
+
+    /// Unit square in the XY plane, counter-clockwise when seen from +Z
+    const SQUARE: [Vec3; 4] = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ];
+
+    #[test]
+    fn convex_quad_fan() {
+        let up = Vec4::new(0.0, 0.0, 1.0, 0.0);
+        let shape = triangulate_convex_faces(SQUARE.to_vec(), &[up], &[0, 1, 2, 3], &[4]).unwrap();
+        assert_eq!(shape.vertices, SQUARE);
+        assert_eq!(shape.indices, [0, 1, 2, 0, 2, 3]);
+
+        // Triangles are flipped to face along the plane normal
+        let shape = triangulate_convex_faces(SQUARE.to_vec(), &[-up], &[0, 1, 2, 3], &[4]).unwrap();
+        assert_eq!(shape.indices, [0, 2, 1, 0, 3, 2]);
+
+        // Without a plane, the stored winding is kept
+        let shape = triangulate_convex_faces(SQUARE.to_vec(), &[], &[3, 2, 1, 0], &[4]).unwrap();
+        assert_eq!(shape.indices, [3, 2, 1, 3, 1, 0]);
+    }
+
+    #[test]
+    fn convex_pentagon_fan() {
+        let vertices = (0..5)
+            .map(|i| {
+                let angle = i as f32 / 5.0 * std::f32::consts::TAU;
+                Vec3::new(angle.cos(), angle.sin(), 0.0)
+            })
+            .collect::<Vec<_>>();
+        // A triangle and the pentagon, with the pentagon's plane listed second
+        let planes = [Vec4::Z, Vec4::Z];
+
+        let shape = triangulate_convex_faces(vertices, &planes, &[0, 1, 2, 0, 1, 2, 3, 4], &[3, 5])
+            .unwrap();
+        assert_eq!(shape.indices, [0, 1, 2, 0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn convex_face_errors() {
+        let invalid_vertex =
+            triangulate_convex_faces(SQUARE.to_vec(), &[], &[0, 1, 7], &[3]).unwrap_err();
+        assert!(matches!(
+            invalid_vertex.downcast_ref(),
+            Some(HavokError::InvalidIndex { index: 7, .. })
+        ));
+
+        // Two triangles, but only four indices
+        let mismatch =
+            triangulate_convex_faces(SQUARE.to_vec(), &[], &[0, 1, 2, 3], &[3, 3]).unwrap_err();
+        assert!(matches!(
+            mismatch.downcast_ref(),
+            Some(HavokError::CountMismatch {
+                expected: 6,
+                actual: 4,
+                ..
+            })
+        ));
+    }
+
+    fn read_error(data: &[u8]) -> HavokError {
+        read_shape_hierarchy(&mut Cursor::new(data), None)
+            .err()
+            .and_then(|e| e.downcast::<HavokError>().ok())
+            .expect("Expected a havok error")
+    }
+
+    #[test]
+    fn self_referencing_shape() {
+        let mut writer = TagFileWriter::new();
+        // The instance array is added right before the compound itself
+        let compound = writer.next_index() + 1;
+        let identity = transform(Vec3::ZERO, glam::Quat::IDENTITY, Vec3::ONE);
+        assert_eq!(
+            add_compound(&mut writer, &[(compound, identity, 0, 0)]),
+            compound
+        );
+        let data = write_collection(&mut writer, &[compound]);
+
+        assert!(matches!(
+            read_error(&data),
+            HavokError::CycleDetected(item) if item == compound
+        ));
+    }
+
+    #[test]
+    fn shape_depth_limit() {
+        let identity = transform(Vec3::ZERO, glam::Quat::IDENTITY, Vec3::ONE);
+        let nested = |depth: usize| {
+            let mut writer = TagFileWriter::new();
+            let mut shape = add_box(&mut writer, Vec3::ONE);
+            for _ in 0..depth {
+                shape = add_compound(&mut writer, &[(shape, identity, 0, 0)]);
+            }
+            write_collection(&mut writer, &[shape])
+        };
+
+        // The box sits below `depth` compounds
+        let hierarchy =
+            read_shape_hierarchy(&mut Cursor::new(nested(MAX_SHAPE_DEPTH - 1)), None).unwrap();
+        assert_eq!(hierarchy.leaves(hierarchy.roots[0]).len(), 1);
+
+        assert!(matches!(
+            read_error(&nested(MAX_SHAPE_DEPTH)),
+            HavokError::DepthLimitExceeded {
+                limit: MAX_SHAPE_DEPTH,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn collision_info() {
+        let mut writer = TagFileWriter::new();
+        let unit_box = add_box(&mut writer, Vec3::ONE);
+        let list = add_list(
+            &mut writer,
+            &[(unit_box, 0x0012_1c23, 42), (unit_box, 5, 0)],
+        );
+        let identity = transform(Vec3::ZERO, glam::Quat::IDENTITY, Vec3::ONE);
+        let compound = add_compound(
+            &mut writer,
+            &[
+                (unit_box, identity, 0x0003_0001, 0x1234_5678_9abc),
+                (list, identity, 0, 0),
+            ],
+        );
+        let data = write_collection(&mut writer, &[compound, list]);
+
+        let hierarchy = read_shape_hierarchy(&mut Cursor::new(&data), None).unwrap();
+        let ShapeNodeKind::List(children) = &hierarchy.node(hierarchy.roots[1]).kind else {
+            panic!("Expected a list shape");
+        };
+        assert_eq!(children.len(), 2);
+        assert!(children
+            .iter()
+            .all(|c| c.node == hierarchy.item_nodes[&unit_box]));
+
+        let collision = children[0].collision;
+        assert_eq!(collision.filter_info.layer(), 3);
+        assert_eq!(collision.filter_info.system_group(), 0x12);
+        assert_eq!(collision.material_id, 42);
+        assert_eq!(collision.child_filter_info_mask, 0);
+
+        // Leaves carry the collision info of the innermost instance or child
+        let leaves = hierarchy.leaves(hierarchy.roots[0]);
+        let collisions = leaves
+            .iter()
+            .map(|l| l.collision.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(collisions.len(), 3);
+        assert_eq!(
+            collisions[0],
+            ShapeCollisionInfo {
+                filter_info: CollisionFilterInfo(0x0003_0001),
+                child_filter_info_mask: 0xffff,
+                material_id: 0x1234_5678_9abc,
+            }
+        );
+        assert_eq!(collisions[1].material_id, 42);
+        assert_eq!(collisions[2].filter_info, CollisionFilterInfo(5));
+
+        // Without an instance above it, a leaf has no collision info
+        let box_node = hierarchy.item_nodes[&unit_box];
+        assert!(hierarchy.leaves(box_node)[0].collision.is_none());
+    }
+}
@@ -0,0 +1,437 @@
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use anyhow::Context;
+use binrw::{BinReaderExt, Endian, VecArgs};
+
+use crate::{
+    error::HavokError,
+    index::IndexItem,
+    section::{TagSection, TagSectionSignature},
+    type_registry::{TypeCompendium, TypeRegistry},
+};
+
+/// Pointer size used when the type layouts don't say otherwise. Destiny files always use 64-bit pointers
+pub const DEFAULT_POINTER_SIZE: u64 = 8;
+
+/// Section tree, item index and type information of a havok tagfile
+pub struct HavokTagFile {
+    /// Sections in the order they appear, excluding the contents of the TYPE section
+    pub sections: Vec<TagSection>,
+
+    pub sdk_version: Option<String>,
+    pub endian: Endian,
+
+    /// Offset and size of the DATA section
+    pub data_offset: u64,
+    pub data_size: usize,
+
+    /// Index items. Offsets are absolute, the first item is always the null item
+    pub items: Vec<IndexItem>,
+
+    pub types: TypeRegistry,
+
+    /// Compendium ID from the TCRF section, if the file doesn't carry its own types
+    pub compendium_id: Option<u64>,
+
+    /// Pointer patch tables from the PTCH section
+    pub patches: Vec<PatchTable>,
+
+    /// Item referenced by every patched pointer, keyed by the absolute offset of the pointer
+    pub pointers: BTreeMap<u64, u64>,
+
+    /// Size of pointer fields in bytes
+    pub pointer_size: u64,
+
+    /// Sorted, deduplicated absolute offsets of all items, used to find where an item ends without type layouts
+    item_offsets: Vec<u64>,
+}
+
+/// Locations of the pointers inside items of a single type
+#[derive(Debug, Clone)]
+pub struct PatchTable {
+    pub typ: u32,
+    /// Absolute offsets of the pointer fields
+    pub offsets: Vec<u64>,
+}
+
+impl HavokTagFile {
+    pub fn read(f: &mut (impl Read + Seek)) -> anyhow::Result<Self> {
+        Self::read_with_compendium(f, None)
+    }
+
+    /// Reads a tagfile, using the given compendium for type information when the file doesn't contain a TYPE section.
+    ///
+    /// The whole stream is read into memory first, see [`Self::from_bytes_with_compendium`].
+    pub fn read_with_compendium(
+        f: &mut (impl Read + Seek),
+        compendium: Option<&TypeCompendium>,
+    ) -> anyhow::Result<Self> {
+        let mut data = vec![];
+        f.seek(SeekFrom::Start(0))?;
+        f.read_to_end(&mut data)?;
+
+        Self::from_bytes_with_compendium(&data, compendium)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        Self::from_bytes_with_compendium(data, None)
+    }
+
+    /// Builds the section tree and item index of a tagfile in memory. Item contents are not decoded
+    pub fn from_bytes_with_compendium(
+        data: &[u8],
+        compendium: Option<&TypeCompendium>,
+    ) -> anyhow::Result<Self> {
+        let f = &mut Cursor::new(data);
+
+        // Destiny's havok files have 16 bytes of padding (?) at the start
+        if f.read_be::<u32>()? == 0 {
+            f.seek(SeekFrom::Start(0x10))?;
+        } else {
+            f.seek(SeekFrom::Start(0x0))?;
+        }
+
+        let tag0: TagSection = f.read_be()?;
+        anyhow::ensure!(
+            tag0.signature == TagSectionSignature::Tag0,
+            "First tag must be TAG0",
+        );
+
+        let file_end = f.seek(SeekFrom::End(0))?;
+        tag0.ensure_within(file_end)?;
+
+        let mut tagfile = HavokTagFile {
+            sections: vec![],
+            sdk_version: None,
+            endian: Endian::Little,
+            data_offset: 0,
+            data_size: 0,
+            items: vec![],
+            types: TypeRegistry::default(),
+            compendium_id: None,
+            patches: vec![],
+            pointers: BTreeMap::new(),
+            pointer_size: DEFAULT_POINTER_SIZE,
+            item_offsets: vec![],
+        };
+
+        let mut patches_raw = vec![];
+
+        let mut items_raw = vec![];
+        let mut types = None;
+        f.seek(SeekFrom::Start(tag0.offset))?;
+        while f.stream_position()? < tag0.end() {
+            let section = f
+                .read_be::<TagSection>()
+                .context("Failed to read section")?;
+            section.ensure_within(tag0.end())?;
+            tagfile.sections.push(section.clone());
+
+            match section.signature {
+                TagSectionSignature::SdkVersion => {
+                    let mut data = vec![0u8; section.size];
+                    f.read_exact(&mut data)?;
+                    tagfile.sdk_version = Some(
+                        String::from_utf8_lossy(&data)
+                            .trim_end_matches('\0')
+                            .to_string(),
+                    );
+                }
+                TagSectionSignature::Data => {
+                    tagfile.data_offset = section.offset;
+                    tagfile.data_size = section.size;
+                }
+                TagSectionSignature::Tcrf => {
+                    tagfile.compendium_id = Some(f.read_le()?);
+                }
+                TagSectionSignature::Type => {
+                    types = Some(TypeRegistry::read_type_section(f, &section)?);
+                }
+                TagSectionSignature::Index => {
+                    f.seek(SeekFrom::Start(section.offset))?;
+                    while f.stream_position()? < section.end() {
+                        let sub = f.read_be::<TagSection>()?;
+                        sub.ensure_within(section.end())?;
+                        if sub.signature == TagSectionSignature::IndexItem {
+                            tagfile.endian = if sub.is_le {
+                                Endian::Little
+                            } else {
+                                Endian::Big
+                            };
+
+                            while f.stream_position()? < sub.end() {
+                                items_raw.push(f.read_type::<IndexItem>(tagfile.endian)?);
+                            }
+                        }
+
+                        if sub.signature == TagSectionSignature::Ptch {
+                            // Patch endianness follows the item table, which precedes it
+                            while f.stream_position()? < sub.end() {
+                                let typ: u32 = f.read_type(tagfile.endian)?;
+                                let count: u32 = f.read_type(tagfile.endian)?;
+                                let remaining = sub.end() - f.stream_position()?;
+                                anyhow::ensure!(
+                                    count as u64 * 4 <= remaining,
+                                    HavokError::TruncatedSection {
+                                        section: "PTCH section".to_string(),
+                                        offset: f.stream_position()?,
+                                        size: count as u64 * 4,
+                                        available: remaining,
+                                    }
+                                );
+
+                                let offsets: Vec<u32> = f.read_type_args(
+                                    tagfile.endian,
+                                    VecArgs {
+                                        count: count as usize,
+                                        inner: (),
+                                    },
+                                )?;
+                                patches_raw.push((typ, offsets));
+                            }
+                        }
+
+                        f.seek(SeekFrom::Start(sub.end()))?;
+                        tagfile.sections.push(sub);
+                    }
+                }
+                _ => {}
+            }
+
+            f.seek(SeekFrom::Start(section.end()))?;
+        }
+
+        tagfile.items = items_raw
+            .into_iter()
+            .map(|mut it| {
+                // Null and empty items may sit at the very end of the data section
+                tagfile.ensure_data_range(tagfile.data_offset + it.offset as u64, 0)?;
+                it.offset += tagfile.data_offset as u32;
+                Ok(it)
+            })
+            .collect::<Result<_, HavokError>>()?;
+
+        tagfile.types = match (types, compendium) {
+            (Some(types), _) => types,
+            (None, Some(compendium)) => {
+                if let Some(id) = tagfile.compendium_id {
+                    anyhow::ensure!(
+                        compendium.ids.contains(&id),
+                        "Tagfile references compendium 0x{id:016x}, which is not in the given compendium"
+                    );
+                }
+
+                compendium.types.clone()
+            }
+            (None, None) => TypeRegistry::destiny2_fallback(),
+        };
+
+        tagfile.pointer_size = tagfile.types.pointer_size().unwrap_or(DEFAULT_POINTER_SIZE);
+
+        tagfile.item_offsets = tagfile.items.iter().map(|it| it.offset as u64).collect();
+        tagfile.item_offsets.sort_unstable();
+        tagfile.item_offsets.dedup();
+
+        for (typ, offsets) in patches_raw {
+            let offsets = offsets
+                .into_iter()
+                .map(|o| tagfile.data_offset + o as u64)
+                .collect::<Vec<_>>();
+
+            for &offset in &offsets {
+                tagfile.ensure_data_range(offset, tagfile.pointer_size)?;
+
+                // Pointers are stored as the index of the item they point to
+                f.seek(SeekFrom::Start(offset))?;
+                let index = match tagfile.pointer_size {
+                    4 => f.read_type::<u32>(tagfile.endian)? as u64,
+                    _ => f.read_type::<u64>(tagfile.endian)?,
+                };
+                tagfile.pointers.insert(offset, index);
+            }
+
+            tagfile.patches.push(PatchTable { typ, offsets });
+        }
+
+        Ok(tagfile)
+    }
+
+    /// Fails if the given absolute range is not contained in the DATA section
+    pub fn ensure_data_range(&self, offset: u64, size: u64) -> Result<(), HavokError> {
+        let data_end = self.data_offset + self.data_size as u64;
+        if offset < self.data_offset || offset.saturating_add(size) > data_end {
+            return Err(HavokError::TruncatedSection {
+                section: "DATA section".to_string(),
+                offset,
+                size,
+                available: data_end.saturating_sub(offset),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn item(&self, index: u64) -> Option<&IndexItem> {
+        // Item 0 is the null item
+        if index == 0 {
+            return None;
+        }
+
+        self.items.get(index as usize)
+    }
+
+    /// Like [`Self::item`], but fails with [`HavokError::InvalidIndex`] for null or out of range indices
+    pub fn require_item(
+        &self,
+        index: u64,
+        context: &'static str,
+    ) -> Result<&IndexItem, HavokError> {
+        self.item(index)
+            .ok_or(HavokError::InvalidIndex { context, index })
+    }
+
+    /// Item referenced by the pointer at the given absolute offset.
+    ///
+    /// Returns `None` if the offset is not covered by the patch table, or if the file has no patch table at all.
+    pub fn pointer_at(&self, offset: u64) -> Option<u64> {
+        self.pointers.get(&offset).copied()
+    }
+
+    /// Whether the file lists its pointers in a PTCH section.
+    ///
+    /// Without one, every non-zero pointer field has to be taken at face value.
+    pub fn has_patches(&self) -> bool {
+        !self.patches.is_empty()
+    }
+
+    /// Every patched pointer inside the given item, as `(absolute offset, referenced item)` pairs
+    pub fn item_references(&self, index: u64) -> Vec<(u64, u64)> {
+        let Some(extent) = self.item_extent(index) else {
+            return vec![];
+        };
+
+        self.pointers.range(extent).map(|(&o, &i)| (o, i)).collect()
+    }
+
+    /// Absolute byte range covered by the given item.
+    ///
+    /// Without type layouts, the item is assumed to extend up to the next item (or the end of the DATA section).
+    pub fn item_extent(&self, index: u64) -> Option<Range<u64>> {
+        let item = self.item(index)?;
+
+        let start = item.offset as u64;
+        let data_end = self.data_offset + self.data_size as u64;
+        let end = match self.types.size(item.typ) {
+            Some(size) => start + size as u64 * item.count.max(1) as u64,
+            // Without layouts, assume the item extends up to the next one
+            None => {
+                let next = self.item_offsets.partition_point(|&o| o <= start);
+                self.item_offsets.get(next).copied().unwrap_or(data_end)
+            }
+        };
+
+        Some(start..end.clamp(start, data_end))
+    }
+
+    /// Class name of the given item, if known
+    pub fn item_type_name(&self, index: u64) -> Option<&str> {
+        self.types.name(self.item(index)?.typ)
+    }
+
+    /// First item whose type has no name, which is the case for files read without their type information.
+    ///
+    /// Lookups by class name can't find such items, see [`HavokError::UnknownType`].
+    pub fn first_unnamed_item(&self) -> Option<u64> {
+        (1..self.items.len() as u64).find(|&i| self.item_type_name(i).is_none())
+    }
+
+    /// Returns the indices of all items with the given class name (or a subclass of it)
+    pub fn find_items(&self, class_name: &str) -> Vec<u64> {
+        self.items
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, it)| self.types.is_a(it.typ, class_name))
+            .map(|(i, _)| i as u64)
+            .collect()
+    }
+
+    /// Returns the index of the first item with the given class name (or a subclass of it)
+    pub fn find_item(&self, class_name: &str) -> Option<u64> {
+        self.find_items(class_name).first().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        types::hkPointer,
+        writer::{ItemData, TagFileWriter},
+    };
+
+    fn pointer(tagfile: &HavokTagFile, data: &[u8], offset: u64) -> hkPointer {
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(offset);
+        cursor.read_type(tagfile.endian).unwrap()
+    }
+
+    #[test]
+    fn patched_pointers() {
+        let mut writer = TagFileWriter::new();
+        let leaf = writer.add_item("Leaf", ItemData::new().zeros(24)).unwrap();
+        let holder = writer
+            .add_item(
+                "Holder",
+                ItemData::new()
+                    .pointer(Some(leaf))
+                    // Looks like an index, but isn't listed in the patch table
+                    .u64(leaf)
+                    .pointer(None),
+            )
+            .unwrap();
+
+        let mut data = vec![];
+        writer.write(&mut data).unwrap();
+        let tagfile = HavokTagFile::from_bytes(&data).unwrap();
+        assert!(tagfile.has_patches());
+        assert_eq!(tagfile.pointer_size, 8);
+
+        let start = tagfile.item(holder).unwrap().offset as u64;
+        assert_eq!(tagfile.item_references(holder), [(start, leaf)]);
+
+        assert_eq!(
+            pointer(&tagfile, &data, start).resolve(&tagfile).unwrap(),
+            Some(leaf)
+        );
+        assert!(matches!(
+            pointer(&tagfile, &data, start + 8).resolve(&tagfile),
+            Err(HavokError::UnpatchedPointer { index, .. }) if index == leaf
+        ));
+        assert!(pointer(&tagfile, &data, start + 16).is_null(&tagfile));
+    }
+
+    #[test]
+    fn item_extents() {
+        let mut writer = TagFileWriter::new();
+        let first = writer.add_item("First", ItemData::new().zeros(20)).unwrap();
+        let second = writer.add_item("Second", ItemData::new().zeros(8)).unwrap();
+
+        let mut data = vec![];
+        writer.write(&mut data).unwrap();
+        let tagfile = HavokTagFile::from_bytes(&data).unwrap();
+
+        let first_start = tagfile.item(first).unwrap().offset as u64;
+        let second_start = tagfile.item(second).unwrap().offset as u64;
+        let data_end = tagfile.data_offset + tagfile.data_size as u64;
+
+        // Without layouts, items extend up to the next item, padding included
+        assert_eq!(tagfile.item_extent(first), Some(first_start..second_start));
+        assert_eq!(tagfile.item_extent(second), Some(second_start..data_end));
+        assert_eq!(tagfile.item_extent(0), None);
+    }
+}
@@ -0,0 +1,679 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Context;
+use binrw::BinReaderExt;
+use bitflags::bitflags;
+
+use crate::section::{TagSection, TagSectionSignature};
+
+bitflags! {
+    /// Optional fields present in a TBOD/TBDY type body
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct TypeBodyFlags: u32 {
+        const FORMAT = 0x1;
+        const SUBTYPE = 0x2;
+        const VERSION = 0x4;
+        const SIZE_ALIGN = 0x8;
+        const FLAGS = 0x10;
+        const MEMBERS = 0x20;
+        const INTERFACES = 0x40;
+        const ATTRIBUTE = 0x80;
+    }
+}
+
+/// The kind of data described by a type, stored in the low byte of the type format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeKind {
+    Void,
+    Opaque,
+    Bool,
+    String,
+    Int,
+    Float,
+    Pointer,
+    Record,
+    Array,
+    Tuple,
+    Unknown(u32),
+}
+
+impl TypeKind {
+    pub fn from_format(format: u32) -> Self {
+        match format & 0xff {
+            0x0 => TypeKind::Void,
+            0x1 => TypeKind::Opaque,
+            0x2 => TypeKind::Bool,
+            0x3 => TypeKind::String,
+            0x4 => TypeKind::Int,
+            0x5 => TypeKind::Float,
+            0x6 => TypeKind::Pointer,
+            0x7 => TypeKind::Record,
+            0x8 => TypeKind::Array,
+            0x28 => TypeKind::Tuple,
+            u => TypeKind::Unknown(u),
+        }
+    }
+}
+
+pub const FORMAT_SIGNED: u32 = 0x200;
+
+#[derive(Debug, Clone)]
+pub enum HkTemplateArgument {
+    Type { name: String, typ: u32 },
+    Value { name: String, value: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct HkMember {
+    pub name: String,
+    pub flags: u32,
+    pub offset: u32,
+    pub typ: u32,
+}
+
+/// A single type as described by the TYPE section.
+///
+/// Optional properties are only set when the type body specifies them, use the
+/// accessors on [`TypeRegistry`] to resolve them through the parent chain.
+#[derive(Debug, Clone, Default)]
+pub struct HkType {
+    pub name: String,
+    pub template_arguments: Vec<HkTemplateArgument>,
+
+    pub parent: Option<u32>,
+    pub body_flags: TypeBodyFlags,
+    pub format: Option<u32>,
+    pub subtype: Option<u32>,
+    pub version: Option<u32>,
+    pub size_align: Option<(u32, u32)>,
+    pub type_flags: Option<u32>,
+    pub members: Vec<HkMember>,
+    pub interfaces: Vec<(u32, u32)>,
+    pub attribute: Option<u32>,
+
+    pub hash: Option<u32>,
+}
+
+/// Type table for a tagfile. Index 0 is always the null type.
+#[derive(Debug, Clone, Default)]
+pub struct TypeRegistry {
+    pub types: Vec<HkType>,
+
+    /// Whether the types have layout information, or just names
+    pub has_layouts: bool,
+}
+
+impl TypeRegistry {
+    /// Names for the type indices found in Destiny 2 havok files, which ship without type information.
+    /// Only used when neither the file itself nor a compendium provides a TYPE section, files using any other type
+    /// need a compendium.
+    ///
+    /// Only indices the index-based reader this replaced matched on in shipped files are listed:
+    /// - 0x74: the root of every shape collection (`s_hkpShape_array`, not a havok class)
+    /// - 0x81: list-style shape container, read as [`Unk81`](crate::types::unknown::Unk81). The actual class
+    ///   is unknown, so it keeps the placeholder name of its struct
+    /// - 0x88: `hkpConvexVerticesShape`, its layout matches the havok class
+    /// - 0xaf: `hkpStaticCompoundShape`, its layout matches the havok class
+    pub fn destiny2_fallback() -> Self {
+        let names: &[(u32, &str)] = &[
+            (0x74, "s_hkpShape_array"),
+            (0x81, "Unk81"),
+            (0x88, "hkpConvexVerticesShape"),
+            (0xaf, "hkpStaticCompoundShape"),
+        ];
+
+        let count = names.iter().map(|(i, _)| *i).max().unwrap_or(0) as usize + 1;
+        let mut types = vec![HkType::default(); count];
+        for (index, name) in names {
+            types[*index as usize].name = name.to_string();
+        }
+
+        Self {
+            types,
+            has_layouts: false,
+        }
+    }
+
+    /// Reads the contents of a TYPE section. `f` must be positioned at the start of the section data.
+    pub fn read_type_section(
+        f: &mut (impl Read + Seek),
+        section: &TagSection,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            section.signature == TagSectionSignature::Type,
+            "Expected a TYPE section, got {:?}",
+            section.signature
+        );
+
+        let mut type_strings = vec![];
+        let mut field_strings = vec![];
+        let mut registry = TypeRegistry {
+            types: vec![],
+            has_layouts: false,
+        };
+
+        f.seek(SeekFrom::Start(section.offset))?;
+        while f.stream_position()? < section.end() {
+            let sub: TagSection = f.read_be()?;
+            sub.ensure_within(section.end())?;
+            let mut data = vec![0u8; sub.size];
+            f.read_exact(&mut data)?;
+
+            match sub.signature {
+                TagSectionSignature::TypeStrings | TagSectionSignature::TypeStrings1 => {
+                    type_strings = read_string_table(&data);
+                }
+                TagSectionSignature::FieldStrings | TagSectionSignature::FieldStrings1 => {
+                    field_strings = read_string_table(&data);
+                }
+                TagSectionSignature::TypeNames | TagSectionSignature::TypeNames1 => {
+                    registry.read_type_names(&data, &type_strings)?;
+                }
+                TagSectionSignature::TypeBodies | TagSectionSignature::TypeBodies1 => {
+                    registry.read_type_bodies(&data, &field_strings)?;
+                    registry.has_layouts = true;
+                }
+                TagSectionSignature::TypeHashes => {
+                    registry.read_type_hashes(&data)?;
+                }
+                _ => {}
+            }
+
+            f.seek(SeekFrom::Start(sub.end()))?;
+        }
+
+        Ok(registry)
+    }
+
+    /// Builds the contents of a TYPE section holding only type names and template arguments.
+    ///
+    /// Readers will know the names of the types, but not their layouts.
+    pub fn write_type_names_section(&self) -> anyhow::Result<Vec<u8>> {
+        let mut strings: Vec<&str> = vec![];
+        // Linear search is fine for the handful of types written by hand
+        fn string_index<'a>(strings: &mut Vec<&'a str>, s: &'a str) -> u64 {
+            match strings.iter().position(|&e| e == s) {
+                Some(i) => i as u64,
+                None => {
+                    strings.push(s);
+                    strings.len() as u64 - 1
+                }
+            }
+        }
+
+        let mut names = vec![];
+        write_packed(&mut names, self.types.len().max(1) as u64)?;
+        for t in self.types.iter().skip(1) {
+            let name = string_index(&mut strings, &t.name);
+            write_packed(&mut names, name)?;
+            write_packed(&mut names, t.template_arguments.len() as u64)?;
+            for a in &t.template_arguments {
+                let (name, value) = match a {
+                    HkTemplateArgument::Type { name, typ } => (name, *typ as u64),
+                    HkTemplateArgument::Value { name, value } => (name, *value),
+                };
+                let name = string_index(&mut strings, name);
+                write_packed(&mut names, name)?;
+                write_packed(&mut names, value)?;
+            }
+        }
+
+        let mut string_table = vec![];
+        for s in strings {
+            string_table.extend_from_slice(s.as_bytes());
+            string_table.push(0);
+        }
+        string_table.resize(string_table.len().next_multiple_of(4), 0);
+        names.resize(names.len().next_multiple_of(4), 0);
+
+        let mut out = vec![];
+        TagSection::write(
+            &mut out,
+            &TagSectionSignature::TypeStrings1,
+            false,
+            &string_table,
+        )?;
+        TagSection::write(&mut out, &TagSectionSignature::TypeNames1, false, &names)?;
+        Ok(out)
+    }
+
+    fn read_type_names(&mut self, data: &[u8], strings: &[String]) -> anyhow::Result<()> {
+        let mut r = PackedReader::new(data);
+        let count = r.read()? as usize;
+        // Every type after the null type takes up at least two bytes (name and template count)
+        anyhow::ensure!(
+            count.saturating_sub(1) * 2 <= data.len(),
+            "Type name table claims {count} types, but is only {} bytes long",
+            data.len()
+        );
+
+        let string = |i: u64| -> anyhow::Result<String> {
+            strings
+                .get(i as usize)
+                .cloned()
+                .with_context(|| format!("Type string index {i} is out of bounds"))
+        };
+
+        self.types = vec![HkType::default(); count.max(1)];
+        for t in self.types.iter_mut().skip(1) {
+            t.name = string(r.read()?)?;
+
+            let template_count = r.read()?;
+            for _ in 0..template_count {
+                let name = string(r.read()?)?;
+                let value = r.read()?;
+                // Template parameters prefixed with 't' refer to a type, 'v' is a plain value
+                t.template_arguments.push(if name.starts_with('t') {
+                    HkTemplateArgument::Type {
+                        name,
+                        typ: value as u32,
+                    }
+                } else {
+                    HkTemplateArgument::Value { name, value }
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_type_bodies(&mut self, data: &[u8], strings: &[String]) -> anyhow::Result<()> {
+        let mut r = PackedReader::new(data);
+        while !r.is_empty() {
+            let index = r.read()? as usize;
+            if index == 0 {
+                continue;
+            }
+
+            let t = self
+                .types
+                .get_mut(index)
+                .with_context(|| format!("Type body references invalid type {index}"))?;
+
+            t.parent = Some(r.read()? as u32).filter(|&p| p != 0);
+            t.body_flags = TypeBodyFlags::from_bits_retain(r.read()? as u32);
+
+            if t.body_flags.contains(TypeBodyFlags::FORMAT) {
+                t.format = Some(r.read()? as u32);
+            }
+            if t.body_flags.contains(TypeBodyFlags::SUBTYPE) {
+                t.subtype = Some(r.read()? as u32);
+            }
+            if t.body_flags.contains(TypeBodyFlags::VERSION) {
+                t.version = Some(r.read()? as u32);
+            }
+            if t.body_flags.contains(TypeBodyFlags::SIZE_ALIGN) {
+                t.size_align = Some((r.read()? as u32, r.read()? as u32));
+            }
+            if t.body_flags.contains(TypeBodyFlags::FLAGS) {
+                t.type_flags = Some(r.read()? as u32);
+            }
+            if t.body_flags.contains(TypeBodyFlags::MEMBERS) {
+                let count = r.read()?;
+                for _ in 0..count {
+                    let name_index = r.read()?;
+                    t.members.push(HkMember {
+                        name: strings.get(name_index as usize).cloned().with_context(|| {
+                            format!("Field string index {name_index} is out of bounds")
+                        })?,
+                        flags: r.read()? as u32,
+                        offset: r.read()? as u32,
+                        typ: r.read()? as u32,
+                    });
+                }
+            }
+            if t.body_flags.contains(TypeBodyFlags::INTERFACES) {
+                let count = r.read()?;
+                for _ in 0..count {
+                    t.interfaces.push((r.read()? as u32, r.read()? as u32));
+                }
+            }
+            if t.body_flags.contains(TypeBodyFlags::ATTRIBUTE) {
+                t.attribute = Some(r.read()? as u32);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_type_hashes(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let mut r = PackedReader::new(data);
+        let count = r.read()?;
+        for _ in 0..count {
+            let index = r.read()? as usize;
+            let hash = r.read_u32_le()?;
+            if let Some(t) = self.types.get_mut(index) {
+                t.hash = Some(hash);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, index: u32) -> Option<&HkType> {
+        if index == 0 {
+            return None;
+        }
+
+        self.types.get(index as usize)
+    }
+
+    pub fn name(&self, index: u32) -> Option<&str> {
+        self.get(index)
+            .map(|t| t.name.as_str())
+            .filter(|n| !n.is_empty())
+    }
+
+    /// Finds the index of the first type with the given name
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.types
+            .iter()
+            .position(|t| t.name == name)
+            .filter(|&i| i != 0)
+            .map(|i| i as u32)
+    }
+
+    /// Returns true if the type or one of its parents has the given name
+    pub fn is_a(&self, index: u32, name: &str) -> bool {
+        self.parent_chain(index).any(|(_, t)| t.name == name)
+    }
+
+    /// Iterates over the type and its parents, starting at the type itself
+    pub fn parent_chain(&self, index: u32) -> impl Iterator<Item = (u32, &HkType)> {
+        let mut current = Some(index);
+        let mut depth = 0;
+        std::iter::from_fn(move || {
+            let index = current?;
+            let t = self.get(index)?;
+            // Guard against malformed parent cycles
+            depth += 1;
+            current = t.parent.filter(|_| depth < 64);
+            Some((index, t))
+        })
+    }
+
+    pub fn format(&self, index: u32) -> Option<u32> {
+        self.parent_chain(index).find_map(|(_, t)| t.format)
+    }
+
+    pub fn kind(&self, index: u32) -> TypeKind {
+        self.format(index)
+            .map(TypeKind::from_format)
+            .unwrap_or(TypeKind::Void)
+    }
+
+    /// The pointed-to type for pointers and arrays, or the element type for tuples
+    pub fn subtype(&self, index: u32) -> Option<u32> {
+        self.parent_chain(index).find_map(|(_, t)| t.subtype)
+    }
+
+    pub fn size(&self, index: u32) -> Option<u32> {
+        self.parent_chain(index)
+            .find_map(|(_, t)| t.size_align)
+            .map(|(s, _)| s)
+    }
+
+    /// Size of pointer types, if the registry has layouts for any of them
+    pub fn pointer_size(&self) -> Option<u64> {
+        (0..self.types.len() as u32)
+            .filter(|&i| self.kind(i) == TypeKind::Pointer)
+            .find_map(|i| self.size(i))
+            .map(|s| s as u64)
+            .filter(|&s| s == 4 || s == 8)
+    }
+
+    /// All members of a record type, including those inherited from parent types
+    pub fn members(&self, index: u32) -> Vec<&HkMember> {
+        let mut chain = self.parent_chain(index).collect::<Vec<_>>();
+        chain.reverse();
+        chain
+            .into_iter()
+            .flat_map(|(_, t)| t.members.iter())
+            .collect()
+    }
+
+    /// Formats the type name including template arguments, eg. `hkArray<hkVector4, hkContainerHeapAllocator>`
+    pub fn full_name(&self, index: u32) -> String {
+        let Some(t) = self.get(index) else {
+            return format!("<unknown type 0x{index:x}>");
+        };
+
+        let name = if t.name.is_empty() {
+            format!("<unknown type 0x{index:x}>")
+        } else {
+            t.name.clone()
+        };
+
+        if t.template_arguments.is_empty() {
+            return name;
+        }
+
+        let args = t
+            .template_arguments
+            .iter()
+            .map(|a| match a {
+                HkTemplateArgument::Type { typ, .. } => self.full_name(*typ),
+                HkTemplateArgument::Value { value, .. } => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("{name}<{args}>")
+    }
+}
+
+/// Type information shared between multiple tagfiles, referenced through a TCRF section
+#[derive(Debug, Clone, Default)]
+pub struct TypeCompendium {
+    pub ids: Vec<u64>,
+    pub types: TypeRegistry,
+}
+
+impl TypeCompendium {
+    pub fn read(f: &mut (impl Read + Seek)) -> anyhow::Result<Self> {
+        // Destiny's havok files have 16 bytes of padding (?) at the start
+        if f.read_be::<u32>()? == 0 {
+            f.seek(SeekFrom::Start(0x10))?;
+        } else {
+            f.seek(SeekFrom::Start(0x0))?;
+        }
+
+        let tcm0: TagSection = f.read_be()?;
+        anyhow::ensure!(
+            tcm0.signature == TagSectionSignature::Compendium,
+            "First tag of a type compendium must be TCM0",
+        );
+
+        let file_end = f.seek(SeekFrom::End(0))?;
+        tcm0.ensure_within(file_end)?;
+
+        let mut compendium = TypeCompendium::default();
+        f.seek(SeekFrom::Start(tcm0.offset))?;
+        while f.stream_position()? < tcm0.end() {
+            let section: TagSection = f.read_be()?;
+            section.ensure_within(tcm0.end())?;
+            match section.signature {
+                TagSectionSignature::CompendiumIds => {
+                    for _ in 0..section.size / 8 {
+                        compendium.ids.push(f.read_le()?);
+                    }
+                }
+                TagSectionSignature::Type => {
+                    compendium.types = TypeRegistry::read_type_section(f, &section)?;
+                }
+                _ => {}
+            }
+
+            f.seek(SeekFrom::Start(section.end()))?;
+        }
+
+        Ok(compendium)
+    }
+}
+
+fn read_string_table(data: &[u8]) -> Vec<String> {
+    let mut strings = data
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+
+    // The table is null-terminated, which leaves an empty string at the end
+    if data.last() == Some(&0) {
+        strings.pop();
+    }
+
+    strings
+}
+
+/// Appends a variable-length integer as read by [`PackedReader`]
+fn write_packed(out: &mut Vec<u8>, v: u64) -> anyhow::Result<()> {
+    match v {
+        0..=0x7f => out.push(v as u8),
+        0x80..=0x3fff => out.extend_from_slice(&(0x8000 | v as u16).to_be_bytes()),
+        0x4000..=0x1fffff => out.extend_from_slice(&(0xc00000 | v as u32).to_be_bytes()[1..]),
+        0x200000..=0x7ffffff => out.extend_from_slice(&(0xe0000000 | v as u32).to_be_bytes()),
+        _ => anyhow::bail!("Value 0x{v:x} is too large to be written as a packed integer"),
+    }
+
+    Ok(())
+}
+
+/// Reader for the variable-length integers used throughout the TYPE section
+struct PackedReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PackedReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn read_u8(&mut self) -> anyhow::Result<u8> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .context("Unexpected end of packed integer data")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn read_be_bytes(&mut self, count: usize) -> anyhow::Result<u64> {
+        let mut v = 0u64;
+        for _ in 0..count {
+            v = (v << 8) | self.read_u8()? as u64;
+        }
+        Ok(v)
+    }
+
+    fn read_u32_le(&mut self) -> anyhow::Result<u32> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + 4)
+            .context("Unexpected end of packed integer data")?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read(&mut self) -> anyhow::Result<u64> {
+        let b = self.read_u8()? as u64;
+        if b & 0x80 == 0 {
+            return Ok(b);
+        }
+
+        Ok(match b >> 3 {
+            0x10..=0x17 => ((b << 8) | self.read_be_bytes(1)?) & 0x3fff,
+            0x18..=0x1b => ((b << 16) | self.read_be_bytes(2)?) & 0x1fffff,
+            0x1c => ((b << 24) | self.read_be_bytes(3)?) & 0x7ffffff,
+            0x1d => ((b & 0x7) << 32) | self.read_be_bytes(4)?,
+            0x1e => ((b & 0x7) << 56) | self.read_be_bytes(7)?,
+            0x1f => {
+                if b & 0x7 == 0 {
+                    self.read_be_bytes(8)?
+                } else {
+                    anyhow::bail!("Unsupported packed integer prefix 0x{b:x}")
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(data: &[u8]) -> anyhow::Result<Vec<u64>> {
+        let mut r = PackedReader::new(data);
+        let mut values = vec![];
+        while !r.is_empty() {
+            values.push(r.read()?);
+        }
+        Ok(values)
+    }
+
+    #[test]
+    fn packed_integer_sizes() {
+        assert_eq!(read_all(&[0x00, 0x7f]).unwrap(), [0, 0x7f]);
+        assert_eq!(read_all(&[0x80, 0x80, 0xbf, 0xff]).unwrap(), [0x80, 0x3fff]);
+        assert_eq!(read_all(&[0xc0, 0x40, 0x00]).unwrap(), [0x4000]);
+        assert_eq!(read_all(&[0xe0, 0x20, 0x00, 0x00]).unwrap(), [0x200000]);
+        assert_eq!(
+            read_all(&[0xe9, 0x01, 0x02, 0x03, 0x04]).unwrap(),
+            [0x1_0102_0304]
+        );
+        assert_eq!(
+            read_all(&[0xf8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]).unwrap(),
+            [0x0102_0304_0506_0708]
+        );
+    }
+
+    #[test]
+    fn packed_integer_errors() {
+        // Truncated two and four byte forms
+        assert!(read_all(&[0x80]).is_err());
+        assert!(read_all(&[0xe0, 0x00]).is_err());
+        assert!(read_all(&[0xf9, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn packed_integer_roundtrip() {
+        let values = [
+            0, 1, 0x7f, 0x80, 0x1234, 0x3fff, 0x4000, 0x1fffff, 0x200000, 0x7ffffff,
+        ];
+        let mut data = vec![];
+        for v in values {
+            write_packed(&mut data, v).unwrap();
+        }
+
+        assert_eq!(read_all(&data).unwrap(), values);
+        assert!(write_packed(&mut data, 0x8000000).is_err());
+    }
+
+    #[test]
+    fn type_name_count_is_bounded_by_data() {
+        let mut registry = TypeRegistry::default();
+        // Claims 100 types in 4 bytes
+        assert!(registry.read_type_names(&[0x64, 0, 0, 0], &[]).is_err());
+
+        let strings = ["hkUint8".to_string()];
+        registry
+            .read_type_names(&[0x02, 0x00, 0x00], &strings)
+            .unwrap();
+        assert_eq!(registry.find("hkUint8"), Some(1));
+    }
+
+    #[test]
+    fn fallback_names_are_unique() {
+        let fallback = TypeRegistry::destiny2_fallback();
+        for (i, t) in fallback.types.iter().enumerate().skip(1) {
+            if !t.name.is_empty() {
+                assert_eq!(fallback.find(&t.name), Some(i as u32));
+            }
+        }
+    }
+}
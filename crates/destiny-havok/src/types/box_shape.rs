@@ -0,0 +1,36 @@
+use binrw::binread;
+use glam::{Vec3, Vec4};
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpBoxShape {
+    pub unk0: [u32; 10],
+    pub radius: f32,
+    pub unk2c: u32,
+
+    #[br(map = Vec4::from_array)]
+    pub half_extents: Vec4,
+}
+
+impl hkpBoxShape {
+    pub fn vertices(&self) -> [Vec3; 8] {
+        let e = self.half_extents.truncate();
+        BOX_VERTICES.map(|v| v * e)
+    }
+}
+
+const BOX_VERTICES: [Vec3; 8] = [
+    Vec3::new(-1.0, -1.0, -1.0),
+    Vec3::new(-1.0, -1.0, 1.0),
+    Vec3::new(-1.0, 1.0, -1.0),
+    Vec3::new(-1.0, 1.0, 1.0),
+    Vec3::new(1.0, -1.0, -1.0),
+    Vec3::new(1.0, -1.0, 1.0),
+    Vec3::new(1.0, 1.0, -1.0),
+    Vec3::new(1.0, 1.0, 1.0),
+];
+
+pub const BOX_INDICES: [u32; 36] = [
+    0, 1, 2, 2, 1, 3, 4, 6, 5, 6, 7, 5, 0, 2, 4, 4, 2, 6, 1, 5, 3, 3, 5, 7, 0, 4, 1, 1, 4, 5, 2, 3,
+    6, 6, 3, 7,
+];
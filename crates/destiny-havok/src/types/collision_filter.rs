@@ -0,0 +1,63 @@
+use binrw::binread;
+
+/// Collision filter info as used by `hkpGroupFilter`.
+///
+/// Two shapes collide if their layers are enabled against each other, unless they share a non-zero system group
+/// and their subsystems don't allow it.
+#[binread]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CollisionFilterInfo(pub u32);
+
+impl CollisionFilterInfo {
+    pub fn layer(&self) -> u32 {
+        self.0 & 0x1f
+    }
+
+    pub fn subsystem_id(&self) -> u32 {
+        (self.0 >> 5) & 0x1f
+    }
+
+    pub fn subsystem_dont_collide_with(&self) -> u32 {
+        (self.0 >> 10) & 0x1f
+    }
+
+    pub fn system_group(&self) -> u32 {
+        self.0 >> 16
+    }
+}
+
+impl std::fmt::Debug for CollisionFilterInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CollisionFilterInfo")
+            .field("layer", &self.layer())
+            .field("system_group", &self.system_group())
+            .field("subsystem_id", &self.subsystem_id())
+            .field(
+                "subsystem_dont_collide_with",
+                &self.subsystem_dont_collide_with(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinReaderExt;
+
+    use super::*;
+
+    #[test]
+    fn filter_info_fields() {
+        let info: CollisionFilterInfo =
+            Cursor::new(0x0012_1c23u32.to_le_bytes()).read_le().unwrap();
+        assert_eq!(info.layer(), 3);
+        assert_eq!(info.subsystem_id(), 1);
+        assert_eq!(info.subsystem_dont_collide_with(), 7);
+        assert_eq!(info.system_group(), 0x12);
+
+        assert_eq!(CollisionFilterInfo(u32::MAX).layer(), 0x1f);
+        assert_eq!(CollisionFilterInfo(u32::MAX).system_group(), 0xffff);
+    }
+}
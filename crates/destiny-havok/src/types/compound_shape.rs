@@ -1,7 +1,7 @@
 use binrw::binread;
 use glam::{Quat, Vec4};
 
-use super::{bvtree::BvTreeType, hkArrayIndex, hkPointerIndex};
+use super::{bvtree::BvTreeType, collision_filter::CollisionFilterInfo, hkArray, hkPointer};
 
 #[binread]
 #[derive(Debug)]
@@ -12,12 +12,10 @@ pub struct hkpStaticCompoundShape {
 
     pub unk30: [u64; 2],
 
-    pub instances: hkArrayIndex,
+    pub instances: hkArray<hkpStaticCompoundShapeInstance>,
 
-    pub unk48: [u64; 5],
-    pub instance_extra_infos: hkArrayIndex,
-
-    pub unk78: u64,
+    pub unk50: [u64; 4],
+    pub instance_extra_infos: hkArray<u16>,
 
     #[br(map = Vec4::from_array)]
     pub tree_domain_min: Vec4,
@@ -29,13 +27,18 @@ pub struct hkpStaticCompoundShape {
 #[derive(Debug)]
 pub struct hkpStaticCompoundShapeInstance {
     pub transform: hkQsTransform,
-    pub shape: hkPointerIndex,
+    pub shape: hkPointer,
 
-    pub unk28: [u32; 6],
+    pub filter_info: CollisionFilterInfo,
+    /// Mask applied to the filter info of the child shape keys
+    pub child_filter_info_mask: u32,
+    /// Material of the instance
+    pub user_data: u64,
+    pub unk48: u64,
 }
 
 #[binread]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct hkQsTransform {
     #[br(map = Vec4::from_array)]
     pub translation: Vec4,
@@ -0,0 +1,260 @@
+use binrw::binread;
+use glam::{Vec3, Vec4};
+
+use super::{bvtree::BvTreeType, hkArray};
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpBvCompressedMeshShape {
+    pub unk0: [u32; 10],
+
+    pub tree_type: BvTreeType,
+
+    pub unk30: u64,
+    pub convex_radius: f32,
+    pub welding_type: u8,
+    pub has_per_primitive_collision_filter_info: u8,
+    pub has_per_primitive_user_data: u8,
+    pub unk3f: u8,
+
+    pub collision_filter_info_palette: hkArray<u32>,
+    pub user_data_palette: hkArray<u32>,
+    pub user_string_palette: hkArray<u64>,
+
+    pub tree: hkpBvCompressedMeshShapeTree,
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpBvCompressedMeshShapeTree {
+    pub nodes: hkArray<[u8; 6]>,
+
+    #[br(map = Vec4::from_array)]
+    pub domain_min: Vec4,
+    #[br(map = Vec4::from_array)]
+    pub domain_max: Vec4,
+
+    pub num_primitive_keys: i32,
+    pub bits_per_key: i32,
+    pub max_key_value: u32,
+    pub unk3c: u32,
+
+    pub sections: hkArray<hkcdStaticMeshTreeBaseSection>,
+    pub primitives: hkArray<hkcdStaticMeshTreeBasePrimitive>,
+    pub shared_vertices_index: hkArray<u16>,
+    pub packed_vertices: hkArray<u32>,
+    pub shared_vertices: hkArray<u64>,
+    pub primitive_data_runs: hkArray<hkpBvCompressedMeshShapeTreeDataRun>,
+}
+
+impl hkpBvCompressedMeshShapeTree {
+    /// Decompresses a shared vertex, which is quantized to 21/21/22 bits over the domain of the whole tree
+    pub fn decompress_shared_vertex(&self, v: u64) -> Vec3 {
+        let scale = (self.domain_max - self.domain_min).truncate()
+            / Vec3::new(0x1fffff as f32, 0x1fffff as f32, 0x3fffff as f32);
+
+        let q = Vec3::new(
+            (v & 0x1fffff) as f32,
+            ((v >> 21) & 0x1fffff) as f32,
+            ((v >> 42) & 0x3fffff) as f32,
+        );
+
+        self.domain_min.truncate() + q * scale
+    }
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkcdStaticMeshTreeBaseSection {
+    pub nodes: hkArray<[u8; 4]>,
+
+    #[br(map = Vec4::from_array)]
+    pub domain_min: Vec4,
+    #[br(map = Vec4::from_array)]
+    pub domain_max: Vec4,
+
+    /// Packed vertex offset (xyz) followed by scale (xyz)
+    pub codec_parms: [f32; 6],
+    pub first_packed_vertex: u32,
+    /// Lower 8 bits are the count, upper 24 bits the offset into the shared vertex index table
+    pub shared_vertices: u32,
+    /// Lower 8 bits are the count, upper 24 bits the index of the first primitive
+    pub primitives: u32,
+    pub data_runs: u32,
+    pub num_packed_vertices: u8,
+    pub num_shared_indices: u8,
+    pub leaf_index: u16,
+    pub page: u8,
+    pub flags: u8,
+    pub layer_data: u8,
+    pub unused_data: u8,
+}
+
+impl hkcdStaticMeshTreeBaseSection {
+    /// Decompresses a packed vertex, which is quantized to 11/11/10 bits over the domain of this section
+    pub fn decompress_packed_vertex(&self, v: u32) -> Vec3 {
+        let offset = Vec3::from_slice(&self.codec_parms[0..3]);
+        let scale = Vec3::from_slice(&self.codec_parms[3..6]);
+
+        let q = Vec3::new(
+            (v & 0x7ff) as f32,
+            ((v >> 11) & 0x7ff) as f32,
+            ((v >> 22) & 0x3ff) as f32,
+        );
+
+        offset + q * scale
+    }
+
+    pub fn primitive_range(&self) -> std::ops::Range<usize> {
+        let start = (self.primitives >> 8) as usize;
+        start..start + (self.primitives & 0xff) as usize
+    }
+
+    pub fn shared_vertex_offset(&self) -> usize {
+        (self.shared_vertices >> 8) as usize
+    }
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy)]
+pub struct hkcdStaticMeshTreeBasePrimitive {
+    pub indices: [u8; 4],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PrimitiveType {
+    Invalid,
+    Triangle,
+    Quad,
+    Custom,
+}
+
+impl hkcdStaticMeshTreeBasePrimitive {
+    pub fn primitive_type(&self) -> PrimitiveType {
+        let [a, b, c, d] = self.indices;
+        if a == b && b == c && c == d {
+            PrimitiveType::Invalid
+        } else if a == b {
+            PrimitiveType::Custom
+        } else if c == d {
+            PrimitiveType::Triangle
+        } else {
+            PrimitiveType::Quad
+        }
+    }
+
+    /// Local vertex indices of the triangles making up this primitive
+    pub fn triangles(&self) -> Vec<[u8; 3]> {
+        let [a, b, c, d] = self.indices;
+        match self.primitive_type() {
+            PrimitiveType::Triangle => vec![[a, b, c]],
+            PrimitiveType::Quad => vec![[a, b, c], [a, c, d]],
+            PrimitiveType::Invalid | PrimitiveType::Custom => vec![],
+        }
+    }
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpBvCompressedMeshShapeTreeDataRun {
+    pub value: u32,
+    pub index: u8,
+    pub count: u8,
+    pub unk6: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinReaderExt;
+
+    use super::*;
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn section(
+        codec_parms: [f32; 6],
+        shared_vertices: u32,
+        primitives: u32,
+    ) -> hkcdStaticMeshTreeBaseSection {
+        let mut data = vec![0u8; 16 + 32];
+        data.extend(f32_bytes(&codec_parms));
+        data.extend(0u32.to_le_bytes());
+        data.extend(shared_vertices.to_le_bytes());
+        data.extend(primitives.to_le_bytes());
+        data.extend([0u8; 12]);
+
+        Cursor::new(data).read_le().unwrap()
+    }
+
+    #[test]
+    fn packed_vertex() {
+        let section = section([1.0, 2.0, 3.0, 0.5, 0.25, 2.0], 0, 0);
+        let v = 10 | (20 << 11) | (30 << 22);
+        assert_eq!(
+            section.decompress_packed_vertex(v),
+            Vec3::new(6.0, 7.0, 63.0)
+        );
+        assert_eq!(
+            section.decompress_packed_vertex(u32::MAX),
+            Vec3::new(
+                1.0 + 0x7ff as f32 * 0.5,
+                2.0 + 0x7ff as f32 * 0.25,
+                3.0 + 0x3ff as f32 * 2.0
+            )
+        );
+    }
+
+    #[test]
+    fn section_ranges() {
+        let section = section([0.0; 6], (7 << 8) | 2, (5 << 8) | 3);
+        assert_eq!(section.primitive_range(), 5..8);
+        assert_eq!(section.shared_vertex_offset(), 7);
+    }
+
+    #[test]
+    fn shared_vertex() {
+        let mut data = vec![0u8; 16];
+        data.extend(f32_bytes(&[-1.0, -1.0, -1.0, 0.0]));
+        data.extend(f32_bytes(&[
+            0x1fffff as f32 - 1.0,
+            0x1fffff as f32 - 1.0,
+            0x3fffff as f32 - 1.0,
+            0.0,
+        ]));
+        data.extend([0u8; 16 + 6 * 16]);
+        let tree: hkpBvCompressedMeshShapeTree = Cursor::new(data).read_le().unwrap();
+
+        let v = 100 | (200 << 21) | (300 << 42);
+        assert_eq!(
+            tree.decompress_shared_vertex(v),
+            Vec3::new(99.0, 199.0, 299.0)
+        );
+    }
+
+    #[test]
+    fn primitive_triangles() {
+        let primitive = |indices| hkcdStaticMeshTreeBasePrimitive { indices };
+
+        let triangle = primitive([0, 1, 2, 2]);
+        assert_eq!(triangle.primitive_type(), PrimitiveType::Triangle);
+        assert_eq!(triangle.triangles(), [[0, 1, 2]]);
+
+        let quad = primitive([0, 1, 2, 3]);
+        assert_eq!(quad.primitive_type(), PrimitiveType::Quad);
+        assert_eq!(quad.triangles(), [[0, 1, 2], [0, 2, 3]]);
+
+        assert_eq!(
+            primitive([1, 1, 2, 3]).primitive_type(),
+            PrimitiveType::Custom
+        );
+        assert_eq!(
+            primitive([4, 4, 4, 4]).primitive_type(),
+            PrimitiveType::Invalid
+        );
+        assert!(primitive([4, 4, 4, 4]).triangles().is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 use binrw::binread;
 use glam::{Vec3, Vec4};
 
-use super::hkArrayIndex;
+use super::{hkArray, hkPointer};
 
 #[binread]
 #[derive(Debug)]
@@ -13,12 +13,44 @@ pub struct hkpConvexVerticesShape {
     #[br(map = Vec4::from_array)]
     pub aabb_center: Vec4,
 
-    pub rotated_vertices: hkArrayIndex,
+    pub rotated_vertices: hkArray<hkFourTransposedPoints>,
 
-    pub unk70: [u64; 2],
+    /// Number of vertices in `rotated_vertices`, the last block is padded up to four
+    pub num_vertices: i32,
+    pub unk64: u32,
 
-    pub plane_equations: hkArrayIndex,
-    // pub connectivity: hkpConvexVerticesConnectivity,
+    /// One plane per face, normal in xyz and the negated distance from the origin in w
+    pub plane_equations: hkArray<[f32; 4]>,
+
+    /// Optional `hkpConvexVerticesConnectivity`, null if the shape was stored without faces
+    pub connectivity: hkPointer,
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpConvexVerticesConnectivity {
+    pub unk0: [u64; 2],
+
+    /// Vertex indices of every face, laid out back to back
+    pub vertex_indices: hkArray<u16>,
+    pub num_vertices_per_face: hkArray<u8>,
+}
+
+impl hkpConvexVerticesConnectivity {
+    /// Splits the flat index list into faces, returning `None` if the face sizes exceed the index count
+    pub fn faces<'a>(
+        vertex_indices: &'a [u16],
+        num_vertices_per_face: &[u8],
+    ) -> Option<Vec<&'a [u16]>> {
+        let mut faces = Vec::with_capacity(num_vertices_per_face.len());
+        let mut offset = 0;
+        for &count in num_vertices_per_face {
+            faces.push(vertex_indices.get(offset..offset + count as usize)?);
+            offset += count as usize;
+        }
+
+        Some(faces)
+    }
 }
 
 #[binread]
@@ -38,3 +70,23 @@ impl hkFourTransposedPoints {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connectivity_faces() {
+        let indices = [0, 1, 2, 3, 0, 3, 4];
+        let faces = hkpConvexVerticesConnectivity::faces(&indices, &[4, 3]).unwrap();
+        assert_eq!(faces, [&[0, 1, 2, 3][..], &[0, 3, 4]]);
+
+        // Face sizes that need more indices than there are
+        assert!(hkpConvexVerticesConnectivity::faces(&indices, &[4, 4]).is_none());
+        assert!(hkpConvexVerticesConnectivity::faces(&[], &[3]).is_none());
+        assert_eq!(
+            hkpConvexVerticesConnectivity::faces(&indices, &[]),
+            Some(vec![])
+        );
+    }
+}
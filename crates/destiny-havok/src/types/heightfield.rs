@@ -0,0 +1,227 @@
+use binrw::binread;
+use glam::{Vec3, Vec4};
+
+use super::{bvtree::BvTreeType, hkArray, hkPointer};
+use crate::shape_collection::Shape;
+
+#[binread]
+#[br(repr = u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeightFieldType {
+    Storage = 0,
+    Compressed = 1,
+    User = 2,
+}
+
+/// Common base of the sampled heightfield shapes
+#[binread]
+#[derive(Debug)]
+pub struct hkpSampledHeightFieldShape {
+    pub unk0: [u32; 10],
+
+    pub x_res: i32,
+    pub z_res: i32,
+    pub height_center: f32,
+    pub use_projection_based_height: u8,
+    pub heightfield_type: HeightFieldType,
+    pub unk36: [u8; 10],
+
+    /// Scale from grid space (x, height, z) to shape space
+    #[br(map = Vec4::from_array)]
+    pub int_to_float_scale: Vec4,
+    #[br(map = Vec4::from_array)]
+    pub float_to_int_scale: Vec4,
+    #[br(map = Vec4::from_array)]
+    pub float_to_int_offset_floor_corrected: Vec4,
+    #[br(map = Vec4::from_array)]
+    pub extents: Vec4,
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpCompressedSampledHeightFieldShape {
+    pub base: hkpSampledHeightFieldShape,
+
+    /// Quantized heights, `storage * scale + offset`
+    pub storage: hkArray<u16>,
+    pub triangle_flip: u8,
+    pub unk91: [u8; 3],
+    pub offset: f32,
+    pub scale: f32,
+    pub unk9c: u32,
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpStorageSampledHeightFieldShape {
+    pub base: hkpSampledHeightFieldShape,
+
+    pub storage: hkArray<f32>,
+    pub triangle_flip: u8,
+    pub unk91: [u8; 15],
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpTriSampledHeightFieldCollection {
+    pub unk0: [u32; 10],
+    pub unk28: u64,
+    pub disable_welding: u8,
+    pub collection_type: u8,
+    pub unk32: [u8; 6],
+
+    /// Points to a sampled heightfield shape
+    pub heightfield: hkPointer,
+    pub child_size: i32,
+    pub radius: f32,
+    pub welding_info: hkArray<u16>,
+    pub unk58: u64,
+
+    #[br(map = Vec4::from_array)]
+    pub triangle_extrusion: Vec4,
+}
+
+#[binread]
+#[derive(Debug)]
+pub struct hkpTriSampledHeightFieldBvTreeShape {
+    pub unk0: [u32; 10],
+
+    pub tree_type: BvTreeType,
+
+    pub unk30: u64,
+    /// Points to a `hkpTriSampledHeightFieldCollection`
+    pub child: hkPointer,
+    pub child_size: i32,
+    pub want_aabb_rejection_test: u8,
+    pub unk45: [u8; 11],
+}
+
+/// Decoded heightfield samples, indexed as `heights[x * z_res + z]`
+#[derive(Debug, Clone)]
+pub struct HeightField {
+    pub x_res: usize,
+    pub z_res: usize,
+    pub heights: Vec<f32>,
+    /// Scale from grid space (x, height, z) to shape space
+    pub scale: Vec3,
+    /// Splits cells along the other diagonal
+    pub triangle_flip: bool,
+}
+
+impl HeightField {
+    pub fn height(&self, x: usize, z: usize) -> f32 {
+        self.heights[x * self.z_res + z]
+    }
+
+    /// Position of a sample in shape space
+    pub fn vertex(&self, x: usize, z: usize) -> Vec3 {
+        Vec3::new(x as f32, self.height(x, z), z as f32) * self.scale
+    }
+
+    /// Triangulates the grid into two triangles per cell, facing up
+    pub fn to_shape(&self) -> Shape {
+        let mut shape = Shape::default();
+        if self.x_res < 2 || self.z_res < 2 {
+            return shape;
+        }
+
+        for x in 0..self.x_res {
+            for z in 0..self.z_res {
+                shape.vertices.push(self.vertex(x, z));
+            }
+        }
+
+        let index = |x: usize, z: usize| (x * self.z_res + z) as u32;
+        for x in 0..self.x_res - 1 {
+            for z in 0..self.z_res - 1 {
+                let (v00, v10, v01, v11) = (
+                    index(x, z),
+                    index(x + 1, z),
+                    index(x, z + 1),
+                    index(x + 1, z + 1),
+                );
+
+                if self.triangle_flip {
+                    shape.indices.extend([v00, v01, v10, v10, v01, v11]);
+                } else {
+                    shape.indices.extend([v00, v11, v10, v00, v01, v11]);
+                }
+            }
+        }
+
+        shape
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heightfield(triangle_flip: bool) -> HeightField {
+        HeightField {
+            x_res: 3,
+            z_res: 2,
+            heights: vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0],
+            scale: Vec3::new(2.0, 0.5, 4.0),
+            triangle_flip,
+        }
+    }
+
+    fn normals(shape: &Shape) -> Vec<Vec3> {
+        shape
+            .indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|i| shape.vertices[t[i] as usize]);
+                (b - a).cross(c - a)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples() {
+        let heightfield = heightfield(false);
+        assert_eq!(heightfield.height(1, 1), 3.0);
+        assert_eq!(heightfield.vertex(2, 1), Vec3::new(4.0, 2.5, 4.0));
+    }
+
+    #[test]
+    fn triangulation() {
+        for triangle_flip in [false, true] {
+            let shape = heightfield(triangle_flip).to_shape();
+            assert_eq!(shape.vertices.len(), 6);
+            assert_eq!(shape.vertices[3], Vec3::new(2.0, 1.5, 4.0));
+
+            // Two cells with two triangles each
+            assert_eq!(shape.indices.len(), 2 * 2 * 3);
+            assert!(shape.indices.iter().all(|&i| i < 6));
+            assert!(normals(&shape).iter().all(|n| n.y > 0.0));
+        }
+
+        // The cells are split along different diagonals
+        let diagonal = |shape: Shape| {
+            shape.indices[..6]
+                .iter()
+                .copied()
+                .filter(|&i| i == 0)
+                .count()
+        };
+        assert_eq!(diagonal(heightfield(false).to_shape()), 2);
+        assert_eq!(diagonal(heightfield(true).to_shape()), 1);
+    }
+
+    #[test]
+    fn degenerate_grid() {
+        let heightfield = HeightField {
+            x_res: 1,
+            z_res: 4,
+            heights: vec![0.0; 4],
+            scale: Vec3::ONE,
+            triangle_flip: false,
+        };
+
+        let shape = heightfield.to_shape();
+        assert!(shape.vertices.is_empty());
+        assert!(shape.indices.is_empty());
+    }
+}
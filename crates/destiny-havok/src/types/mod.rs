@@ -1,10 +1,195 @@
 #![allow(non_camel_case_types)]
 
+pub mod box_shape;
 pub mod bvtree;
+pub mod collision_filter;
 pub mod compound_shape;
+pub mod compressed_mesh;
 pub mod convex_vertices;
+pub mod heightfield;
+pub mod navmesh;
 pub mod unknown;
 
-// cohae: Temporary types until we have a custom reader to handle inline arrays
-pub type hkArrayIndex = u64;
-pub type hkPointerIndex = u64;
+use std::{
+    fmt,
+    io::{Read, Seek, SeekFrom},
+    marker::PhantomData,
+};
+
+use anyhow::Context;
+use binrw::{BinRead, BinReaderExt, BinResult, Endian, VecArgs};
+
+use crate::{error::HavokError, index::IndexItem, tagfile::HavokTagFile};
+
+/// A pointer to another item.
+///
+/// The file stores the index of the referenced item. When the tagfile has a patch table, only the offsets it lists
+/// are pointers, and the index is read from there at the pointer size of the file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct hkPointer {
+    /// Index of the referenced item, 0 for null pointers
+    pub index: u64,
+    /// Absolute offset of the pointer in the file
+    pub offset: u64,
+}
+
+impl hkPointer {
+    /// Index of the referenced item, `None` for null pointers.
+    ///
+    /// Fails with [`HavokError::UnpatchedPointer`] if the file has a patch table that doesn't list this pointer,
+    /// but the field isn't zero either.
+    pub fn resolve(&self, tagfile: &HavokTagFile) -> Result<Option<u64>, HavokError> {
+        let index = if tagfile.has_patches() {
+            match tagfile.pointer_at(self.offset) {
+                Some(index) => index,
+                None if self.index == 0 => 0,
+                None => {
+                    return Err(HavokError::UnpatchedPointer {
+                        offset: self.offset,
+                        index: self.index,
+                    })
+                }
+            }
+        } else {
+            self.index
+        };
+
+        Ok(Some(index).filter(|&i| i != 0))
+    }
+
+    pub fn is_null(&self, tagfile: &HavokTagFile) -> bool {
+        matches!(self.resolve(tagfile), Ok(None))
+    }
+
+    /// Reads the referenced item as `T`, returning `None` for null pointers
+    pub fn read<T>(
+        &self,
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+    ) -> anyhow::Result<Option<T>>
+    where
+        T: for<'a> BinRead<Args<'a> = ()> + 'static,
+    {
+        let Some(index) = self.resolve(tagfile)? else {
+            return Ok(None);
+        };
+
+        let item = tagfile.require_item(index, "Pointer")?;
+        f.seek(SeekFrom::Start(item.offset as u64))?;
+        Ok(Some(f.read_type(tagfile.endian)?))
+    }
+}
+
+impl BinRead for hkPointer {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _: ()) -> BinResult<Self> {
+        let offset = reader.stream_position()?;
+        Ok(hkPointer {
+            index: u64::read_options(reader, endian, ())?,
+            offset,
+        })
+    }
+}
+
+impl fmt::Debug for hkPointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hkPointer({})", self.index)
+    }
+}
+
+/// An array whose elements are stored in a separate item
+pub struct hkArray<T> {
+    pub data: hkPointer,
+    /// Size and capacity as stored in the file. The count of the referenced item is what's actually used
+    pub size: u32,
+    pub capacity_and_flags: u32,
+    _marker: PhantomData<T>,
+}
+
+impl<T> hkArray<T> {
+    /// The item holding the elements, `None` for empty arrays
+    pub fn item<'t>(&self, tagfile: &'t HavokTagFile) -> anyhow::Result<Option<&'t IndexItem>> {
+        match self.data.resolve(tagfile)? {
+            Some(index) => Ok(Some(tagfile.require_item(index, "Array")?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn len(&self, tagfile: &HavokTagFile) -> usize {
+        self.item(tagfile)
+            .ok()
+            .flatten()
+            .map_or(0, |item| item.count as usize)
+    }
+
+    pub fn is_empty(&self, tagfile: &HavokTagFile) -> bool {
+        self.len(tagfile) == 0
+    }
+}
+
+impl<T> hkArray<T>
+where
+    T: for<'a> BinRead<Args<'a> = ()> + 'static,
+{
+    /// Reads every element of the array, restoring the stream position afterwards
+    pub fn read(
+        &self,
+        tagfile: &HavokTagFile,
+        f: &mut (impl Read + Seek),
+    ) -> anyhow::Result<Vec<T>> {
+        let Some(item) = self.item(tagfile)? else {
+            return Ok(vec![]);
+        };
+
+        // Every element takes up at least one byte, which rules out absurd counts before allocating anything
+        tagfile.ensure_data_range(item.offset as u64, item.count as u64)?;
+
+        let pos = f.stream_position()?;
+        f.seek(SeekFrom::Start(item.offset as u64))?;
+        let elements = f
+            .read_type_args(
+                tagfile.endian,
+                VecArgs {
+                    count: item.count as usize,
+                    inner: (),
+                },
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to read {} array elements at 0x{:x}",
+                    item.count, item.offset
+                )
+            })?;
+        f.seek(SeekFrom::Start(pos))?;
+
+        Ok(elements)
+    }
+}
+
+impl<T> BinRead for hkArray<T> {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _: ()) -> BinResult<Self> {
+        Ok(hkArray {
+            data: hkPointer::read_options(reader, endian, ())?,
+            size: u32::read_options(reader, endian, ())?,
+            capacity_and_flags: u32::read_options(reader, endian, ())?,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T> fmt::Debug for hkArray<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hkArray<{}>({})",
+            std::any::type_name::<T>()
+                .rsplit("::")
+                .next()
+                .unwrap_or_default(),
+            self.data.index
+        )
+    }
+}
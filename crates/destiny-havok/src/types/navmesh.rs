@@ -0,0 +1,77 @@
+use binrw::binread;
+use bitflags::bitflags;
+use glam::Vec4;
+
+use super::hkArray;
+
+#[binread]
+#[derive(Debug)]
+pub struct hkaiNavMesh {
+    pub unk0: [u64; 2],
+
+    pub faces: hkArray<hkaiNavMeshFace>,
+    pub edges: hkArray<hkaiNavMeshEdge>,
+    pub vertices: hkArray<[f32; 4]>,
+    pub streaming_sets: hkArray<u8>,
+
+    /// `face_data_striding` values per face
+    pub face_data: hkArray<i32>,
+    /// `edge_data_striding` values per edge
+    pub edge_data: hkArray<i32>,
+    pub face_data_striding: i32,
+    pub edge_data_striding: i32,
+
+    pub flags: u8,
+    pub unk79: [u8; 7],
+
+    #[br(map = Vec4::from_array)]
+    pub aabb_min: Vec4,
+    #[br(map = Vec4::from_array)]
+    pub aabb_max: Vec4,
+
+    pub erosion_radius: f32,
+    pub unka4: u32,
+    pub user_data: u64,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy)]
+pub struct hkaiNavMeshFace {
+    pub start_edge_index: i32,
+    pub start_user_edge_index: i32,
+    pub num_edges: i16,
+    pub num_user_edges: i16,
+    pub cluster_index: i16,
+    pub padding: u16,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy)]
+pub struct hkaiNavMeshEdge {
+    pub a: i32,
+    pub b: i32,
+    /// -1 for boundary edges
+    pub opposite_edge: u32,
+    /// -1 for boundary edges
+    pub opposite_face: u32,
+
+    #[br(map = NavMeshEdgeFlags::from_bits_retain)]
+    pub flags: NavMeshEdgeFlags,
+    pub padding: u8,
+    /// hkHalf16
+    pub user_edge_cost: u16,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct NavMeshEdgeFlags: u8 {
+        const SILHOUETTE = 0x1;
+        const RETRIANGULATED = 0x2;
+        const ORIGINAL = 0x4;
+        const OPPOSITE_EDGE_UNLOADED = 0x8;
+        const USER = 0x10;
+        const BLOCKED = 0x20;
+        /// The opposite face lives in another navmesh section
+        const EXTERNAL_OPPOSITE = 0x40;
+    }
+}
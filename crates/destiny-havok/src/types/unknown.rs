@@ -3,14 +3,14 @@
 use binrw::binread;
 use glam::Vec4;
 
-use super::{hkArrayIndex, hkPointerIndex};
+use super::{collision_filter::CollisionFilterInfo, hkArray, hkPointer};
 
 #[binread]
 #[derive(Debug)]
 pub struct Unk81 {
     pub unk0: [u64; 7],
-    pub unk38: hkArrayIndex,
-    pub unk40: [u64; 2],
+    pub unk38: hkArray<Unk84>,
+    pub unk48: u64,
 
     #[br(map = Vec4::from_array)]
     pub unk50: Vec4,
@@ -20,9 +20,15 @@ pub struct Unk81 {
     pub unk70: [u64; 4],
 }
 
+/// Child of a list shape, matches `hkpListShape::ChildInfo`
 #[binread]
 #[derive(Debug)]
 pub struct Unk84 {
-    pub shape: hkPointerIndex,
-    pub unk8: [u64; 3],
+    pub shape: hkPointer,
+    pub collision_filter_info: CollisionFilterInfo,
+    /// Material of the child
+    pub shape_info: u16,
+    pub shape_size: u16,
+    pub num_child_shapes: i32,
+    pub unk14: [u32; 3],
 }
@@ -0,0 +1,201 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use binrw::{BinRead, BinReaderExt, VecArgs};
+
+use crate::{
+    navmesh::NavMesh,
+    reflection::ReflectedValue,
+    shape_collection::ShapeHierarchy,
+    tagfile::HavokTagFile,
+    type_registry::TypeCompendium,
+    types::{hkArray, hkPointer},
+};
+
+/// A tagfile backed by a borrowed byte slice.
+///
+/// The section tree and item index are built once when the view is created, item contents are only decoded when
+/// they are requested. Nothing is copied out of the slice until then, which makes this the cheapest way to scan
+/// large numbers of tags.
+pub struct HavokTagFileView<'a> {
+    pub tagfile: HavokTagFile,
+    data: &'a [u8],
+}
+
+impl<'a> HavokTagFileView<'a> {
+    pub fn new(data: &'a [u8]) -> anyhow::Result<Self> {
+        Self::with_compendium(data, None)
+    }
+
+    /// Like [`Self::new`], using the given compendium for type information when the file doesn't contain a TYPE section
+    pub fn with_compendium(
+        data: &'a [u8],
+        compendium: Option<&TypeCompendium>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            tagfile: HavokTagFile::from_bytes_with_compendium(data, compendium)?,
+            data,
+        })
+    }
+
+    /// The whole file
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// A cursor over the whole file, for use with the stream based readers
+    pub fn cursor(&self) -> Cursor<&'a [u8]> {
+        Cursor::new(self.data)
+    }
+
+    /// Raw bytes of an item, see [`HavokTagFile::item_extent`]
+    pub fn item_data(&self, index: u64) -> Option<&'a [u8]> {
+        let extent = self.tagfile.item_extent(index)?;
+        self.data.get(extent.start as usize..extent.end as usize)
+    }
+
+    /// Decodes the first element of an item as `T`
+    pub fn read_item<T>(&self, index: u64) -> anyhow::Result<T>
+    where
+        T: for<'b> BinRead<Args<'b> = ()> + 'static,
+    {
+        let item = self.tagfile.require_item(index, "Item")?;
+
+        let mut cursor = self.cursor();
+        cursor.set_position(item.offset as u64);
+        cursor
+            .read_type(self.tagfile.endian)
+            .with_context(|| format!("Failed to read item {index}"))
+    }
+
+    /// Decodes every element of an item as `T`
+    pub fn read_item_array<T>(&self, index: u64) -> anyhow::Result<Vec<T>>
+    where
+        T: for<'b> BinRead<Args<'b> = ()> + 'static,
+    {
+        let item = self.tagfile.require_item(index, "Item")?;
+
+        // Every element takes up at least one byte, which rules out absurd counts before allocating anything
+        self.tagfile
+            .ensure_data_range(item.offset as u64, item.count as u64)?;
+
+        let mut cursor = self.cursor();
+        cursor.set_position(item.offset as u64);
+        cursor
+            .read_type_args(
+                self.tagfile.endian,
+                VecArgs {
+                    count: item.count as usize,
+                    inner: (),
+                },
+            )
+            .with_context(|| format!("Failed to read item {index}"))
+    }
+
+    /// Decodes the item referenced by a pointer, `None` for null pointers
+    pub fn read_pointer<T>(&self, pointer: &hkPointer) -> anyhow::Result<Option<T>>
+    where
+        T: for<'b> BinRead<Args<'b> = ()> + 'static,
+    {
+        pointer.read(&self.tagfile, &mut self.cursor())
+    }
+
+    pub fn read_array<T>(&self, array: &hkArray<T>) -> anyhow::Result<Vec<T>>
+    where
+        T: for<'b> BinRead<Args<'b> = ()> + 'static,
+    {
+        array.read(&self.tagfile, &mut self.cursor())
+    }
+
+    pub fn read_item_reflected(&self, index: u64) -> anyhow::Result<ReflectedValue> {
+        self.tagfile.read_item_reflected(&mut self.cursor(), index)
+    }
+
+    pub fn read_shape_hierarchy(&self) -> anyhow::Result<ShapeHierarchy> {
+        ShapeHierarchy::read_collection(&self.tagfile, &mut self.cursor())
+    }
+
+    pub fn read_navmeshes(&self) -> anyhow::Result<Vec<NavMesh>> {
+        NavMesh::read_all(&self.tagfile, &mut self.cursor())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{
+        shape_collection::{read_shape_hierarchy, Shape},
+        writer::{write_shape_collection, ShapeSource},
+    };
+
+    fn collection() -> Vec<u8> {
+        let triangle = Shape {
+            vertices: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+            indices: vec![0, 1, 2],
+        };
+        let corners = (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32))
+            .collect::<Vec<_>>();
+
+        let mut data = vec![];
+        write_shape_collection(
+            &mut data,
+            &[
+                ShapeSource::Mesh(&triangle),
+                ShapeSource::ConvexVertices(&corners),
+            ],
+        )
+        .unwrap();
+        data
+    }
+
+    #[test]
+    fn matches_tagfile() {
+        let data = collection();
+        let view = HavokTagFileView::new(&data).unwrap();
+        let tagfile = HavokTagFile::from_bytes(&data).unwrap();
+
+        let items = |t: &HavokTagFile| {
+            t.items
+                .iter()
+                .map(|it| (it.typ, it.offset, it.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(items(&view.tagfile), items(&tagfile));
+        assert_eq!(view.tagfile.pointers, tagfile.pointers);
+
+        let root = tagfile.find_item("s_hkpShape_array").unwrap();
+        let extent = tagfile.item_extent(root).unwrap();
+        assert_eq!(
+            view.item_data(root),
+            Some(&data[extent.start as usize..extent.end as usize])
+        );
+
+        let from_view = view.read_shape_hierarchy().unwrap();
+        let from_stream = read_shape_hierarchy(&mut Cursor::new(&data), None).unwrap();
+        assert_eq!(from_view.roots, from_stream.roots);
+        assert_eq!(from_view.item_nodes, from_stream.item_nodes);
+        for &root in &from_view.roots {
+            let (a, b) = (from_view.flatten(root), from_stream.flatten(root));
+            assert_eq!(
+                from_view.node(root).class_name,
+                from_stream.node(root).class_name
+            );
+            assert_eq!((a.vertices, a.indices), (b.vertices, b.indices));
+        }
+    }
+
+    #[test]
+    fn truncated_input() {
+        let data = collection();
+        for len in 0..data.len() {
+            assert!(
+                HavokTagFileView::new(&data[..len]).is_err(),
+                "Reading {len} of {} bytes succeeded",
+                data.len()
+            );
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::{collections::BTreeMap, io::Write};
+
+use glam::{Vec3, Vec4};
+
+use crate::{
+    index::ItemFlags,
+    section::{TagSection, TagSectionSignature},
+    shape_collection::Shape,
+    type_registry::{HkType, TypeRegistry},
+};
+
+pub const DEFAULT_SDK_VERSION: &str = "20160100";
+
+/// Set on the capacity of arrays that don't own their storage, which is the case for every serialized array
+const ARRAY_DONT_DEALLOCATE: u32 = 0x80000000;
+
+/// Builds a tagfile one item at a time.
+///
+/// Items are written little endian, with pointers stored as the index of the item they point to and listed in the
+/// PTCH section. The file gets a TYPE section with just the type names, so it can be read back without a
+/// compendium, unless `compendium_id` is set.
+pub struct TagFileWriter {
+    pub sdk_version: String,
+    /// Writes a TCRF section referencing this compendium instead of a TYPE section, like the files shipped with
+    /// Destiny 2. Reading the file back then needs a compendium with the types from [`Self::types`]
+    pub compendium_id: Option<u64>,
+
+    types: TypeRegistry,
+    data: Vec<u8>,
+    /// Type, flags, offset relative to the DATA section and element count of every item, including the null item
+    items: Vec<(u32, ItemFlags, u32, u32)>,
+    /// Offsets of pointers relative to the DATA section, grouped by the type of the item containing them
+    patches: BTreeMap<u32, Vec<u32>>,
+}
+
+/// Contents of an item, along with the location of the pointers inside it
+#[derive(Default)]
+pub struct ItemData {
+    bytes: Vec<u8>,
+    pointers: Vec<usize>,
+}
+
+impl ItemData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.bytes.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn i32(&mut self, v: i32) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn f32(&mut self, v: f32) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn vec4(&mut self, v: Vec4) -> &mut Self {
+        for c in v.to_array() {
+            self.f32(c);
+        }
+        self
+    }
+
+    pub fn zeros(&mut self, count: usize) -> &mut Self {
+        self.bytes.resize(self.bytes.len() + count, 0);
+        self
+    }
+
+    /// A pointer to the given item, or a null pointer
+    pub fn pointer(&mut self, item: Option<u64>) -> &mut Self {
+        if item.is_some() {
+            self.pointers.push(self.bytes.len());
+        }
+        self.u64(item.unwrap_or(0))
+    }
+
+    /// An array stored in the given item, as returned by [`TagFileWriter::add_array`]
+    pub fn array(&mut self, item: Option<u64>, count: usize) -> &mut Self {
+        self.pointer(item)
+            .u32(count as u32)
+            .u32(count as u32 | ARRAY_DONT_DEALLOCATE)
+    }
+}
+
+impl Default for TagFileWriter {
+    fn default() -> Self {
+        Self {
+            sdk_version: DEFAULT_SDK_VERSION.to_string(),
+            compendium_id: None,
+            types: TypeRegistry {
+                types: vec![HkType::default()],
+                has_layouts: false,
+            },
+            data: vec![],
+            items: vec![(0, ItemFlags::empty(), 0, 0)],
+            patches: BTreeMap::new(),
+        }
+    }
+}
+
+impl TagFileWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index the next added item will get, for items that reference items added after them
+    pub fn next_index(&self) -> u64 {
+        self.items.len() as u64
+    }
+
+    /// Types of the items added so far
+    pub fn types(&self) -> &TypeRegistry {
+        &self.types
+    }
+
+    fn type_index(&mut self, name: &str) -> u32 {
+        self.types.find(name).unwrap_or_else(|| {
+            self.types.types.push(HkType {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            self.types.types.len() as u32 - 1
+        })
+    }
+
+    fn push_item(
+        &mut self,
+        type_name: &str,
+        flags: ItemFlags,
+        count: usize,
+        data: &ItemData,
+    ) -> anyhow::Result<u64> {
+        let typ = self.type_index(type_name);
+
+        self.data.resize(self.data.len().next_multiple_of(16), 0);
+        let offset = u32::try_from(self.data.len()).map_err(|_| {
+            anyhow::anyhow!("Data section exceeds 4GB while writing a {type_name} item")
+        })?;
+
+        self.data.extend_from_slice(&data.bytes);
+        self.patches
+            .entry(typ)
+            .or_default()
+            .extend(data.pointers.iter().map(|&p| offset + p as u32));
+
+        self.items.push((typ, flags, offset, count as u32));
+        Ok(self.items.len() as u64 - 1)
+    }
+
+    /// Adds a single object, returning the index of its item
+    pub fn add_item(&mut self, type_name: &str, data: &ItemData) -> anyhow::Result<u64> {
+        self.push_item(type_name, ItemFlags::POINTER, 1, data)
+    }
+
+    /// Adds the elements of an array, laid out back to back in `data`.
+    /// Returns `None` for empty arrays, which are stored as null pointers.
+    pub fn add_array(
+        &mut self,
+        type_name: &str,
+        count: usize,
+        data: &ItemData,
+    ) -> anyhow::Result<Option<u64>> {
+        if count == 0 {
+            return Ok(None);
+        }
+
+        self.push_item(type_name, ItemFlags::ARRAY, count, data)
+            .map(Some)
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> anyhow::Result<()> {
+        let mut items = vec![];
+        for &(typ, flags, offset, count) in &self.items {
+            items.extend_from_slice(&(typ | (flags.bits() << 24)).to_le_bytes());
+            items.extend_from_slice(&offset.to_le_bytes());
+            items.extend_from_slice(&count.to_le_bytes());
+        }
+
+        let mut patches = vec![];
+        for (typ, offsets) in self.patches.iter().filter(|(_, o)| !o.is_empty()) {
+            patches.extend_from_slice(&typ.to_le_bytes());
+            patches.extend_from_slice(&(offsets.len() as u32).to_le_bytes());
+            for offset in offsets {
+                patches.extend_from_slice(&offset.to_le_bytes());
+            }
+        }
+
+        let mut index = vec![];
+        TagSection::write(&mut index, &TagSectionSignature::IndexItem, true, &items)?;
+        TagSection::write(&mut index, &TagSectionSignature::Ptch, true, &patches)?;
+
+        let mut sdk_version = self.sdk_version.clone().into_bytes();
+        sdk_version.resize(sdk_version.len().next_multiple_of(4), 0);
+
+        let mut data = self.data.clone();
+        data.resize(data.len().next_multiple_of(16), 0);
+
+        let mut contents = vec![];
+        TagSection::write(
+            &mut contents,
+            &TagSectionSignature::SdkVersion,
+            true,
+            &sdk_version,
+        )?;
+        TagSection::write(&mut contents, &TagSectionSignature::Data, true, &data)?;
+        match self.compendium_id {
+            Some(id) => TagSection::write(
+                &mut contents,
+                &TagSectionSignature::Tcrf,
+                true,
+                &id.to_le_bytes(),
+            )?,
+            None => TagSection::write(
+                &mut contents,
+                &TagSectionSignature::Type,
+                false,
+                &self.types.write_type_names_section()?,
+            )?,
+        }
+        TagSection::write(&mut contents, &TagSectionSignature::Index, false, &index)?;
+
+        let mut out = vec![];
+        TagSection::write(&mut out, &TagSectionSignature::Tag0, false, &contents)?;
+        w.write_all(&out)?;
+
+        Ok(())
+    }
+}
+
+/// A shape to be written by [`write_shape_collection`]
+pub enum ShapeSource<'a> {
+    /// Written as a convex vertices shape with one face per triangle, so the triangles read back unchanged.
+    ///
+    /// Havok treats the shape as the convex hull of its vertices, so the mesh has to be convex with outward facing
+    /// triangles. Anything else is rejected.
+    Mesh(&'a Shape),
+    /// Written as a convex vertices shape without faces, readers reconstruct the convex hull
+    ConvexVertices(&'a [Vec3]),
+}
+
+/// Writes a tagfile with a shape collection (`s_hkpShape_array`) that [`crate::shape_collection::ShapeHierarchy`]
+/// can read back
+pub fn write_shape_collection(
+    w: &mut impl Write,
+    shapes: &[ShapeSource<'_>],
+) -> anyhow::Result<()> {
+    let mut writer = TagFileWriter::new();
+
+    let mut entries = ItemData::new();
+    for (i, shape) in shapes.iter().enumerate() {
+        let item = match shape {
+            ShapeSource::Mesh(shape) => {
+                anyhow::ensure!(
+                    !shape.indices.is_empty() && shape.indices.len() % 3 == 0,
+                    "Shape {i} has no complete triangles"
+                );
+                add_convex_vertices_shape(&mut writer, &shape.vertices, Some(&shape.indices))?
+            }
+            ShapeSource::ConvexVertices(vertices) => {
+                anyhow::ensure!(!vertices.is_empty(), "Shape {i} has no vertices");
+                add_convex_vertices_shape(&mut writer, vertices, None)?
+            }
+        };
+
+        entries.pointer(Some(item));
+    }
+
+    let entries = writer.add_array("s_hkpShape_array_data", shapes.len(), &entries)?;
+    let mut collection = ItemData::new();
+    collection.array(entries, shapes.len());
+    writer.add_item("s_hkpShape_array", &collection)?;
+
+    writer.write(w)
+}
+
+/// Adds a `hkpConvexVerticesShape`, with connectivity if triangles are given
+fn add_convex_vertices_shape(
+    writer: &mut TagFileWriter,
+    vertices: &[Vec3],
+    triangles: Option<&[u32]>,
+) -> anyhow::Result<u64> {
+    anyhow::ensure!(
+        vertices.len() <= u16::MAX as usize + 1,
+        "Convex vertices shapes can't have more than 65536 vertices, got {}",
+        vertices.len()
+    );
+
+    let min = vertices
+        .iter()
+        .copied()
+        .reduce(Vec3::min)
+        .unwrap_or_default();
+    let max = vertices
+        .iter()
+        .copied()
+        .reduce(Vec3::max)
+        .unwrap_or_default();
+
+    // Vertices are stored in blocks of four, padded with the last vertex
+    let mut blocks = ItemData::new();
+    let block_count = vertices.len().div_ceil(4);
+    for block in 0..block_count {
+        let points: [Vec3; 4] =
+            std::array::from_fn(|i| vertices[(block * 4 + i).min(vertices.len() - 1)]);
+        for axis in 0..3 {
+            for p in points {
+                blocks.f32(p[axis]);
+            }
+        }
+    }
+    let rotated_vertices = writer.add_array("hkFourTransposedPoints", block_count, &blocks)?;
+
+    // Distance a vertex may lie in front of a face before the shape counts as non-convex
+    let tolerance = (max - min).length() * 1e-5;
+
+    let (planes, plane_count, connectivity) = match triangles {
+        Some(triangles) => {
+            let mut planes = ItemData::new();
+            let mut vertex_indices = ItemData::new();
+            let mut face_sizes = ItemData::new();
+            for (triangle, t) in triangles.chunks_exact(3).enumerate() {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| {
+                    vertices
+                        .get(i as usize)
+                        .copied()
+                        .ok_or_else(|| anyhow::anyhow!("Triangle references invalid vertex {i}"))
+                });
+                let (a, b, c) = (a?, b?, c?);
+
+                let normal = (b - a).cross(c - a).normalize_or_zero();
+                if let Some(v) = vertices.iter().position(|&v| normal.dot(v - a) > tolerance) {
+                    anyhow::bail!(
+                        "Mesh is not convex, vertex {v} lies in front of triangle {triangle}"
+                    );
+                }
+
+                planes.vec4(normal.extend(-normal.dot(a)));
+                for &i in t {
+                    vertex_indices.u16(i as u16);
+                }
+                face_sizes.u8(3);
+            }
+
+            let face_count = triangles.len() / 3;
+            let planes = writer.add_array("hkVector4", face_count, &planes)?;
+            let vertex_indices = writer.add_array("hkUint16", triangles.len(), &vertex_indices)?;
+            let face_sizes = writer.add_array("hkUint8", face_count, &face_sizes)?;
+
+            let mut connectivity = ItemData::new();
+            connectivity
+                .zeros(16)
+                .array(vertex_indices, triangles.len())
+                .array(face_sizes, face_count);
+            let connectivity = writer.add_item("hkpConvexVerticesConnectivity", &connectivity)?;
+
+            (planes, face_count, Some(connectivity))
+        }
+        None => (None, 0, None),
+    };
+
+    let mut shape = ItemData::new();
+    shape
+        .zeros(0x30)
+        .vec4(((max - min) / 2.0).extend(0.0))
+        .vec4(((max + min) / 2.0).extend(0.0))
+        .array(rotated_vertices, block_count)
+        .i32(vertices.len() as i32)
+        .u32(0)
+        .array(planes, plane_count)
+        .pointer(connectivity);
+
+    writer.add_item("hkpConvexVerticesShape", &shape)
+}
@@ -0,0 +1,86 @@
+use std::io::Cursor;
+
+use destiny_havok::{
+    shape_collection::{read_shape_collection, Shape},
+    writer::{write_shape_collection, ShapeSource},
+};
+use glam::Vec3;
+
+fn write_read(shapes: &[ShapeSource<'_>]) -> Vec<Shape> {
+    let mut data = vec![];
+    write_shape_collection(&mut data, shapes).unwrap();
+    read_shape_collection(&mut Cursor::new(data), None).unwrap()
+}
+
+fn assert_same(a: &[Shape], b: &[Shape]) {
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b) {
+        assert_eq!(a.vertices, b.vertices);
+        assert_eq!(a.indices, b.indices);
+    }
+}
+
+#[test]
+fn shape_collection_roundtrip() {
+    // Five vertices, so the last block of transposed points needs padding
+    let pyramid = Shape {
+        vertices: vec![
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.5),
+        ],
+        indices: vec![0, 2, 1, 0, 3, 2, 0, 1, 4, 1, 2, 4, 2, 3, 4, 3, 0, 4],
+    };
+    let cube_points = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        Vec3::new(2.0, 2.0, 0.0),
+        Vec3::new(0.0, 0.0, 2.0),
+        Vec3::new(2.0, 0.0, 2.0),
+        Vec3::new(0.0, 2.0, 2.0),
+        Vec3::new(2.0, 2.0, 2.0),
+        // Interior point, dropped by the hull
+        Vec3::new(1.0, 1.0, 1.0),
+    ];
+
+    let first = write_read(&[
+        ShapeSource::Mesh(&pyramid),
+        ShapeSource::ConvexVertices(&cube_points),
+    ]);
+    assert_same(&first[..1], &[pyramid]);
+    assert_eq!(first[1].vertices.len(), 8);
+    assert_eq!(first[1].indices.len(), 12 * 3);
+
+    let second = write_read(&first.iter().map(ShapeSource::Mesh).collect::<Vec<_>>());
+    assert_same(&first, &second);
+}
+
+#[test]
+fn non_convex_mesh_is_rejected() {
+    // A pyramid with its base pushed in towards the apex
+    let dented = Shape {
+        vertices: vec![
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.5),
+            Vec3::new(0.0, 0.0, 1.0),
+        ],
+        indices: vec![
+            0, 1, 4, 1, 2, 4, 2, 3, 4, 3, 0, 4, // sides
+            1, 0, 5, 2, 1, 5, 3, 2, 5, 0, 3, 5, // dent
+        ],
+    };
+
+    let mut data = vec![];
+    let err = write_shape_collection(&mut data, &[ShapeSource::Mesh(&dented)]).unwrap_err();
+    assert!(err.to_string().contains("not convex"), "{err}");
+
+    // Its vertices can still be written as a hull
+    let hull = write_read(&[ShapeSource::ConvexVertices(&dented.vertices)]);
+    assert_eq!(hull[0].vertices.len(), 5);
+}
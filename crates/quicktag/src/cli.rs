@@ -0,0 +1,799 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use base64::Engine;
+use destiny_pkg::TagHash;
+use itertools::Itertools;
+use log::info;
+use nohash_hasher::{IntMap, IntSet};
+
+use crate::{
+    diff::{TagDiff, TagSnapshot},
+    graph::{GraphFormat, ReferenceGraph},
+    inference::infer_struct_layout,
+    packages::{package_manager, package_name, parse_tag},
+    pathfinding::find_reference_paths,
+    references::{
+        export_references, import_references, user_references_path, ReferenceDatabase,
+        REFERENCE_NAMES,
+    },
+    scanner::{load_tag_cache, TagCache},
+    search::{search_tags, SearchKind, SearchQuery},
+    text::create_stringmap,
+    traversal::{format_tag_entry, ExtendedScanResult, TagTraversal},
+    util::serialize_display,
+};
+
+/// Commands that run without opening a window. Results are printed to stdout, logging goes to stderr.
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Build the tag cache if it's missing or out of date and print a summary
+    Scan,
+
+    /// List the tags referencing and referenced by a tag
+    Refs { tag: String },
+
+    /// Print the hierarchy of tags referenced by a tag
+    Tree {
+        tag: String,
+
+        /// Maximum traversal depth
+        #[arg(short, long, default_value_t = 16)]
+        depth: usize,
+
+        /// Include raw strings found in each tag
+        #[arg(short, long)]
+        strings: bool,
+    },
+
+    /// Search the string map (case insensitive)
+    Strings {
+        query: String,
+
+        /// List the tags containing each matching string hash
+        #[arg(short, long)]
+        tags: bool,
+    },
+
+    /// Find the shortest chains of references leading from one tag to another
+    Path {
+        from: String,
+        to: String,
+
+        /// Number of paths to find
+        #[arg(short = 'k', long, default_value_t = 1)]
+        count: usize,
+    },
+
+    /// Export the reference graph, or the part of it reachable from a tag
+    Graph {
+        /// Only export tags reachable from this tag
+        #[arg(short, long)]
+        tag: Option<String>,
+
+        /// Maximum number of references to follow from the tag
+        #[arg(short, long)]
+        depth: Option<usize>,
+
+        /// Output format, defaults to JSON if --json is passed and GraphML otherwise
+        #[arg(short, long, value_enum)]
+        format: Option<GraphFormat>,
+
+        /// Write the graph to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Search the data of every structure tag, or every tag with --all-tags, for a value or byte pattern
+    Search {
+        #[arg(value_enum)]
+        kind: SearchKind,
+        value: String,
+
+        /// Maximum difference for float values to match
+        #[arg(short, long, default_value_t = 0.0001)]
+        tolerance: f32,
+
+        /// Search every tag instead of only structure tags, including textures, audio and other large buffers
+        #[arg(short, long)]
+        all_tags: bool,
+    },
+
+    /// Compare two packages directories or two tag cache files, listing added, removed and changed tags.
+    /// Packages directories have to be scanned, but also allow comparing the values in changed tags.
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+
+        /// Maximum number of field changes to list for every changed tag
+        #[arg(short, long, default_value_t = 64)]
+        max_fields: usize,
+    },
+
+    /// Guess the layout of a structure from every instance of its class, printed as a draft binrw struct
+    Infer {
+        /// Class hash as shown in the tag view (eg. 44968080), or the name of a known class
+        class: String,
+
+        /// Maximum number of tags to read instances from
+        #[arg(short, long, default_value_t = 2000)]
+        max_tags: usize,
+    },
+
+    /// Manage the names and field notes of reference classes
+    Classes {
+        #[command(subcommand)]
+        command: ClassesCommand,
+    },
+
+    /// Print information about a tag along with a hexdump of its data
+    Dump {
+        tag: String,
+
+        /// Write the raw tag data to this file instead of printing a hexdump
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// The user reference file (references_<version>.json next to the executable) is merged over the built-in names
+/// at startup
+#[derive(clap::Subcommand, Debug)]
+pub enum ClassesCommand {
+    /// List every known class name
+    List,
+
+    /// Merge a reference file into the user reference file, replacing existing names
+    Import { file: PathBuf },
+
+    /// Write the known class names and field notes to a reference file
+    Export {
+        file: PathBuf,
+
+        /// Only export names and notes from the user reference file
+        #[arg(short, long)]
+        user_only: bool,
+    },
+}
+
+pub fn run(command: Command, json: bool) -> anyhow::Result<()> {
+    match command {
+        Command::Scan => scan(json),
+        Command::Refs { tag } => refs(&tag, json),
+        Command::Tree {
+            tag,
+            depth,
+            strings,
+        } => tree(&tag, depth, strings, json),
+        Command::Strings { query, tags } => strings(&query, tags, json),
+        Command::Path { from, to, count } => path(&from, &to, count, json),
+        Command::Graph {
+            tag,
+            depth,
+            format,
+            output,
+        } => graph(
+            tag.as_deref(),
+            depth,
+            format.unwrap_or(if json {
+                GraphFormat::Json
+            } else {
+                GraphFormat::Graphml
+            }),
+            output.as_deref(),
+        ),
+        Command::Search {
+            kind,
+            value,
+            tolerance,
+            all_tags,
+        } => search(kind, &value, tolerance, all_tags, json),
+        Command::Diff {
+            old,
+            new,
+            max_fields,
+        } => diff(&old, &new, max_fields, json),
+        Command::Infer { class, max_tags } => infer(&class, max_tags, json),
+        Command::Classes { command } => classes(command, json),
+        Command::Dump { tag, output } => dump(&tag, output.as_deref(), json),
+    }
+}
+
+fn load_cache() -> anyhow::Result<TagCache> {
+    load_tag_cache(package_manager().version)
+}
+
+fn resolve_tag(input: &str) -> anyhow::Result<TagHash> {
+    let tag = parse_tag(input);
+    if tag == TagHash::NONE || package_manager().get_entry(tag).is_none() {
+        anyhow::bail!("Could not find tag '{input}'");
+    }
+
+    Ok(tag)
+}
+
+/// Parses a class hash as displayed by quicktag, or looks up a class by name
+fn resolve_class(input: &str) -> anyhow::Result<u32> {
+    let input = input.trim();
+    if let Some((class, _)) = REFERENCE_NAMES
+        .read()
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(input))
+    {
+        return Ok(*class);
+    }
+
+    u32::from_str_radix(input.trim_start_matches("D2Class_"), 16)
+        .map(u32::from_be)
+        .with_context(|| format!("'{input}' is not a class hash or known class name"))
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// A tag along with its formatted entry
+#[derive(serde::Serialize)]
+struct TagLabel {
+    #[serde(serialize_with = "serialize_display")]
+    tag: TagHash,
+    label: String,
+}
+
+impl TagLabel {
+    fn new(tag: TagHash) -> Self {
+        Self {
+            tag,
+            label: format_tag_entry(tag, package_manager().get_entry(tag).as_ref()),
+        }
+    }
+}
+
+fn scan(json: bool) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct ScanSummary {
+        tags: usize,
+        failed: usize,
+        references: usize,
+        string_hashes: usize,
+        raw_strings: usize,
+    }
+
+    let cache = load_cache()?;
+    let summary = ScanSummary {
+        tags: cache.hashes.len(),
+        failed: cache.hashes.values().filter(|s| !s.successful).count(),
+        references: cache
+            .hashes
+            .values()
+            .map(|s| s.file_hashes.len() + s.file_hashes64.len())
+            .sum(),
+        string_hashes: cache.hashes.values().map(|s| s.string_hashes.len()).sum(),
+        raw_strings: cache.hashes.values().map(|s| s.raw_strings.len()).sum(),
+    };
+
+    if json {
+        return print_json(&summary);
+    }
+
+    println!("Tags: {}", summary.tags);
+    println!("Failed to read: {}", summary.failed);
+    println!("Tag references: {}", summary.references);
+    println!("String hashes: {}", summary.string_hashes);
+    println!("Raw strings: {}", summary.raw_strings);
+
+    Ok(())
+}
+
+fn refs(input: &str, json: bool) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct Reference {
+        #[serde(flatten)]
+        tag: TagLabel,
+        /// `None` for references from the tag header
+        offset: Option<u64>,
+    }
+
+    #[derive(serde::Serialize)]
+    struct References {
+        #[serde(flatten)]
+        tag: TagLabel,
+        referenced_by: Vec<TagLabel>,
+        references: Vec<Reference>,
+    }
+
+    let tag = resolve_tag(input)?;
+    let cache = load_cache()?;
+    let scan = cache
+        .hashes
+        .get(&tag)
+        .cloned()
+        .map(ExtendedScanResult::from_scanresult)
+        .with_context(|| format!("Tag {tag} is not in the cache (it has no references)"))?;
+
+    let result = References {
+        tag: TagLabel::new(tag),
+        referenced_by: scan
+            .references
+            .iter()
+            .map(|(t, e)| TagLabel {
+                tag: *t,
+                label: format_tag_entry(*t, Some(e)),
+            })
+            .collect(),
+        references: scan
+            .file_hashes
+            .iter()
+            .map(|h| Reference {
+                tag: TagLabel {
+                    tag: h.hash.hash32(),
+                    label: format_tag_entry(h.hash.hash32(), h.entry.as_ref()),
+                },
+                offset: (h.offset != u64::MAX).then_some(h.offset),
+            })
+            .collect(),
+    };
+
+    if json {
+        return print_json(&result);
+    }
+
+    println!("{}", result.tag.label);
+    println!();
+    println!("Referenced by ({}):", result.referenced_by.len());
+    for t in &result.referenced_by {
+        println!("  {}", t.label);
+    }
+
+    println!();
+    println!("References ({}):", result.references.len());
+    for r in &result.references {
+        match r.offset {
+            Some(offset) => println!("  {} @ 0x{offset:X}", r.tag.label),
+            None => println!("  {} @ TagHeader reference", r.tag.label),
+        }
+    }
+
+    Ok(())
+}
+
+fn tree(input: &str, depth_limit: usize, show_strings: bool, json: bool) -> anyhow::Result<()> {
+    let tag = resolve_tag(input)?;
+    let cache = load_cache()?;
+    let traversal = TagTraversal::new(tag, depth_limit, &cache, show_strings);
+
+    if json {
+        return print_json(&traversal);
+    }
+
+    print!("{}", traversal.to_text());
+
+    Ok(())
+}
+
+fn strings(query: &str, show_tags: bool, json: bool) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct StringMatch {
+        hash: String,
+        strings: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tags: Option<Vec<TagLabel>>,
+    }
+
+    let stringmap = create_stringmap()?;
+    let query = query.to_lowercase();
+    let matches = stringmap
+        .iter()
+        .filter(|(_, s)| s.iter().any(|s| s.to_lowercase().contains(&query)))
+        .sorted_by_key(|(hash, _)| **hash)
+        .collect_vec();
+
+    let mut tags_by_string: IntMap<u32, Vec<TagHash>> = Default::default();
+    if show_tags {
+        let hashes: IntSet<u32> = matches.iter().map(|(h, _)| **h).collect();
+        let cache = load_cache()?;
+        for (tag, scan) in cache.hashes.iter().sorted_by_key(|(t, _)| t.0) {
+            for hash in scan.string_hashes.iter().map(|s| s.hash).unique() {
+                if hashes.contains(&hash) {
+                    tags_by_string.entry(hash).or_default().push(*tag);
+                }
+            }
+        }
+    }
+
+    let results = matches
+        .into_iter()
+        .map(|(hash, strings)| StringMatch {
+            hash: format!("{hash:08x}"),
+            strings: strings.clone(),
+            tags: show_tags.then(|| {
+                tags_by_string
+                    .get(hash)
+                    .map(|tags| tags.iter().map(|t| TagLabel::new(*t)).collect())
+                    .unwrap_or_default()
+            }),
+        })
+        .collect_vec();
+
+    if json {
+        return print_json(&results);
+    }
+
+    for m in &results {
+        for s in &m.strings {
+            println!("{} '{}'", m.hash, s.replace('\n', "\\n"));
+        }
+
+        if let Some(tags) = &m.tags {
+            for t in tags {
+                println!("    {}", t.label);
+            }
+        }
+    }
+
+    info!("{} matching strings", results.len());
+
+    Ok(())
+}
+
+fn path(from: &str, to: &str, count: usize, json: bool) -> anyhow::Result<()> {
+    let from = resolve_tag(from)?;
+    let to = resolve_tag(to)?;
+    let cache = load_cache()?;
+    let paths = find_reference_paths(&cache, from, to, count);
+
+    if json {
+        return print_json(&paths);
+    }
+
+    if paths.is_empty() {
+        println!("No path found from {from} to {to}");
+    }
+
+    for (i, path) in paths.iter().enumerate() {
+        println!("Path {} ({} references):", i + 1, path.reference_count());
+        println!("{}", path.to_text());
+        println!();
+    }
+
+    Ok(())
+}
+
+fn graph(
+    input: Option<&str>,
+    depth_limit: Option<usize>,
+    format: GraphFormat,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let root = input.map(resolve_tag).transpose()?;
+    let cache = load_cache()?;
+    let graph = match root {
+        Some(root) => ReferenceGraph::reachable_from(&cache, root, depth_limit),
+        None => ReferenceGraph::from_cache(&cache),
+    };
+
+    info!(
+        "Exporting {} tags and {} references",
+        graph.nodes.len(),
+        graph.edges.len()
+    );
+
+    match output {
+        Some(output) => {
+            let mut w = BufWriter::new(
+                File::create(output)
+                    .with_context(|| format!("Failed to create {}", output.display()))?,
+            );
+            graph.write(format, &mut w)?;
+            w.flush()?;
+        }
+        None => {
+            let mut w = BufWriter::new(std::io::stdout().lock());
+            graph.write(format, &mut w)?;
+            w.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn search(
+    kind: SearchKind,
+    value: &str,
+    tolerance: f32,
+    all_tags: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    let query = SearchQuery::parse(kind, value, tolerance)
+        .with_context(|| format!("Invalid {kind:?} value '{value}'"))?;
+    let matches = search_tags(&query, all_tags);
+
+    if json {
+        return print_json(&matches);
+    }
+
+    for m in &matches {
+        println!(
+            "{} @ {}",
+            m.label,
+            m.offsets.iter().map(|o| format!("0x{o:X}")).join(", ")
+        );
+    }
+
+    info!(
+        "{} matches in {} tags",
+        matches.iter().map(|m| m.offsets.len()).sum::<usize>(),
+        matches.len()
+    );
+
+    Ok(())
+}
+
+fn diff(old: &Path, new: &Path, max_fields: usize, json: bool) -> anyhow::Result<()> {
+    let version = package_manager().version;
+    let old = TagSnapshot::open(old, version)?;
+    let new = TagSnapshot::open(new, version)?;
+    let diff = TagDiff::new(&old, &new, max_fields);
+
+    if json {
+        return print_json(&diff);
+    }
+
+    print!("{}", diff.to_text());
+
+    Ok(())
+}
+
+fn infer(input: &str, max_tags: usize, json: bool) -> anyhow::Result<()> {
+    let class = resolve_class(input)?;
+    let cache = load_cache()?;
+    let layout = infer_struct_layout(&cache, class, max_tags)?;
+
+    if json {
+        return print_json(&layout);
+    }
+
+    print!("{}", layout.to_rust());
+
+    Ok(())
+}
+
+fn classes(command: ClassesCommand, json: bool) -> anyhow::Result<()> {
+    match command {
+        ClassesCommand::List => {
+            let database = ReferenceDatabase::current();
+            if json {
+                return print_json(&database);
+            }
+
+            for definition in &database.references {
+                println!("{:08X} {}", definition.class.to_be(), definition.name);
+                for field in &definition.fields {
+                    println!("    0x{:X}: {}", field.offset, field.note);
+                }
+            }
+        }
+        ClassesCommand::Import { file } => {
+            let count = import_references(&file)?;
+            info!(
+                "Imported {count} classes into {}",
+                user_references_path().display()
+            );
+        }
+        ClassesCommand::Export { file, user_only } => {
+            let count = export_references(&file, user_only)?;
+            info!("Exported {count} classes to {}", file.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn dump(input: &str, output: Option<&Path>, json: bool) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct TagDump {
+        #[serde(flatten)]
+        tag: TagLabel,
+        package: String,
+        size: usize,
+        /// Base64, omitted when the data is written to a file
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<String>,
+    }
+
+    let tag = resolve_tag(input)?;
+    let data = package_manager()
+        .read_tag(tag)
+        .with_context(|| format!("Failed to read tag {tag}"))?;
+
+    let package = package_name(tag.pkg_id());
+
+    if let Some(output) = output {
+        std::fs::write(output, &data)
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+        info!("Wrote {} bytes to {}", data.len(), output.display());
+    }
+
+    let result = TagDump {
+        tag: TagLabel::new(tag),
+        package,
+        size: data.len(),
+        data: (json && output.is_none())
+            .then(|| base64::engine::general_purpose::STANDARD.encode(&data)),
+    };
+
+    if json {
+        return print_json(&result);
+    }
+
+    println!("{}", result.tag.label);
+    println!("Package {}, {} bytes", result.package, result.size);
+
+    if output.is_none() {
+        println!();
+        print!("{}", hexdump(&data));
+    }
+
+    Ok(())
+}
+
+fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(out, "{:08X}  ", i * 16).ok();
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => write!(out, "{b:02X} ").ok(),
+                None => write!(out, "   ").ok(),
+            };
+
+            if j == 7 {
+                out.push(' ');
+            }
+        }
+
+        out.push(' ');
+        out.extend(line.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(clap::Parser, Debug)]
+    struct Args {
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    fn parse(args: &[&str]) -> Result<Command, clap::Error> {
+        Args::try_parse_from(std::iter::once("quicktag").chain(args.iter().copied()))
+            .map(|a| a.command)
+    }
+
+    #[test]
+    fn tree_defaults() {
+        let Command::Tree {
+            tag,
+            depth,
+            strings,
+        } = parse(&["tree", "80801234"]).unwrap()
+        else {
+            panic!("Expected a tree command");
+        };
+
+        assert_eq!(tag, "80801234");
+        assert_eq!(depth, 16);
+        assert!(!strings);
+
+        let Command::Tree { depth, strings, .. } =
+            parse(&["tree", "80801234", "-d", "3", "--strings"]).unwrap()
+        else {
+            panic!("Expected a tree command");
+        };
+
+        assert_eq!(depth, 3);
+        assert!(strings);
+    }
+
+    #[test]
+    fn graph_options() {
+        let Command::Graph {
+            tag,
+            depth,
+            format,
+            output,
+        } = parse(&[
+            "graph", "-t", "80801234", "--depth", "2", "-f", "dot", "-o", "out.dot",
+        ])
+        .unwrap()
+        else {
+            panic!("Expected a graph command");
+        };
+
+        assert_eq!(tag.as_deref(), Some("80801234"));
+        assert_eq!(depth, Some(2));
+        assert_eq!(format, Some(GraphFormat::Dot));
+        assert_eq!(output, Some(PathBuf::from("out.dot")));
+
+        assert!(matches!(
+            parse(&["graph"]).unwrap(),
+            Command::Graph {
+                tag: None,
+                depth: None,
+                format: None,
+                output: None
+            }
+        ));
+    }
+
+    #[test]
+    fn search_and_classes() {
+        let Command::Search {
+            kind,
+            value,
+            tolerance,
+            all_tags,
+        } = parse(&["search", "vec4", "1,2,3,4", "-t", "0.5"]).unwrap()
+        else {
+            panic!("Expected a search command");
+        };
+
+        assert_eq!(kind, SearchKind::Vec4);
+        assert_eq!(value, "1,2,3,4");
+        assert_eq!(tolerance, 0.5);
+        assert!(!all_tags);
+
+        assert!(matches!(
+            parse(&["search", "hex", "DE AD ?? EF", "--all-tags"]).unwrap(),
+            Command::Search { all_tags: true, .. }
+        ));
+
+        assert!(matches!(
+            parse(&["classes", "export", "refs.json", "--user-only"]).unwrap(),
+            Command::Classes {
+                command: ClassesCommand::Export {
+                    user_only: true,
+                    ..
+                }
+            }
+        ));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["tree"]).is_err());
+        assert!(parse(&["tree", "80801234", "-d", "deep"]).is_err());
+        assert!(parse(&["search", "f16", "1.0"]).is_err());
+        assert!(parse(&["graph", "-f", "svg"]).is_err());
+        assert!(parse(&["path", "80801234"]).is_err());
+    }
+
+    #[test]
+    fn hexdump_lines() {
+        let data = b"0123456789abcdef\x00\xffxyz";
+        assert_eq!(
+            hexdump(data),
+            "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  0123456789abcdef\n\
+             00000010  00 FF 78 79 7A                                    ..xyz\n"
+        );
+    }
+}
@@ -0,0 +1,618 @@
+use std::{fmt::Write, path::Path, sync::Arc};
+
+use anyhow::Context;
+use binrw::Endian;
+use destiny_pkg::{PackageManager, PackageVersion, TagHash};
+use itertools::Itertools;
+use log::{error, info};
+use nohash_hasher::{IntMap, IntSet};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    graph::outgoing_references_in,
+    packages::package_manager,
+    scanner::{create_scanner_context, read_package_tags, scan_file, ScanResult, TagCache},
+    traversal::format_tag_entry_in,
+    util::{serialize_display, u32_from_endian},
+};
+
+/// Scan results for one side of a diff, read from a tag cache or by scanning a packages directory
+pub struct TagSnapshot {
+    /// Used for tag labels, 64-bit hash lookups and endianness.
+    /// Tag caches don't carry their packages, so they use the globally loaded package manager
+    package_manager: Arc<PackageManager>,
+    /// Whether the tag data can be read from `package_manager`, only the case when scanning a packages directory
+    has_data: bool,
+    hashes: IntMap<TagHash, ScanResult>,
+}
+
+impl TagSnapshot {
+    /// Opens a packages directory, or a tag cache file
+    pub fn open(path: &Path, version: PackageVersion) -> anyhow::Result<Self> {
+        if path.is_dir() {
+            Self::scan_packages(path, version)
+        } else {
+            let cache = TagCache::read(path)?;
+            Ok(Self {
+                package_manager: package_manager(),
+                has_data: false,
+                hashes: cache
+                    .hashes
+                    .into_iter()
+                    .filter(|(_, scan)| scan.content_hash.is_some() || !scan.successful)
+                    .collect(),
+            })
+        }
+    }
+
+    fn scan_packages(path: &Path, version: PackageVersion) -> anyhow::Result<Self> {
+        info!("Scanning packages in {}", path.display());
+        let pm = PackageManager::new(path.to_string_lossy().to_string(), version)
+            .with_context(|| format!("Failed to open packages in {}", path.display()))?;
+        let context = create_scanner_context(&pm)?;
+
+        let package_paths = pm.package_paths.values().cloned().collect_vec();
+        let hashes = package_paths
+            .par_iter()
+            .flat_map_iter(|path| {
+                let mut results = vec![];
+                let result = read_package_tags(version, path, false, |tag, data| match data {
+                    Ok(data) => {
+                        let mut scan = scan_file(&context, &data);
+                        scan.retain_existing_tags(&context);
+                        results.push((tag, scan));
+                    }
+                    Err(e) => {
+                        error!("Failed to read entry {path}:{}: {e}", tag.entry_index());
+                        results.push((
+                            tag,
+                            ScanResult {
+                                successful: false,
+                                ..Default::default()
+                            },
+                        ));
+                    }
+                });
+
+                if let Err(e) = result {
+                    error!("Skipping package: {e:?}");
+                }
+
+                results
+            })
+            .collect();
+
+        Ok(Self {
+            package_manager: Arc::new(pm),
+            has_data: true,
+            hashes,
+        })
+    }
+
+    fn read_tag(&self, tag: TagHash) -> Option<Vec<u8>> {
+        if !self.has_data {
+            return None;
+        }
+
+        self.package_manager.read_tag(tag).ok()
+    }
+
+    fn endian(&self) -> Endian {
+        self.package_manager.version.endian()
+    }
+
+    fn diff_tag(&self, tag: TagHash) -> DiffTag {
+        DiffTag::new(tag, &self.package_manager)
+    }
+
+    /// Tags referenced by a tag, without duplicates
+    fn reference_targets(&self, scan: &ScanResult) -> Vec<TagHash> {
+        outgoing_references_in(self.package_manager.clone(), scan)
+            .map(|(t, _)| t)
+            .unique()
+            .sorted_by_key(|t| t.0)
+            .collect()
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct DiffTag {
+    #[serde(serialize_with = "serialize_display")]
+    pub tag: TagHash,
+    pub label: String,
+}
+
+impl DiffTag {
+    fn new(tag: TagHash, pm: &PackageManager) -> Self {
+        Self {
+            tag,
+            label: format_tag_entry_in(pm, tag, pm.get_entry(tag).as_ref()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ReferenceEdge {
+    #[serde(serialize_with = "serialize_display")]
+    pub source: TagHash,
+    #[serde(serialize_with = "serialize_display")]
+    pub target: TagHash,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldChangeKind {
+    TagHash,
+    TagHash64,
+    StringHash,
+    Array,
+    /// Any other value, only compared when the tag data is available
+    Value,
+}
+
+#[derive(serde::Serialize)]
+pub struct FieldChange {
+    pub offset: u64,
+    pub kind: FieldChangeKind,
+    /// `None` if the field isn't present in the old tag
+    pub old: Option<String>,
+    /// `None` if the field isn't present in the new tag
+    pub new: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ChangedTag {
+    #[serde(flatten)]
+    pub tag: DiffTag,
+    /// Sizes of the old and new data, only known when comparing packages directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<(usize, usize)>,
+    pub references_added: Vec<DiffTag>,
+    pub references_removed: Vec<DiffTag>,
+    pub fields: Vec<FieldChange>,
+    /// Number of field changes left out
+    pub fields_omitted: usize,
+}
+
+/// Differences between two sets of packages, tags are compared by the hash of their data
+#[derive(serde::Serialize)]
+pub struct TagDiff {
+    pub added: Vec<DiffTag>,
+    pub removed: Vec<DiffTag>,
+    pub changed: Vec<ChangedTag>,
+    /// Tags that failed to read in either snapshot, left out of the other lists
+    pub unreadable: Vec<DiffTag>,
+    pub references_added: Vec<ReferenceEdge>,
+    pub references_removed: Vec<ReferenceEdge>,
+}
+
+impl TagDiff {
+    /// Compares two snapshots, listing at most `max_fields` field changes for every changed tag
+    pub fn new(old: &TagSnapshot, new: &TagSnapshot, max_fields: usize) -> Self {
+        let unreadable: IntSet<TagHash> = old
+            .hashes
+            .iter()
+            .chain(new.hashes.iter())
+            .filter(|(_, scan)| !scan.successful)
+            .map(|(&tag, _)| tag)
+            .collect();
+        let old_tags: IntSet<TagHash> = old
+            .hashes
+            .keys()
+            .copied()
+            .filter(|t| !unreadable.contains(t))
+            .collect();
+        let new_tags: IntSet<TagHash> = new
+            .hashes
+            .keys()
+            .copied()
+            .filter(|t| !unreadable.contains(t))
+            .collect();
+
+        let added = new_tags
+            .difference(&old_tags)
+            .copied()
+            .sorted_by_key(|t| t.0)
+            .collect_vec();
+        let removed = old_tags
+            .difference(&new_tags)
+            .copied()
+            .sorted_by_key(|t| t.0)
+            .collect_vec();
+        let changed = old_tags
+            .intersection(&new_tags)
+            .copied()
+            .filter(|t| old.hashes[t].content_hash != new.hashes[t].content_hash)
+            .sorted_by_key(|t| t.0)
+            .collect_vec();
+
+        let mut references_added = vec![];
+        let mut references_removed = vec![];
+        for &tag in &added {
+            references_added.extend(new.reference_targets(&new.hashes[&tag]).into_iter().map(
+                |target| ReferenceEdge {
+                    source: tag,
+                    target,
+                },
+            ));
+        }
+        for &tag in &removed {
+            references_removed.extend(old.reference_targets(&old.hashes[&tag]).into_iter().map(
+                |target| ReferenceEdge {
+                    source: tag,
+                    target,
+                },
+            ));
+        }
+
+        let changed = changed
+            .into_iter()
+            .map(|tag| {
+                let old_scan = &old.hashes[&tag];
+                let new_scan = &new.hashes[&tag];
+
+                let old_targets = old.reference_targets(old_scan);
+                let new_targets = new.reference_targets(new_scan);
+                let targets_added = new_targets
+                    .iter()
+                    .filter(|t| !old_targets.contains(t))
+                    .copied()
+                    .collect_vec();
+                let targets_removed = old_targets
+                    .iter()
+                    .filter(|t| !new_targets.contains(t))
+                    .copied()
+                    .collect_vec();
+
+                references_added.extend(targets_added.iter().map(|&target| ReferenceEdge {
+                    source: tag,
+                    target,
+                }));
+                references_removed.extend(targets_removed.iter().map(|&target| ReferenceEdge {
+                    source: tag,
+                    target,
+                }));
+
+                let data = old.read_tag(tag).zip(new.read_tag(tag));
+                let mut fields = field_changes(
+                    old_scan,
+                    new_scan,
+                    data.as_ref().map(|(o, n)| (o.as_slice(), n.as_slice())),
+                    new.endian(),
+                );
+                let fields_omitted = fields.len().saturating_sub(max_fields);
+                fields.truncate(max_fields);
+
+                ChangedTag {
+                    tag: new.diff_tag(tag),
+                    size: data.as_ref().map(|(o, n)| (o.len(), n.len())),
+                    references_added: targets_added.into_iter().map(|t| new.diff_tag(t)).collect(),
+                    references_removed: targets_removed
+                        .into_iter()
+                        .map(|t| old.diff_tag(t))
+                        .collect(),
+                    fields,
+                    fields_omitted,
+                }
+            })
+            .collect();
+
+        Self {
+            added: added.into_iter().map(|t| new.diff_tag(t)).collect(),
+            removed: removed.into_iter().map(|t| old.diff_tag(t)).collect(),
+            changed,
+            unreadable: unreadable
+                .into_iter()
+                .sorted_by_key(|t| t.0)
+                .map(|t| {
+                    if new.hashes.contains_key(&t) {
+                        new.diff_tag(t)
+                    } else {
+                        old.diff_tag(t)
+                    }
+                })
+                .collect(),
+            references_added,
+            references_removed,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "Added tags ({}):", self.added.len()).ok();
+        for t in &self.added {
+            writeln!(out, "  + {}", t.label).ok();
+        }
+
+        writeln!(out, "\nRemoved tags ({}):", self.removed.len()).ok();
+        for t in &self.removed {
+            writeln!(out, "  - {}", t.label).ok();
+        }
+
+        writeln!(out, "\nChanged tags ({}):", self.changed.len()).ok();
+        for c in &self.changed {
+            writeln!(out, "  ~ {}", c.tag.label).ok();
+            if let Some((old_size, new_size)) = c.size {
+                if old_size != new_size {
+                    writeln!(out, "      size 0x{old_size:X} → 0x{new_size:X}").ok();
+                }
+            }
+
+            for t in &c.references_added {
+                writeln!(out, "      + reference to {}", t.label).ok();
+            }
+            for t in &c.references_removed {
+                writeln!(out, "      - reference to {}", t.label).ok();
+            }
+
+            for f in &c.fields {
+                writeln!(
+                    out,
+                    "      0x{:X} {:?}: {} → {}",
+                    f.offset,
+                    f.kind,
+                    f.old.as_deref().unwrap_or("(none)"),
+                    f.new.as_deref().unwrap_or("(none)")
+                )
+                .ok();
+            }
+
+            if c.fields_omitted != 0 {
+                writeln!(out, "      ... {} more field changes", c.fields_omitted).ok();
+            }
+        }
+
+        if !self.unreadable.is_empty() {
+            writeln!(out, "\nUnreadable tags ({}):", self.unreadable.len()).ok();
+            for t in &self.unreadable {
+                writeln!(out, "  ! {}", t.label).ok();
+            }
+        }
+
+        writeln!(
+            out,
+            "\nReferences: {} added, {} removed",
+            self.references_added.len(),
+            self.references_removed.len()
+        )
+        .ok();
+
+        out
+    }
+}
+
+/// Fields found by the scanner, along with their formatted value
+fn scanned_fields(scan: &ScanResult) -> IntMap<u64, (FieldChangeKind, String)> {
+    let mut fields: IntMap<u64, (FieldChangeKind, String)> = Default::default();
+    fields.extend(
+        scan.file_hashes
+            .iter()
+            .filter(|h| h.offset != u64::MAX)
+            .map(|h| (h.offset, (FieldChangeKind::TagHash, h.hash.to_string()))),
+    );
+    fields.extend(
+        scan.file_hashes64
+            .iter()
+            .map(|h| (h.offset, (FieldChangeKind::TagHash64, h.hash.to_string()))),
+    );
+    fields.extend(scan.string_hashes.iter().map(|h| {
+        (
+            h.offset,
+            (FieldChangeKind::StringHash, format!("{:08x}", h.hash)),
+        )
+    }));
+    fields.extend(scan.arrays.iter().map(|a| {
+        (
+            a.offset,
+            (
+                FieldChangeKind::Array,
+                format!("{} x {:08X}", a.count, a.class.to_be()),
+            ),
+        )
+    }));
+
+    fields
+}
+
+/// Compares the fields found by the scanner, and every other 4-byte value if the tag data is available
+fn field_changes(
+    old: &ScanResult,
+    new: &ScanResult,
+    data: Option<(&[u8], &[u8])>,
+    endian: Endian,
+) -> Vec<FieldChange> {
+    let old_fields = scanned_fields(old);
+    let new_fields = scanned_fields(new);
+
+    let mut changes = vec![];
+    for offset in old_fields.keys().chain(new_fields.keys()).copied().unique() {
+        let old_field = old_fields.get(&offset);
+        let new_field = new_fields.get(&offset);
+        if old_field == new_field {
+            continue;
+        }
+
+        changes.push(FieldChange {
+            offset,
+            kind: new_field.or(old_field).unwrap().0,
+            old: old_field.map(|(_, v)| v.clone()),
+            new: new_field.map(|(_, v)| v.clone()),
+        });
+    }
+
+    if let Some((old_data, new_data)) = data {
+        for (i, (o, n)) in old_data
+            .chunks_exact(4)
+            .zip(new_data.chunks_exact(4))
+            .enumerate()
+        {
+            let offset = i as u64 * 4;
+            if o == n || old_fields.contains_key(&offset) || new_fields.contains_key(&offset) {
+                continue;
+            }
+
+            changes.push(FieldChange {
+                offset,
+                kind: FieldChangeKind::Value,
+                old: Some(format_value(u32_from_endian(endian, o.try_into().unwrap()))),
+                new: Some(format_value(u32_from_endian(endian, n.try_into().unwrap()))),
+            });
+        }
+    }
+
+    changes.sort_by_key(|c| c.offset);
+    changes
+}
+
+/// Formats a value as a float if it looks like one, and as hex otherwise
+fn format_value(value: u32) -> String {
+    let f = f32::from_bits(value);
+    if f.is_normal() && (1e-6..1e7).contains(&f.abs()) {
+        format!("{f} (0x{value:08X})")
+    } else {
+        format!("0x{value:08X}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{ScannedArray, ScannedHash};
+
+    fn tag_hash(offset: u64, hash: u32) -> ScannedHash<TagHash> {
+        ScannedHash {
+            offset,
+            hash: TagHash(hash),
+        }
+    }
+
+    fn array(offset: u64, count: usize) -> ScannedArray {
+        ScannedArray {
+            offset,
+            header_size: 16,
+            count,
+            class: 0x80809ad8,
+            references: vec![],
+        }
+    }
+
+    fn summary(changes: &[FieldChange]) -> Vec<(u64, FieldChangeKind)> {
+        changes.iter().map(|c| (c.offset, c.kind)).collect()
+    }
+
+    #[test]
+    fn scanned_hashes() {
+        let old = ScanResult {
+            file_hashes: vec![tag_hash(0x10, 0x80a0_0001), tag_hash(0x20, 0x80a0_0002)],
+            string_hashes: vec![ScannedHash {
+                offset: 0x30,
+                hash: 0x1234_5678,
+            }],
+            ..Default::default()
+        };
+        let new = ScanResult {
+            file_hashes: vec![tag_hash(0x10, 0x80a0_0003), tag_hash(0x40, 0x80a0_0004)],
+            string_hashes: vec![ScannedHash {
+                offset: 0x30,
+                hash: 0x1234_5678,
+            }],
+            ..Default::default()
+        };
+
+        let changes = field_changes(&old, &new, None, Endian::Little);
+        assert_eq!(
+            summary(&changes),
+            [
+                (0x10, FieldChangeKind::TagHash),
+                (0x20, FieldChangeKind::TagHash),
+                (0x40, FieldChangeKind::TagHash)
+            ]
+        );
+
+        assert_eq!(
+            changes[0].old.as_deref(),
+            Some(TagHash(0x80a0_0001).to_string().as_str())
+        );
+        assert_eq!(
+            changes[0].new.as_deref(),
+            Some(TagHash(0x80a0_0003).to_string().as_str())
+        );
+        // Removed field
+        assert!(changes[1].old.is_some() && changes[1].new.is_none());
+        // Added field
+        assert!(changes[2].old.is_none() && changes[2].new.is_some());
+    }
+
+    #[test]
+    fn array_count() {
+        let old = ScanResult {
+            arrays: vec![array(0x8, 4)],
+            ..Default::default()
+        };
+        let new = ScanResult {
+            arrays: vec![array(0x8, 5)],
+            ..Default::default()
+        };
+
+        let changes = field_changes(&old, &new, None, Endian::Little);
+        assert_eq!(summary(&changes), [(0x8, FieldChangeKind::Array)]);
+        assert_eq!(changes[0].old.as_deref(), Some("4 x D89A8080"));
+        assert_eq!(changes[0].new.as_deref(), Some("5 x D89A8080"));
+    }
+
+    #[test]
+    fn header_reference_ignored() {
+        let old = ScanResult {
+            file_hashes: vec![tag_hash(u64::MAX, 0x80a0_0001)],
+            ..Default::default()
+        };
+        let new = ScanResult {
+            file_hashes: vec![tag_hash(u64::MAX, 0x80a0_0002)],
+            ..Default::default()
+        };
+
+        assert!(field_changes(&old, &new, None, Endian::Little).is_empty());
+    }
+
+    #[test]
+    fn data_values() {
+        let old = ScanResult {
+            file_hashes: vec![tag_hash(0x4, 0x80a0_0001)],
+            ..Default::default()
+        };
+        let new = ScanResult {
+            file_hashes: vec![tag_hash(0x4, 0x80a0_0002)],
+            ..Default::default()
+        };
+
+        let old_data = [1u32, 0x80a0_0001, 7, 1.5f32.to_bits(), 9];
+        let new_data = [2u32, 0x80a0_0002, 7, 2.5f32.to_bits(), 9];
+        let to_be = |values: &[u32]| values.iter().flat_map(|v| v.to_be_bytes()).collect_vec();
+        let (old_data, new_data) = (to_be(&old_data), to_be(&new_data));
+
+        let changes = field_changes(&old, &new, Some((&old_data, &new_data)), Endian::Big);
+        // The scanned hash at 0x4 isn't reported twice, and equal values are skipped
+        assert_eq!(
+            summary(&changes),
+            [
+                (0x0, FieldChangeKind::Value),
+                (0x4, FieldChangeKind::TagHash),
+                (0xC, FieldChangeKind::Value)
+            ]
+        );
+        assert_eq!(changes[0].old.as_deref(), Some("0x00000001"));
+        assert_eq!(changes[0].new.as_deref(), Some("0x00000002"));
+        assert_eq!(changes[2].old.as_deref(), Some("1.5 (0x3FC00000)"));
+        assert_eq!(changes[2].new.as_deref(), Some("2.5 (0x40200000)"));
+    }
+
+    #[test]
+    fn value_formatting() {
+        assert_eq!(format_value(1.0f32.to_bits()), "1 (0x3F800000)");
+        assert_eq!(format_value((-0.25f32).to_bits()), "-0.25 (0xBE800000)");
+        assert_eq!(format_value(0), "0x00000000");
+        assert_eq!(format_value(0x80a0_1234), "0x80A01234");
+        assert_eq!(format_value(u32::MAX), "0xFFFFFFFF");
+    }
+}
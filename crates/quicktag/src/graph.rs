@@ -0,0 +1,576 @@
+use std::{collections::VecDeque, io::Write, sync::Arc};
+
+use destiny_pkg::{PackageManager, TagHash};
+use itertools::Itertools;
+use nohash_hasher::{IntMap, IntSet};
+
+use crate::{
+    packages::{package_manager, package_name},
+    references::REFERENCE_NAMES,
+    scanner::{ScanResult, TagCache},
+    tagtypes::TagType,
+    util::{serialize_display, serialize_display_option},
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphFormat {
+    /// GraphML, for Gephi, networkx, yEd, etc.
+    Graphml,
+    /// Graphviz
+    Dot,
+    Json,
+}
+
+#[derive(serde::Serialize)]
+pub struct GraphNode {
+    #[serde(serialize_with = "serialize_display")]
+    pub tag: TagHash,
+    /// Name from the named tag table, if any
+    pub name: Option<String>,
+    /// Type of the tag, `None` if its package entry wasn't found. The same goes for the other entry fields
+    pub tag_type: Option<String>,
+    pub file_type: Option<u8>,
+    pub file_subtype: Option<u8>,
+    pub package: String,
+    /// Reference class of the tag
+    #[serde(serialize_with = "serialize_display_option")]
+    pub reference: Option<TagHash>,
+    pub reference_name: Option<String>,
+}
+
+/// All references from one tag to another
+#[derive(serde::Serialize)]
+pub struct GraphEdge {
+    #[serde(serialize_with = "serialize_display")]
+    pub source: TagHash,
+    #[serde(serialize_with = "serialize_display")]
+    pub target: TagHash,
+    /// Offsets of the references in the source tag
+    pub offsets: Vec<u64>,
+    /// The target is referenced from the tag header of the source
+    pub tag_header: bool,
+}
+
+/// Tags as nodes, with an edge for every tag referencing another
+#[derive(serde::Serialize)]
+pub struct ReferenceGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl ReferenceGraph {
+    /// Every tag in the cache
+    pub fn from_cache(cache: &TagCache) -> Self {
+        Self::from_tags(cache, cache.hashes.keys().copied().collect())
+    }
+
+    /// Every tag reachable by following references from `root`, up to `depth_limit` references away
+    pub fn reachable_from(cache: &TagCache, root: TagHash, depth_limit: Option<usize>) -> Self {
+        let tags = reachable_tags(cache, root, depth_limit, |scan| {
+            outgoing_references(scan).collect()
+        });
+
+        Self::from_tags(cache, tags)
+    }
+
+    /// Graph of the given tags, leaving out references to tags outside of the set
+    fn from_tags(cache: &TagCache, node_tags: IntSet<TagHash>) -> Self {
+        let edges = graph_edges(cache, &node_tags, |scan| {
+            outgoing_references(scan).collect()
+        });
+
+        let pm = package_manager();
+        let reference_names = REFERENCE_NAMES.read();
+        let named_tags: IntMap<TagHash, &str> = pm
+            .named_tags
+            .iter()
+            .map(|v| (v.hash, v.name.as_str()))
+            .collect();
+        let nodes = node_tags
+            .into_iter()
+            .sorted_by_key(|t| t.0)
+            .map(|tag| {
+                let entry = pm.get_entry(tag);
+                let reference = entry.as_ref().map(|e| e.reference);
+
+                GraphNode {
+                    tag,
+                    name: named_tags.get(&tag).map(|n| n.to_string()),
+                    tag_type: entry.as_ref().map(|e| {
+                        TagType::from_type_subtype(e.file_type, e.file_subtype).to_string()
+                    }),
+                    file_type: entry.as_ref().map(|e| e.file_type),
+                    file_subtype: entry.as_ref().map(|e| e.file_subtype),
+                    package: package_name(tag.pkg_id()),
+                    reference: reference.map(TagHash),
+                    reference_name: reference
+                        .and_then(|r| reference_names.get(&r))
+                        .map(|s| s.to_string()),
+                }
+            })
+            .collect();
+
+        Self { nodes, edges }
+    }
+
+    pub fn write(&self, format: GraphFormat, w: &mut impl Write) -> anyhow::Result<()> {
+        match format {
+            GraphFormat::Graphml => self.write_graphml(w)?,
+            GraphFormat::Dot => self.write_dot(w)?,
+            GraphFormat::Json => serde_json::to_writer_pretty(w, self)?,
+        }
+
+        Ok(())
+    }
+
+    pub fn write_graphml(&self, w: &mut impl Write) -> std::io::Result<()> {
+        const NODE_KEYS: [(&str, &str); 8] = [
+            ("name", "string"),
+            ("tag_type", "string"),
+            ("file_type", "int"),
+            ("file_subtype", "int"),
+            ("package", "string"),
+            ("reference", "string"),
+            ("reference_name", "string"),
+            ("label", "string"),
+        ];
+        const EDGE_KEYS: [(&str, &str); 3] = [
+            ("count", "int"),
+            ("offsets", "string"),
+            ("tag_header", "boolean"),
+        ];
+
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            w,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        for (name, ty) in NODE_KEYS {
+            writeln!(
+                w,
+                r#"  <key id="{name}" for="node" attr.name="{name}" attr.type="{ty}"/>"#
+            )?;
+        }
+        for (name, ty) in EDGE_KEYS {
+            writeln!(
+                w,
+                r#"  <key id="{name}" for="edge" attr.name="{name}" attr.type="{ty}"/>"#
+            )?;
+        }
+
+        writeln!(w, r#"  <graph id="references" edgedefault="directed">"#)?;
+        for node in &self.nodes {
+            writeln!(w, r#"    <node id="{}">"#, node.tag)?;
+            for (key, value) in node.attributes() {
+                writeln!(
+                    w,
+                    r#"      <data key="{key}">{}</data>"#,
+                    escape_xml(&value)
+                )?;
+            }
+            writeln!(w, "    </node>")?;
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                w,
+                r#"    <edge source="{}" target="{}">"#,
+                edge.source, edge.target
+            )?;
+            for (key, value) in edge.attributes() {
+                writeln!(w, r#"      <data key="{key}">{value}</data>"#)?;
+            }
+            writeln!(w, "    </edge>")?;
+        }
+
+        writeln!(w, "  </graph>")?;
+        writeln!(w, "</graphml>")?;
+
+        Ok(())
+    }
+
+    pub fn write_dot(&self, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(w, "digraph references {{")?;
+        for node in &self.nodes {
+            let attributes = node
+                .attributes()
+                .into_iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_dot(&value)))
+                .join(", ");
+            writeln!(w, "  \"{}\" [{attributes}];", node.tag)?;
+        }
+
+        for edge in &self.edges {
+            let attributes = edge
+                .attributes()
+                .into_iter()
+                .map(|(key, value)| format!("{key}=\"{value}\""))
+                .join(", ");
+            writeln!(
+                w,
+                "  \"{}\" -> \"{}\" [{attributes}];",
+                edge.source, edge.target
+            )?;
+        }
+        writeln!(w, "}}")?;
+
+        Ok(())
+    }
+}
+
+impl GraphNode {
+    /// Attributes for the GraphML and DOT writers, leaving out missing values
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        let mut attributes = vec![];
+        if let Some(name) = &self.name {
+            attributes.push(("name", name.clone()));
+        }
+
+        attributes.extend(
+            [
+                ("tag_type", self.tag_type.clone()),
+                ("file_type", self.file_type.map(|v| v.to_string())),
+                ("file_subtype", self.file_subtype.map(|v| v.to_string())),
+                ("package", Some(self.package.clone())),
+                ("reference", self.reference.map(|v| v.to_string())),
+                ("reference_name", self.reference_name.clone()),
+            ]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?))),
+        );
+
+        attributes.push((
+            "label",
+            self.name
+                .clone()
+                .or_else(|| self.reference_name.clone())
+                .map(|n| format!("{} {n}", self.tag))
+                .unwrap_or_else(|| self.tag.to_string()),
+        ));
+
+        attributes
+    }
+}
+
+impl GraphEdge {
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "count",
+                (self.offsets.len() + self.tag_header as usize).to_string(),
+            ),
+            (
+                "offsets",
+                self.offsets.iter().map(|o| format!("0x{o:X}")).join(" "),
+            ),
+            ("tag_header", self.tag_header.to_string()),
+        ]
+    }
+}
+
+/// Every tag reachable by following `references` from `root`, including `root` itself
+fn reachable_tags(
+    cache: &TagCache,
+    root: TagHash,
+    depth_limit: Option<usize>,
+    references: impl Fn(&ScanResult) -> Vec<(TagHash, u64)>,
+) -> IntSet<TagHash> {
+    let mut seen: IntSet<TagHash> = Default::default();
+    let mut queue = VecDeque::from([(root, 0)]);
+    seen.insert(root);
+
+    while let Some((tag, depth)) = queue.pop_front() {
+        if depth_limit.map(|l| depth >= l).unwrap_or_default() {
+            continue;
+        }
+
+        let Some(scan) = cache.hashes.get(&tag) else {
+            continue;
+        };
+
+        for (target, _) in references(scan) {
+            if seen.insert(target) {
+                queue.push_back((target, depth + 1));
+            }
+        }
+    }
+
+    seen
+}
+
+/// Edges between `tags`, with every reference from one tag to another merged into a single edge
+fn graph_edges(
+    cache: &TagCache,
+    tags: &IntSet<TagHash>,
+    references: impl Fn(&ScanResult) -> Vec<(TagHash, u64)>,
+) -> Vec<GraphEdge> {
+    let mut edges = vec![];
+    for tag in tags.iter().sorted_by_key(|t| t.0) {
+        let Some(scan) = cache.hashes.get(tag) else {
+            continue;
+        };
+
+        let mut targets: IntMap<TagHash, GraphEdge> = Default::default();
+        for (target, offset) in references(scan) {
+            if !tags.contains(&target) {
+                continue;
+            }
+
+            let edge = targets.entry(target).or_insert_with(|| GraphEdge {
+                source: *tag,
+                target,
+                offsets: vec![],
+                tag_header: false,
+            });
+
+            if offset == u64::MAX {
+                edge.tag_header = true;
+            } else {
+                edge.offsets.push(offset);
+            }
+        }
+
+        edges.extend(
+            targets
+                .into_values()
+                .sorted_by_key(|e| e.target.0)
+                .map(|mut e| {
+                    e.offsets.sort_unstable();
+                    e
+                }),
+        );
+    }
+
+    edges
+}
+
+/// Outgoing references of a tag, with 64-bit hashes converted to 32-bit ones
+pub fn outgoing_references(scan: &ScanResult) -> impl Iterator<Item = (TagHash, u64)> + '_ {
+    outgoing_references_in(package_manager(), scan)
+}
+
+/// Like [`outgoing_references`], converting 64-bit hashes with the given package manager
+pub fn outgoing_references_in(
+    pm: Arc<PackageManager>,
+    scan: &ScanResult,
+) -> impl Iterator<Item = (TagHash, u64)> + '_ {
+    scan.file_hashes.iter().map(|h| (h.hash, h.offset)).chain(
+        scan.file_hashes64
+            .iter()
+            .filter_map(move |h| pm.hash64_table.get(&h.hash.0).map(|e| (e.hash32, h.offset))),
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::ScannedHash;
+
+    fn tag(entry: u16) -> TagHash {
+        TagHash::new(0x100, entry)
+    }
+
+    /// Cache with a scanned tag for every source, with references at the given offsets
+    fn cache(references: &[(u16, u16, u64)]) -> TagCache {
+        let mut cache = TagCache::default();
+        for &(source, target, offset) in references {
+            cache
+                .hashes
+                .entry(tag(source))
+                .or_default()
+                .file_hashes
+                .push(ScannedHash {
+                    offset,
+                    hash: tag(target),
+                });
+        }
+
+        cache
+    }
+
+    fn references(scan: &ScanResult) -> Vec<(TagHash, u64)> {
+        scan.file_hashes
+            .iter()
+            .map(|h| (h.hash, h.offset))
+            .collect()
+    }
+
+    fn reachable(cache: &TagCache, depth_limit: Option<usize>) -> Vec<u16> {
+        reachable_tags(cache, tag(1), depth_limit, references)
+            .into_iter()
+            .map(|t| t.entry_index())
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn reachable_depth_limit() {
+        // 6 is only referenced by a tag that isn't in the cache, 1 is referenced back by 4
+        let cache = cache(&[
+            (1, 2, 0),
+            (1, 5, 4),
+            (2, 3, 0),
+            (3, 4, 0),
+            (4, 1, 0),
+            (7, 6, 0),
+        ]);
+
+        assert_eq!(reachable(&cache, Some(0)), [1]);
+        assert_eq!(reachable(&cache, Some(1)), [1, 2, 5]);
+        assert_eq!(reachable(&cache, Some(2)), [1, 2, 3, 5]);
+        assert_eq!(reachable(&cache, None), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn merged_edges() {
+        let cache = cache(&[
+            (1, 2, 0x20),
+            (1, 3, 0x10),
+            (1, 2, u64::MAX),
+            (1, 2, 0x8),
+            (1, 9, 0x4),
+            (3, 1, 0x0),
+        ]);
+        let tags = [1, 2, 3].map(tag).into_iter().collect();
+
+        let edges = graph_edges(&cache, &tags, references)
+            .into_iter()
+            .map(|e| {
+                (
+                    e.source.entry_index(),
+                    e.target.entry_index(),
+                    e.offsets,
+                    e.tag_header,
+                )
+            })
+            .collect_vec();
+
+        assert_eq!(
+            edges,
+            [
+                (1, 2, vec![0x8, 0x20], true),
+                (1, 3, vec![0x10], false),
+                (3, 1, vec![0x0], false),
+            ]
+        );
+    }
+
+    fn graph() -> ReferenceGraph {
+        ReferenceGraph {
+            nodes: vec![
+                GraphNode {
+                    tag: tag(1),
+                    name: Some("a<b> & \"c\"".to_string()),
+                    tag_type: Some("Tag".to_string()),
+                    file_type: Some(8),
+                    file_subtype: Some(0),
+                    package: "w64_0100.pkg".to_string(),
+                    reference: Some(TagHash(0x80801234)),
+                    reference_name: Some("Class".to_string()),
+                },
+                GraphNode {
+                    tag: tag(2),
+                    name: None,
+                    tag_type: None,
+                    file_type: None,
+                    file_subtype: None,
+                    package: String::new(),
+                    reference: None,
+                    reference_name: None,
+                },
+            ],
+            edges: vec![GraphEdge {
+                source: tag(1),
+                target: tag(2),
+                offsets: vec![0x8, 0x20],
+                tag_header: true,
+            }],
+        }
+    }
+
+    fn written(format: GraphFormat) -> String {
+        let mut out = vec![];
+        graph().write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn graphml() {
+        let out = written(GraphFormat::Graphml);
+        let (t1, t2) = (tag(1), tag(2));
+
+        assert!(out.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+        assert!(out
+            .contains(r#"<key id="file_type" for="node" attr.name="file_type" attr.type="int"/>"#));
+        assert!(out.contains(r#"<data key="name">a&lt;b&gt; &amp; &quot;c&quot;</data>"#));
+        assert!(out.contains(&format!(
+            r#"<data key="label">{t1} a&lt;b&gt; &amp; &quot;c&quot;</data>"#
+        )));
+        assert!(out.contains(&format!(
+            "    <node id=\"{t2}\">\n      <data key=\"package\"></data>\n      \
+             <data key=\"label\">{t2}</data>\n    </node>\n"
+        )));
+        assert!(out.contains(&format!(
+            "    <edge source=\"{t1}\" target=\"{t2}\">\n      <data key=\"count\">3</data>\n      \
+             <data key=\"offsets\">0x8 0x20</data>\n      <data key=\"tag_header\">true</data>\n"
+        )));
+        assert!(out.ends_with("  </graph>\n</graphml>\n"));
+    }
+
+    #[test]
+    fn dot() {
+        let (t1, t2) = (tag(1), tag(2));
+        let expected = [
+            "digraph references {".to_string(),
+            format!(
+                "  \"{t1}\" [name=\"a<b> & \\\"c\\\"\", tag_type=\"Tag\", file_type=\"8\", \
+                 file_subtype=\"0\", package=\"w64_0100.pkg\", reference=\"{}\", \
+                 reference_name=\"Class\", label=\"{t1} a<b> & \\\"c\\\"\"];",
+                TagHash(0x80801234)
+            ),
+            format!("  \"{t2}\" [package=\"\", label=\"{t2}\"];"),
+            format!(
+                "  \"{t1}\" -> \"{t2}\" [count=\"3\", offsets=\"0x8 0x20\", tag_header=\"true\"];"
+            ),
+            "}".to_string(),
+            String::new(),
+        ];
+
+        assert_eq!(written(GraphFormat::Dot), expected.join("\n"));
+    }
+
+    #[test]
+    fn json_missing_entry() {
+        let value: serde_json::Value = serde_json::from_str(&written(GraphFormat::Json)).unwrap();
+
+        assert_eq!(value["nodes"][0]["file_type"], 8);
+        assert_eq!(
+            value["nodes"][0]["reference"],
+            TagHash(0x80801234).to_string()
+        );
+        assert!(value["nodes"][1]["file_type"].is_null());
+        assert!(value["nodes"][1]["reference"].is_null());
+        assert_eq!(value["edges"][0]["offsets"], serde_json::json!([8, 32]));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">&amp;</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;amp;&lt;/a&gt;"
+        );
+        assert_eq!(escape_dot(r#"C:\tags\"x""#), r#"C:\\tags\\\"x\""#);
+        assert_eq!(escape_dot("plain"), "plain");
+    }
+}
@@ -17,11 +17,12 @@ use eframe::{
     epaint::{Color32, Rounding, Vec2},
 };
 use egui_notify::Toasts;
+use log::error;
 use poll_promise::Promise;
 
 use crate::{
-    packages::package_manager,
-    scanner::{load_tag_cache, scanner_progress, ScanStatus, TagCache},
+    packages::{package_manager, parse_tag},
+    scanner::{load_tag_cache, scanner_progress, FutureCacheError, ScanStatus, TagCache},
     text::{create_stringmap, StringCache},
 };
 
@@ -82,7 +83,7 @@ impl QuickTagApp {
 
         QuickTagApp {
             cache_load: Some(Promise::spawn_thread("load_cache", move || {
-                load_tag_cache(version)
+                load_tag_cache(version).unwrap_or_else(show_cache_error)
             })),
             cache: Default::default(),
             tag_view: None,
@@ -101,6 +102,38 @@ impl QuickTagApp {
     }
 }
 
+/// Reports a failed cache load, exiting if the cache is from a newer version of quicktag
+fn show_cache_error(e: anyhow::Error) -> TagCache {
+    error!("Failed to load tag cache: {e:?}");
+
+    if let Some(future) = e.downcast_ref::<FutureCacheError>() {
+        native_dialog::MessageDialog::new()
+            .set_type(native_dialog::MessageType::Error)
+            .set_title("Future cache")
+            .set_text(&format!(
+                "Your cache file ({}) is newer than this build of quicktag\n\nCache version: v{}\nExpected version: v{}",
+                future.cache_name,
+                future.cache_version,
+                TagCache::default().version
+            ))
+            .show_alert()
+            .ok();
+
+        std::process::exit(21);
+    }
+
+    native_dialog::MessageDialog::new()
+        .set_type(native_dialog::MessageType::Error)
+        .set_title("Failed to load tag cache")
+        .set_text(&format!(
+            "{e:#}\n\nQuicktag will continue without a tag cache"
+        ))
+        .show_alert()
+        .ok();
+
+    TagCache::default()
+}
+
 impl eframe::App for QuickTagApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         let mut is_loading_cache = false;
@@ -162,26 +195,7 @@ impl eframe::App for QuickTagApp {
                     let submitted = ui.text_edit_singleline(&mut self.tag_input).lost_focus()
                         && ui.input(|i| i.key_pressed(egui::Key::Enter));
                     if ui.button("Open").clicked() || submitted {
-                        let tag_input_trimmed = self.tag_input.trim();
-                        let tag = if tag_input_trimmed.len() >= 16 {
-                            let hash =
-                                u64::from_str_radix(tag_input_trimmed, 16).unwrap_or_default();
-                            if let Some(t) = package_manager().hash64_table.get(&u64::from_be(hash))
-                            {
-                                t.hash32
-                            } else {
-                                TagHash::NONE
-                            }
-                        } else if tag_input_trimmed.len() > 8
-                            && tag_input_trimmed.chars().all(char::is_numeric)
-                        {
-                            let hash = tag_input_trimmed.parse().unwrap_or_default();
-                            TagHash(hash)
-                        } else {
-                            let hash =
-                                u32::from_str_radix(tag_input_trimmed, 16).unwrap_or_default();
-                            TagHash(u32::from_be(hash))
-                        };
+                        let tag = parse_tag(&self.tag_input);
 
                         self.open_tag(tag);
                     }
@@ -1,9 +1,9 @@
 use destiny_pkg::{package::UEntryHeader, PackageNamedTagEntry};
 use eframe::egui::{self, RichText};
 
-use crate::{packages::package_manager, tagtypes::TagType};
+use crate::{packages::package_manager, tagtypes::TagType, traversal::format_tag_entry};
 
-use super::{common::tag_context, tag::format_tag_entry, View, ViewAction};
+use super::{common::tag_context, View, ViewAction};
 
 pub struct NamedTags {
     pub tags: Vec<(UEntryHeader, PackageNamedTagEntry)>,
@@ -3,9 +3,9 @@ use std::path::PathBuf;
 use destiny_pkg::TagHash;
 use eframe::egui::{self, RichText};
 
-use crate::{packages::package_manager, tagtypes::TagType};
+use crate::{packages::package_manager, tagtypes::TagType, traversal::format_tag_entry};
 
-use super::{common::tag_context, tag::format_tag_entry, View, ViewAction};
+use super::{common::tag_context, View, ViewAction};
 
 pub struct PackagesView {
     selected_package: u16,
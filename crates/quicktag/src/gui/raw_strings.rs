@@ -11,9 +11,10 @@ use crate::{
     packages::package_manager,
     scanner::{fnv1, TagCache},
     tagtypes::TagType,
+    traversal::format_tag_entry,
 };
 
-use super::{common::tag_context, tag::format_tag_entry, View, ViewAction};
+use super::{common::tag_context, View, ViewAction};
 
 pub struct RawStringsView {
     strings: Vec<(String, Vec<TagHash>, u32)>,
@@ -9,9 +9,10 @@ use crate::{
     scanner::TagCache,
     tagtypes::TagType,
     text::{StringCache, StringCacheVec},
+    traversal::format_tag_entry,
 };
 
-use super::{common::tag_context, tag::format_tag_entry, View, ViewAction};
+use super::{common::tag_context, View, ViewAction};
 
 pub struct StringsView {
     cache: Arc<TagCache>,
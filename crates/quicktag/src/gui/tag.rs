@@ -1,12 +1,9 @@
 use std::{
-    fmt::Display,
-    io::{Cursor, Seek, SeekFrom},
     path::Path,
     sync::Arc,
     time::{Duration, Instant},
 };
 
-use binrw::{binread, BinReaderExt};
 use destiny_pkg::{package::UEntryHeader, TagHash, TagHash64};
 use eframe::egui::load::SizedTexture;
 use eframe::egui::{vec2, TextureId};
@@ -18,17 +15,20 @@ use eframe::{
 };
 use itertools::Itertools;
 use log::error;
-use nohash_hasher::{IntMap, IntSet};
+use nohash_hasher::IntMap;
 use poll_promise::Promise;
-use std::fmt::Write;
 
 use crate::{gui::texture::Texture, scanner::read_raw_string_blob, util::u32_from_endian};
 use crate::{
-    packages::package_manager,
-    references::REFERENCE_NAMES,
-    scanner::{ScanResult, TagCache},
+    packages::{package_manager, parse_tag},
+    pathfinding::{find_reference_paths, ReferencePath},
+    references::{REFERENCE_FIELD_NOTES, REFERENCE_NAMES},
+    scanner::{
+        tag_regions, ScannedArray, ScannedPointer, TagCache, TagRegion, RAW_STRING_BLOB_CLASS,
+    },
     tagtypes::TagType,
     text::StringCache,
+    traversal::{format_tag_entry, traverse_tags, ExtendedScanResult, ExtendedTagHash},
 };
 
 use super::{
@@ -43,7 +43,9 @@ pub struct TagView {
     string_cache: Arc<StringCache>,
     string_hashes: Vec<(u64, u32)>,
     raw_strings: Vec<(u64, String)>,
-    arrays: Vec<(u64, TagArray)>,
+    arrays: Vec<ScannedArray>,
+    relative_pointers: Vec<ScannedPointer>,
+    regions: Vec<TagRegion>,
 
     textures: IntMap<TagHash, (Texture, TextureId)>,
 
@@ -56,6 +58,9 @@ pub struct TagView {
     tag_traversal: Option<Promise<String>>,
     traversal_depth_limit: usize,
     traversal_show_strings: bool,
+    path_target_input: String,
+    path_count: usize,
+    path_search: Option<Promise<Vec<ReferencePath>>>,
     start_time: Instant,
 
     render_state: RenderState,
@@ -69,7 +74,6 @@ impl TagView {
         render_state: RenderState,
     ) -> Option<TagView> {
         let tag_data = package_manager().read_tag(tag).ok()?;
-        let mut array_offsets = vec![];
         let mut raw_string_offsets = vec![];
         let mut string_hashes = vec![];
 
@@ -79,11 +83,7 @@ impl TagView {
             let value = u32_from_endian(endian, v);
             let offset = i as u64 * 4;
 
-            if matches!(value, 0x80809fb8 | 0x80800184) {
-                array_offsets.push(offset + 4);
-            }
-
-            if value == 0x80800065 {
+            if value == RAW_STRING_BLOB_CLASS {
                 raw_string_offsets.push(offset);
             }
 
@@ -97,52 +97,6 @@ impl TagView {
             .flat_map(|o| read_raw_string_blob(&tag_data, o))
             .collect_vec();
 
-        let mut arrays: Vec<(u64, TagArray)> = if package_manager().version.is_d1() {
-            array_offsets
-                .into_iter()
-                .filter_map(|o| {
-                    let mut c = Cursor::new(&tag_data);
-                    c.seek(SeekFrom::Start(o)).ok()?;
-                    Some((
-                        o,
-                        TagArray {
-                            count: c.read_be::<u32>().ok()? as _,
-                            tagtype: c.read_be::<u32>().ok()?,
-                            references: vec![],
-                        },
-                    ))
-                })
-                .collect_vec()
-        } else {
-            array_offsets
-                .into_iter()
-                .filter_map(|o| {
-                    let mut c = Cursor::new(&tag_data);
-                    c.seek(SeekFrom::Start(o)).ok()?;
-                    Some((o, c.read_le().ok()?))
-                })
-                .collect_vec()
-        };
-
-        let mut cur = Cursor::new(&tag_data);
-        loop {
-            let offset = cur.stream_position().unwrap();
-            let Ok((value1, value2)) = cur.read_le::<(u64, u64)>() else {
-                break;
-            };
-
-            let possibly_count = value1;
-            let possibly_array_offset = (offset + 8).saturating_add(value2);
-
-            if let Some((_, array)) = arrays.iter_mut().find(|(offset, arr)| {
-                *offset == possibly_array_offset && arr.count == possibly_count
-            }) {
-                array.references.push(offset);
-            }
-
-            cur.seek(SeekFrom::Current(-8)).unwrap();
-        }
-
         let tag64 = package_manager()
             .hash64_table
             .iter()
@@ -150,7 +104,11 @@ impl TagView {
             .map(|(&h64, _)| TagHash64(h64));
 
         let tag_entry = package_manager().get_entry(tag)?;
-        let scan = ExtendedScanResult::from_scanresult(cache.hashes.get(&tag).cloned()?);
+        let scan_result = cache.hashes.get(&tag).cloned()?;
+        let arrays = scan_result.arrays.clone();
+        let relative_pointers = scan_result.relative_pointers.clone();
+        let regions = tag_regions(tag_data.len() as u64, &arrays);
+        let scan = ExtendedScanResult::from_scanresult(scan_result);
 
         let mut textures: IntMap<TagHash, (Texture, TextureId)> = Default::default();
         for hash in &scan.file_hashes {
@@ -178,6 +136,8 @@ impl TagView {
 
         Some(Self {
             arrays,
+            relative_pointers,
+            regions,
             string_hashes,
             tag,
             tag64,
@@ -190,6 +150,9 @@ impl TagView {
             traversal_depth_limit: 16,
             tag_traversal: None,
             traversal_show_strings: false,
+            path_target_input: String::new(),
+            path_count: 1,
+            path_search: None,
             string_cache,
             raw_strings,
             start_time: Instant::now(),
@@ -210,6 +173,30 @@ impl TagView {
             error!("Could not open new tag view for {tag} (tag not found in cache)");
         }
     }
+
+    /// Note from the reference file for the field at the given offset in the tag data
+    fn field_note(&self, offset: u64) -> Option<String> {
+        let region = self
+            .regions
+            .iter()
+            .find(|r| (r.start..r.end).contains(&offset))?;
+
+        let relative = offset - region.start;
+        let (class, field_offset) = match region.array {
+            Some((_, class)) => match region.exact_element_size().filter(|&s| s != 0) {
+                Some(size) => (class, relative % size),
+                // Padding makes the size of elements in short arrays uncertain, so only the first one is labelled
+                None => (class, relative),
+            },
+            None => (self.tag_entry.reference, relative),
+        };
+
+        REFERENCE_FIELD_NOTES
+            .read()
+            .get(&class)?
+            .get(&field_offset)
+            .cloned()
+    }
 }
 
 impl View for TagView {
@@ -299,7 +286,10 @@ impl View for TagView {
                                 let offset_label = if tag.offset == u64::MAX {
                                     "TagHeader reference".to_string()
                                 } else {
-                                    format!("0x{:X}", tag.offset)
+                                    match self.field_note(tag.offset) {
+                                        Some(note) => format!("0x{:X} ({note})", tag.offset),
+                                        None => format!("0x{:X}", tag.offset),
+                                    }
                                 };
 
                                 let tag_label = if let Some(entry) = &tag.entry {
@@ -378,6 +368,90 @@ impl View for TagView {
                 ui.heading(RichText::new("⚠ Tag data failed to read").color(Color32::YELLOW));
             }
 
+            ui.horizontal(|ui| {
+                ui.label("Find path to");
+                ui.text_edit_singleline(&mut self.path_target_input);
+                ui.add(egui::DragValue::new(&mut self.path_count).clamp_range(1..=32));
+                ui.label("Max paths");
+
+                if ui
+                    .add_enabled(
+                        self.path_search
+                            .as_ref()
+                            .map(|v| v.poll().is_ready())
+                            .unwrap_or(true),
+                        egui::Button::new("Find"),
+                    )
+                    .clicked()
+                {
+                    let target = parse_tag(&self.path_target_input);
+                    if target == TagHash::NONE {
+                        error!("Invalid tag '{}'", self.path_target_input);
+                    } else {
+                        let tag = self.tag;
+                        let cache = self.cache.clone();
+                        let count = self.path_count;
+                        self.path_search = Some(Promise::spawn_thread("find paths", move || {
+                            find_reference_paths(&cache, tag, target, count)
+                        }));
+                    }
+                }
+            });
+
+            if let Some(search) = self.path_search.as_ref() {
+                if let Some(paths) = search.ready() {
+                    if paths.is_empty() {
+                        ui.label(RichText::new("No path found").italics());
+                    }
+
+                    egui::ScrollArea::vertical()
+                        .id_source("tv_paths")
+                        .max_height(256.0)
+                        .show(ui, |ui| {
+                            for (i, path) in paths.iter().enumerate() {
+                                CollapsingHeader::new(format!(
+                                    "Path {} ({} references)",
+                                    i + 1,
+                                    path.reference_count()
+                                ))
+                                .id_source(("tv_path", i))
+                                .default_open(i == 0)
+                                .show(ui, |ui| {
+                                    for (j, hop) in path.hops.iter().enumerate() {
+                                        let label = if j == 0 {
+                                            hop.label.clone()
+                                        } else {
+                                            format!(
+                                                "@ {} → {}",
+                                                ReferencePath::offset_label(hop),
+                                                hop.label
+                                            )
+                                        };
+
+                                        if ui
+                                            .add_enabled(
+                                                hop.tag != self.tag,
+                                                egui::SelectableLabel::new(false, label),
+                                            )
+                                            .context_menu(|ui| tag_context(ui, hop.tag, None))
+                                            .clicked()
+                                        {
+                                            open_new_tag = Some(hop.tag);
+                                        }
+                                    }
+                                });
+                            }
+                        });
+                } else {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Searching for paths");
+                    });
+                }
+            }
+
+            ui.separator();
+
             if self.tag_type.is_tag() {
                 ui.horizontal(|ui| {
                     if ui
@@ -396,7 +470,7 @@ impl View for TagView {
                         let show_strings = self.traversal_show_strings;
                         self.tag_traversal =
                             Some(Promise::spawn_thread("traverse tags", move || {
-                                traverse_tags(tag, depth_limit, cache, show_strings)
+                                traverse_tags(tag, depth_limit, &cache, show_strings)
                             }));
                     }
 
@@ -437,7 +511,10 @@ impl View for TagView {
             }
         });
 
-        if !self.string_hashes.is_empty() || !self.raw_strings.is_empty() || !self.arrays.is_empty()
+        if !self.string_hashes.is_empty()
+            || !self.raw_strings.is_empty()
+            || !self.arrays.is_empty()
+            || !self.relative_pointers.is_empty()
         {
             egui::SidePanel::right("tv_right_panel")
                 .resizable(true)
@@ -445,6 +522,33 @@ impl View for TagView {
                 .show_inside(ui, |ui| {
                     ui.style_mut().wrap = Some(false);
                     egui::ScrollArea::vertical().show(ui, |ui| {
+                        CollapsingHeader::new(egui::RichText::new("Struct boundaries").strong())
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                ui.group(|ui| {
+                                    for region in &self.regions {
+                                        let label = match region.array {
+                                            None => format!(
+                                                "Root 0x{:X}..0x{:X}",
+                                                region.start, region.end
+                                            ),
+                                            Some((count, class)) => format!(
+                                                "{} x{count}{} 0x{:X}..0x{:X}",
+                                                class_label(class),
+                                                region
+                                                    .element_size()
+                                                    .map(|s| format!(" (~0x{s:X} bytes each)"))
+                                                    .unwrap_or_default(),
+                                                region.start,
+                                                region.end
+                                            ),
+                                        };
+
+                                        ui.selectable_label(false, label).clicked();
+                                    }
+                                });
+                            });
+
                         CollapsingHeader::new(egui::RichText::new("Arrays").strong())
                             .default_open(true)
                             .show(ui, |ui| {
@@ -452,22 +556,14 @@ impl View for TagView {
                                     if self.arrays.is_empty() {
                                         ui.label(RichText::new("No arrays found").italics());
                                     } else {
-                                        for (offset, array) in &self.arrays {
-                                            let ref_label = REFERENCE_NAMES
-                                                .read()
-                                                .get(&array.tagtype)
-                                                .map(|s| {
-                                                    format!("{s} ({:08X})", array.tagtype.to_be())
-                                                })
-                                                .unwrap_or_else(|| {
-                                                    format!("{:08X}", array.tagtype.to_be())
-                                                });
-
+                                        for array in &self.arrays {
                                             ui.selectable_label(
                                                 false,
                                                 format!(
                                                     "type={} count={} @ 0x{:X}",
-                                                    ref_label, array.count, offset
+                                                    class_label(array.class),
+                                                    array.count,
+                                                    array.offset
                                                 ),
                                             )
                                             .on_hover_text({
@@ -490,6 +586,29 @@ impl View for TagView {
                                 });
                             });
 
+                        CollapsingHeader::new(egui::RichText::new("Relative pointers").strong())
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                ui.group(|ui| {
+                                    if self.relative_pointers.is_empty() {
+                                        ui.label(
+                                            RichText::new("No relative pointers found").italics(),
+                                        );
+                                    } else {
+                                        for pointer in &self.relative_pointers {
+                                            ui.selectable_label(
+                                                false,
+                                                format!(
+                                                    "0x{:X} → 0x{:X}",
+                                                    pointer.offset, pointer.target
+                                                ),
+                                            )
+                                            .clicked();
+                                        }
+                                    }
+                                });
+                            });
+
                         CollapsingHeader::new(egui::RichText::new("String Hashes").strong())
                             .default_open(true)
                             .show(ui, |ui| {
@@ -572,267 +691,6 @@ impl View for TagView {
     }
 }
 
-pub enum ExtendedTagHash {
-    Hash32(TagHash),
-    Hash64(TagHash64),
-}
-
-impl ExtendedTagHash {
-    pub fn hash32(&self) -> TagHash {
-        match self {
-            ExtendedTagHash::Hash32(h) => *h,
-            ExtendedTagHash::Hash64(h) => package_manager()
-                .hash64_table
-                .get(&h.0)
-                .map(|v| v.hash32)
-                .unwrap_or(TagHash::NONE),
-        }
-    }
-}
-
-impl Display for ExtendedTagHash {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ExtendedTagHash::Hash32(h) => h.fmt(f),
-            ExtendedTagHash::Hash64(h) => h.fmt(f),
-        }
-    }
-}
-
-struct ExtendedScanResult {
-    pub successful: bool,
-    pub file_hashes: Vec<ScannedHashWithEntry<ExtendedTagHash>>,
-
-    /// References from other files
-    pub references: Vec<(TagHash, UEntryHeader)>,
-}
-
-impl ExtendedScanResult {
-    pub fn from_scanresult(s: ScanResult) -> ExtendedScanResult {
-        let mut file_hashes_combined = vec![];
-
-        file_hashes_combined.extend(s.file_hashes.into_iter().map(|s| ScannedHashWithEntry {
-            offset: s.offset,
-            hash: ExtendedTagHash::Hash32(s.hash),
-            entry: package_manager().get_entry(s.hash),
-        }));
-
-        file_hashes_combined.extend(s.file_hashes64.into_iter().map(|s| {
-            ScannedHashWithEntry {
-                offset: s.offset,
-                hash: ExtendedTagHash::Hash64(s.hash),
-                entry: package_manager().get_entry(
-                    package_manager()
-                        .hash64_table
-                        .get(&s.hash.0)
-                        .unwrap()
-                        .hash32,
-                ),
-            }
-        }));
-
-        file_hashes_combined.sort_unstable_by_key(|v| v.offset);
-
-        ExtendedScanResult {
-            successful: s.successful,
-            file_hashes: file_hashes_combined,
-            references: s
-                .references
-                .into_iter()
-                // TODO(cohae): Unwrap *should* be safe as long as the cache is valid but i want to be sure
-                .map(|t| (t, package_manager().get_entry(t).unwrap()))
-                .collect(),
-        }
-    }
-}
-
-struct ScannedHashWithEntry<T: Sized> {
-    pub offset: u64,
-    pub hash: T,
-    pub entry: Option<UEntryHeader>,
-}
-
-/// Traverses down every tag to make a hierarchy of tags
-fn traverse_tags(
-    starting_tag: TagHash,
-    depth_limit: usize,
-    cache: Arc<TagCache>,
-    show_strings: bool,
-) -> String {
-    let mut result = String::new();
-    let mut seen_tags = Default::default();
-    let mut pipe_stack = vec![];
-
-    traverse_tag(
-        &mut result,
-        starting_tag,
-        TagHash::NONE,
-        0,
-        &mut seen_tags,
-        &mut pipe_stack,
-        depth_limit,
-        cache,
-        show_strings,
-    );
-
-    result
-}
-
-#[allow(clippy::too_many_arguments)]
-fn traverse_tag(
-    out: &mut String,
-    tag: TagHash,
-    parent_tag: TagHash,
-    offset: u64,
-    seen_tags: &mut IntSet<TagHash>,
-    pipe_stack: &mut Vec<char>,
-    depth_limit: usize,
-    cache: Arc<TagCache>,
-    show_strings: bool,
-) {
-    let depth = pipe_stack.len();
-
-    let pm = package_manager();
-
-    seen_tags.insert(tag);
-
-    let entry = pm.get_entry(tag);
-    let fancy_tag = format_tag_entry(tag, entry.as_ref());
-    writeln!(out, "{fancy_tag} @ 0x{offset:X}",).ok();
-
-    if depth >= depth_limit {
-        let mut line_header = String::new();
-        for s in pipe_stack.iter() {
-            write!(line_header, "{s}   ").ok();
-        }
-
-        writeln!(out, "{line_header}└ Depth limit reached ({})", depth_limit).ok();
-
-        return;
-    }
-
-    let Some(scan_result) = cache.hashes.get(&tag).cloned() else {
-        return;
-    };
-
-    let scan = ExtendedScanResult::from_scanresult(scan_result);
-
-    // writeln!(
-    //     out,
-    //     "{} {} ({}+{}, ref {}) @ 0x{offset:X}",
-    //     tag,
-    //     TagType::from_type_subtype(entry.file_type, entry.file_subtype),
-    //     entry.file_type,
-    //     entry.file_subtype,
-    //     TagHash(entry.reference),
-    // )
-    // .ok();
-
-    let all_hashes = scan
-        .file_hashes
-        .iter()
-        .map(|v| (v.hash.hash32(), v.offset))
-        .collect_vec();
-
-    if all_hashes.is_empty() {
-        return;
-    }
-
-    let mut line_header = String::new();
-    for s in pipe_stack.iter() {
-        write!(line_header, "{s}   ").ok();
-    }
-
-    if show_strings {
-        let tag_data = package_manager().read_tag(tag).unwrap();
-        let mut raw_strings = vec![];
-        for (i, b) in tag_data.chunks_exact(4).enumerate() {
-            let v: [u8; 4] = b.try_into().unwrap();
-            let hash = u32::from_le_bytes(v);
-
-            if hash == 0x80800065 {
-                raw_strings.extend(read_raw_string_blob(&tag_data, i as u64 * 4));
-            }
-        }
-
-        if !raw_strings.is_empty() {
-            writeln!(
-                out,
-                "{line_header}├──Strings: [{}]",
-                raw_strings.into_iter().map(|(_, string)| string).join(", ")
-            )
-            .ok();
-        }
-    }
-
-    for (i, (t, offset)) in all_hashes.iter().enumerate() {
-        let branch = if i + 1 == all_hashes.len() {
-            "└"
-        } else {
-            "├"
-        };
-
-        // Last tag, add a space instead of a pipe
-        if i + 1 == all_hashes.len() {
-            pipe_stack.push(' ');
-        } else {
-            pipe_stack.push('│');
-        }
-
-        if seen_tags.contains(t) {
-            let entry = pm.get_entry(*t);
-            let fancy_tag = format_tag_entry(*t, entry.as_ref());
-
-            let offset_label = if *offset == u64::MAX {
-                "TagHeader reference".to_string()
-            } else {
-                format!("0x{:X}", offset)
-            };
-
-            if entry
-                .map(|e| e.file_type != 8 && e.file_subtype != 16)
-                .unwrap_or_default()
-            {
-                writeln!(out, "{line_header}{branch}──{fancy_tag} @ {offset_label}").ok();
-            } else if *t == parent_tag {
-                writeln!(
-                    out,
-                    "{line_header}{branch}──{fancy_tag} @ {offset_label} (parent)"
-                )
-                .ok();
-            } else if *t == tag {
-                writeln!(
-                    out,
-                    "{line_header}{branch}──{fancy_tag} @ {offset_label} (self reference)"
-                )
-                .ok();
-            } else {
-                writeln!(
-                    out,
-                    "{line_header}{branch}──{fancy_tag} @ {offset_label} (already traversed)"
-                )
-                .ok();
-            }
-        } else {
-            write!(out, "{line_header}{branch}──").ok();
-            traverse_tag(
-                out,
-                *t,
-                tag,
-                *offset,
-                seen_tags,
-                pipe_stack,
-                depth_limit,
-                cache.clone(),
-                show_strings,
-            );
-        }
-        pipe_stack.pop();
-    }
-
-    writeln!(out, "{line_header}").ok();
-}
-
 impl Drop for TagView {
     fn drop(&mut self) {
         for (_, (_, t)) in self.textures.iter() {
@@ -841,38 +699,11 @@ impl Drop for TagView {
     }
 }
 
-pub fn format_tag_entry(tag: TagHash, entry: Option<&UEntryHeader>) -> String {
-    if let Some(entry) = entry {
-        let named_tag = package_manager()
-            .named_tags
-            .iter()
-            .find(|v| v.hash == tag)
-            .map(|v| format!("{} ", v.name))
-            .unwrap_or_default();
-
-        let ref_label = REFERENCE_NAMES
-            .read()
-            .get(&entry.reference)
-            .map(|s| format!(" ({s})"))
-            .unwrap_or_default();
-
-        format!(
-            "{named_tag}{tag} {}{ref_label} ({}+{}, ref {})",
-            TagType::from_type_subtype(entry.file_type, entry.file_subtype),
-            entry.file_type,
-            entry.file_subtype,
-            TagHash(entry.reference),
-        )
-    } else {
-        format!("{} (pkg entry not found)", tag)
-    }
-}
-
-#[binread]
-struct TagArray {
-    pub count: u64,
-    pub tagtype: u32,
-
-    #[br(ignore)]
-    pub references: Vec<u64>,
+/// Name and hash of a reference class
+fn class_label(class: u32) -> String {
+    REFERENCE_NAMES
+        .read()
+        .get(&class)
+        .map(|s| format!("{s} ({:08X})", class.to_be()))
+        .unwrap_or_else(|| format!("{:08X}", class.to_be()))
 }
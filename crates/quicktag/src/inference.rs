@@ -0,0 +1,684 @@
+use std::fmt::Write;
+
+use binrw::Endian;
+use destiny_pkg::TagHash;
+use itertools::Itertools;
+use nohash_hasher::{IntMap, IntSet};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    packages::package_manager,
+    references::{REFERENCE_FIELD_NOTES, REFERENCE_NAMES},
+    scanner::{tag_regions, ScanResult, TagCache},
+    util::{u32_from_endian, u64_from_endian},
+};
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldKind {
+    /// Size of the tag data, always the first field of a tag's root structure
+    FileSize,
+    U32,
+    F32,
+    Vector4,
+    TagHash,
+    TagHash64,
+    StringHash,
+    TablePointer {
+        class: u32,
+    },
+    RelPointer,
+    U32Array {
+        count: usize,
+    },
+    Bytes {
+        count: usize,
+    },
+}
+
+impl FieldKind {
+    pub fn size(&self) -> u64 {
+        match self {
+            FieldKind::U32 | FieldKind::F32 | FieldKind::TagHash | FieldKind::StringHash => 4,
+            FieldKind::FileSize | FieldKind::TagHash64 => 8,
+            FieldKind::RelPointer => pointer_size(),
+            FieldKind::TablePointer { .. } => pointer_size() * 2,
+            FieldKind::Vector4 => 16,
+            FieldKind::U32Array { count } => *count as u64 * 4,
+            FieldKind::Bytes { count } => *count as u64,
+        }
+    }
+
+    /// Type of the field in alkahest. alkahest only has aliases for 64-bit pointers, Destiny 1 fields use the
+    /// underlying generic types with 32-bit offsets instead
+    fn rust_type(&self, d1: bool) -> String {
+        match self {
+            FieldKind::FileSize => "u64".to_string(),
+            FieldKind::U32 => "u32".to_string(),
+            FieldKind::F32 => "f32".to_string(),
+            FieldKind::Vector4 => "Vector4".to_string(),
+            FieldKind::TagHash => "TagHash".to_string(),
+            FieldKind::TagHash64 => "TagHash64".to_string(),
+            FieldKind::StringHash => "FnvHash".to_string(),
+            FieldKind::TablePointer { class } => {
+                if d1 {
+                    format!("_TablePointer<i32, u32, {}>", struct_name(*class))
+                } else {
+                    format!("TablePointer<{}>", struct_name(*class))
+                }
+            }
+            FieldKind::RelPointer => {
+                if d1 {
+                    "_RelPointer<i32, ()>".to_string()
+                } else {
+                    "RelPointer".to_string()
+                }
+            }
+            FieldKind::U32Array { count } => format!("[u32; {count}]"),
+            FieldKind::Bytes { count } => format!("[u8; {count}]"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct InferredField {
+    pub offset: u64,
+    pub kind: FieldKind,
+    /// Observed values worth pointing out, such as the values of enum-like fields
+    pub note: Option<String>,
+}
+
+/// A guess at the layout of a structure, based on every instance of its class found in the tag cache
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct InferredStruct {
+    pub class: u32,
+    pub size: u64,
+    pub instance_count: usize,
+    pub tag_count: usize,
+    pub fields: Vec<InferredField>,
+}
+
+impl InferredStruct {
+    /// Draft binrw definition, in the style of alkahest's structure definitions
+    pub fn to_rust(&self) -> String {
+        self.to_rust_for(package_manager().version.is_d1())
+    }
+
+    fn to_rust_for(&self, d1: bool) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "// D2Class_{:08X}, inferred from {} instances in {} tags",
+            self.class.to_be(),
+            self.instance_count,
+            self.tag_count
+        )
+        .ok();
+        writeln!(out, "#[derive(BinRead, Debug)]").ok();
+        writeln!(out, "pub struct {} {{", struct_name(self.class)).ok();
+
+        for field in &self.fields {
+            let name = match field.kind {
+                FieldKind::FileSize => "file_size".to_string(),
+                _ => format!("unk{:x}", field.offset),
+            };
+
+            write!(out, "    pub {name}: {},", field.kind.rust_type(d1)).ok();
+            if let Some(note) = &field.note {
+                write!(out, " // {note}").ok();
+            }
+            writeln!(out).ok();
+        }
+
+        writeln!(out, "}}").ok();
+
+        out
+    }
+}
+
+/// Name of the struct for a class, the known name if there is one
+pub fn struct_name(class: u32) -> String {
+    REFERENCE_NAMES
+        .read()
+        .get(&class)
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("Unk{class:08x}"))
+}
+
+fn pointer_size() -> u64 {
+    if package_manager().version.is_d1() {
+        4
+    } else {
+        8
+    }
+}
+
+/// What the scanner found in a tag, by offset
+struct TagFacts {
+    data: Vec<u8>,
+    endian: Endian,
+    tag_hashes: IntSet<u64>,
+    tag_hashes64: IntSet<u64>,
+    string_hashes: IntSet<u64>,
+    /// Table pointers along with the class of the array they point to
+    table_pointers: IntMap<u64, u32>,
+    relative_pointers: IntSet<u64>,
+}
+
+impl TagFacts {
+    fn new(data: Vec<u8>, endian: Endian, scan: &ScanResult) -> Self {
+        Self {
+            data,
+            endian,
+            tag_hashes: scan.file_hashes.iter().map(|h| h.offset).collect(),
+            tag_hashes64: scan.file_hashes64.iter().map(|h| h.offset).collect(),
+            string_hashes: scan.string_hashes.iter().map(|h| h.offset).collect(),
+            table_pointers: scan
+                .arrays
+                .iter()
+                .flat_map(|a| a.references.iter().map(|&o| (o, a.class)))
+                .collect(),
+            relative_pointers: scan.relative_pointers.iter().map(|p| p.offset).collect(),
+        }
+    }
+}
+
+struct Instance<'a> {
+    facts: &'a TagFacts,
+    base: u64,
+    is_root: bool,
+}
+
+impl Instance<'_> {
+    fn u32(&self, offset: u64) -> u32 {
+        let o = (self.base + offset) as usize;
+        u32_from_endian(
+            self.facts.endian,
+            self.facts.data[o..o + 4].try_into().unwrap(),
+        )
+    }
+
+    fn u64(&self, offset: u64) -> u64 {
+        let o = (self.base + offset) as usize;
+        u64_from_endian(
+            self.facts.endian,
+            self.facts.data[o..o + 8].try_into().unwrap(),
+        )
+    }
+
+    fn is_zero(&self, offset: u64, size: u64) -> bool {
+        let o = (self.base + offset) as usize;
+        self.facts.data[o..o + size as usize]
+            .iter()
+            .all(|&b| b == 0)
+    }
+}
+
+/// Gathers every instance of a class, either as the root structure of a tag or as the elements of an array, and
+/// guesses the type of every field from the values found in them. Reads at most `max_tags` tags.
+pub fn infer_struct_layout(
+    cache: &TagCache,
+    class: u32,
+    max_tags: usize,
+) -> anyhow::Result<InferredStruct> {
+    let pm = package_manager();
+
+    let tags = cache
+        .hashes
+        .iter()
+        .filter(|(tag, scan)| {
+            scan.arrays.iter().any(|a| a.class == class && a.count > 0)
+                || pm
+                    .get_entry(**tag)
+                    .map(|e| e.reference == class)
+                    .unwrap_or_default()
+        })
+        .map(|(tag, _)| *tag)
+        .sorted_by_key(|t| t.0)
+        .take(max_tags)
+        .collect_vec();
+
+    anyhow::ensure!(
+        !tags.is_empty(),
+        "No instances of class {:08X} found",
+        class.to_be()
+    );
+
+    let endian = pm.version.endian();
+    let facts: Vec<(TagHash, TagFacts)> = tags
+        .par_iter()
+        .filter_map(|&tag| {
+            let data = package_manager().read_tag(tag).ok()?;
+            Some((tag, TagFacts::new(data, endian, cache.hashes.get(&tag)?)))
+        })
+        .collect();
+
+    // Regions holding instances, as (facts, start, end, element count), with the root structure as a single element
+    let mut regions = vec![];
+    for (tag, facts) in &facts {
+        let scan = &cache.hashes[tag];
+        let is_root = pm
+            .get_entry(*tag)
+            .map(|e| e.reference == class)
+            .unwrap_or_default();
+
+        for region in tag_regions(facts.data.len() as u64, &scan.arrays) {
+            match region.array {
+                None if is_root => regions.push((facts, region.start, region.end, None)),
+                Some((count, c)) if c == class && count > 0 => {
+                    regions.push((facts, region.start, region.end, Some(count)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Array elements and root structures are padded, the most common size is the most likely one
+    let size = regions
+        .iter()
+        .map(|(_, start, end, count)| (end - start) / count.unwrap_or(1) as u64)
+        .filter(|&s| s != 0)
+        .counts()
+        .into_iter()
+        .max_by_key(|&(s, n)| (n, std::cmp::Reverse(s)))
+        .map(|(s, _)| s)
+        .ok_or_else(|| anyhow::anyhow!("All instances of {:08X} are empty", class.to_be()))?;
+
+    let mut instances = vec![];
+    for &(facts, start, end, count) in &regions {
+        for i in 0..count.unwrap_or(1) as u64 {
+            let base = start + i * size;
+            if base + size > end {
+                break;
+            }
+
+            instances.push(Instance {
+                facts,
+                base,
+                is_root: count.is_none(),
+            });
+        }
+    }
+
+    anyhow::ensure!(
+        !instances.is_empty(),
+        "No complete instances of {:08X} found",
+        class.to_be()
+    );
+
+    let mut fields = infer_fields(&instances, size);
+    if let Some(notes) = REFERENCE_FIELD_NOTES.read().get(&class) {
+        for field in &mut fields {
+            if let Some(note) = notes.get(&field.offset) {
+                field.note = Some(match &field.note {
+                    Some(observed) => format!("{note} ({observed})"),
+                    None => note.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(InferredStruct {
+        class,
+        size,
+        instance_count: instances.len(),
+        tag_count: facts.len(),
+        fields,
+    })
+}
+
+fn infer_fields(instances: &[Instance], size: u64) -> Vec<InferredField> {
+    let pointer_size = pointer_size();
+    let mut fields = vec![];
+
+    // Every instance either has the thing at this offset, or nothing at all
+    let present_or_zero = |offset: u64, size: u64, present: &dyn Fn(&Instance) -> bool| {
+        let mut any = false;
+        for i in instances {
+            if present(i) {
+                any = true;
+            } else if !i.is_zero(offset, size) {
+                return false;
+            }
+        }
+
+        any
+    };
+
+    let mut offset = 0;
+    while offset + 4 <= size {
+        let fits = |s: u64| offset % s.min(8) == 0 && offset + s <= size;
+
+        if offset == 0
+            && fits(8)
+            && instances
+                .iter()
+                .all(|i| i.is_root && i.u64(0) == i.facts.data.len() as u64)
+        {
+            fields.push(field(offset, FieldKind::FileSize, None));
+            offset += 8;
+            continue;
+        }
+
+        if fits(pointer_size * 2)
+            && present_or_zero(offset, pointer_size * 2, &|i| {
+                i.facts.table_pointers.contains_key(&(i.base + offset))
+            })
+        {
+            let class = instances
+                .iter()
+                .filter_map(|i| i.facts.table_pointers.get(&(i.base + offset)))
+                .counts()
+                .into_iter()
+                .max_by_key(|&(_, n)| n)
+                .map(|(&c, _)| c)
+                .unwrap();
+
+            fields.push(field(offset, FieldKind::TablePointer { class }, None));
+            offset += pointer_size * 2;
+            continue;
+        }
+
+        if fits(pointer_size)
+            && present_or_zero(offset, pointer_size, &|i| {
+                i.facts.relative_pointers.contains(&(i.base + offset))
+            })
+        {
+            fields.push(field(offset, FieldKind::RelPointer, None));
+            offset += pointer_size;
+            continue;
+        }
+
+        if fits(8)
+            && present_or_zero(offset, 8, &|i| {
+                i.facts.tag_hashes64.contains(&(i.base + offset))
+            })
+        {
+            fields.push(field(offset, FieldKind::TagHash64, None));
+            offset += 8;
+            continue;
+        }
+
+        fields.push(infer_u32_field(instances, offset));
+        offset += 4;
+    }
+
+    if offset < size {
+        fields.push(field(
+            offset,
+            FieldKind::Bytes {
+                count: (size - offset) as usize,
+            },
+            None,
+        ));
+    }
+
+    merge_fields(fields)
+}
+
+fn infer_u32_field(instances: &[Instance], offset: u64) -> InferredField {
+    let values = instances.iter().map(|i| i.u32(offset)).collect_vec();
+
+    // Hash fields are either a valid hash or unset
+    let set = instances
+        .iter()
+        .zip(&values)
+        .filter(|(_, &v)| v != 0 && v != u32::MAX)
+        .map(|(i, _)| i)
+        .collect_vec();
+
+    if set.is_empty() {
+        return field(offset, FieldKind::U32, None);
+    }
+
+    let mostly = |count: usize| count * 10 >= set.len() * 9;
+    if mostly(
+        set.iter()
+            .filter(|i| i.facts.tag_hashes.contains(&(i.base + offset)))
+            .count(),
+    ) {
+        return field(offset, FieldKind::TagHash, None);
+    }
+
+    if mostly(
+        set.iter()
+            .filter(|i| i.facts.string_hashes.contains(&(i.base + offset)))
+            .count(),
+    ) {
+        return field(offset, FieldKind::StringHash, None);
+    }
+
+    let nonzero = values.iter().copied().filter(|&v| v != 0).collect_vec();
+    let floats = nonzero
+        .iter()
+        .map(|&v| f32::from_bits(v))
+        .filter(|f| f.is_normal() && (1e-6..1e7).contains(&f.abs()))
+        .collect_vec();
+
+    if !nonzero.is_empty() && floats.len() * 10 >= nonzero.len() * 9 {
+        let (min, max) = floats.iter().fold((f32::MAX, f32::MIN), |(min, max), &f| {
+            (min.min(f), max.max(f))
+        });
+
+        let note = (floats.len() == nonzero.len()).then(|| format!("{min} to {max}"));
+        return field(offset, FieldKind::F32, note);
+    }
+
+    let distinct = values.iter().copied().unique().sorted().collect_vec();
+    if distinct.len() <= 8 && distinct.iter().all(|&v| v < 0x10000) && values.len() > 1 {
+        return field(
+            offset,
+            FieldKind::U32,
+            Some(format!("values: {}", distinct.iter().join(", "))),
+        );
+    }
+
+    field(offset, FieldKind::U32, None)
+}
+
+fn field(offset: u64, kind: FieldKind, note: Option<String>) -> InferredField {
+    InferredField { offset, kind, note }
+}
+
+/// Merges four aligned floats into vectors and runs of plain integers into arrays
+fn merge_fields(fields: Vec<InferredField>) -> Vec<InferredField> {
+    let mut merged: Vec<InferredField> = vec![];
+    let mut i = 0;
+    while i < fields.len() {
+        let f = &fields[i];
+
+        let is_float_run = f.offset % 16 == 0
+            && fields.len() >= i + 4
+            && fields[i..i + 4]
+                .iter()
+                .enumerate()
+                .all(|(j, g)| g.kind == FieldKind::F32 && g.offset == f.offset + j as u64 * 4);
+        if is_float_run {
+            merged.push(field(f.offset, FieldKind::Vector4, None));
+            i += 4;
+            continue;
+        }
+
+        let plain_run = fields[i..]
+            .iter()
+            .enumerate()
+            .take_while(|(j, g)| {
+                g.kind == FieldKind::U32 && g.note.is_none() && g.offset == f.offset + *j as u64 * 4
+            })
+            .count();
+        if plain_run >= 4 {
+            merged.push(field(
+                f.offset,
+                FieldKind::U32Array { count: plain_run },
+                None,
+            ));
+            i += plain_run;
+            continue;
+        }
+
+        merged.push(f.clone());
+        i += 1;
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One single-field instance per value, laid out back to back
+    fn facts(values: &[u32], tag_hashes: &[usize], string_hashes: &[usize]) -> TagFacts {
+        TagFacts {
+            data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            endian: Endian::Little,
+            tag_hashes: tag_hashes.iter().map(|&i| i as u64 * 4).collect(),
+            tag_hashes64: Default::default(),
+            string_hashes: string_hashes.iter().map(|&i| i as u64 * 4).collect(),
+            table_pointers: Default::default(),
+            relative_pointers: Default::default(),
+        }
+    }
+
+    fn infer(facts: &TagFacts) -> InferredField {
+        let instances = (0..facts.data.len() as u64 / 4)
+            .map(|i| Instance {
+                facts,
+                base: i * 4,
+                is_root: false,
+            })
+            .collect_vec();
+
+        infer_u32_field(&instances, 0)
+    }
+
+    #[test]
+    fn u32_hashes() {
+        let field = infer(&facts(
+            &[0x80a0_1234, 0, 0x80a0_5678, u32::MAX],
+            &[0, 2],
+            &[],
+        ));
+        assert_eq!(field.kind, FieldKind::TagHash);
+
+        // Nine out of ten set values are enough
+        let values = (1..=10).collect_vec();
+        let field = infer(&facts(&values, &[], &(0..9).collect_vec()));
+        assert_eq!(field.kind, FieldKind::StringHash);
+
+        let field = infer(&facts(&[0x1234, 0x5678], &[], &[0]));
+        assert_ne!(field.kind, FieldKind::StringHash);
+    }
+
+    #[test]
+    fn u32_floats() {
+        let values = [1.5f32, -2.0, 0.0, 100.0].map(f32::to_bits);
+        let field = infer(&facts(&values, &[], &[]));
+        assert_eq!(field.kind, FieldKind::F32);
+        assert_eq!(field.note.as_deref(), Some("-2 to 100"));
+    }
+
+    #[test]
+    fn u32_values() {
+        let field = infer(&facts(&[2, 0, 1, 2, 1], &[], &[]));
+        assert_eq!(field.kind, FieldKind::U32);
+        assert_eq!(field.note.as_deref(), Some("values: 0, 1, 2"));
+
+        // Too many distinct values to be enum-like
+        let field = infer(&facts(&(0..20).collect_vec(), &[], &[]));
+        assert_eq!((field.kind, field.note), (FieldKind::U32, None));
+
+        let field = infer(&facts(&[0, 0, 0], &[], &[]));
+        assert_eq!((field.kind, field.note), (FieldKind::U32, None));
+    }
+
+    #[test]
+    fn merge_vectors() {
+        let fields = (0..6)
+            .map(|i| field(0x10 + i * 4, FieldKind::F32, None))
+            .collect_vec();
+
+        let merged = merge_fields(fields);
+        assert_eq!(
+            merged.iter().map(|f| (f.offset, &f.kind)).collect_vec(),
+            [
+                (0x10, &FieldKind::Vector4),
+                (0x20, &FieldKind::F32),
+                (0x24, &FieldKind::F32)
+            ]
+        );
+
+        // Unaligned runs stay separate floats
+        let fields = (0..4)
+            .map(|i| field(0x14 + i * 4, FieldKind::F32, None))
+            .collect_vec();
+        assert!(merge_fields(fields)
+            .iter()
+            .all(|f| f.kind == FieldKind::F32));
+    }
+
+    #[test]
+    fn merge_u32_runs() {
+        let mut fields = (0..5)
+            .map(|i| field(i * 4, FieldKind::U32, None))
+            .collect_vec();
+        fields.push(field(
+            0x14,
+            FieldKind::U32,
+            Some("values: 1, 2".to_string()),
+        ));
+        fields.extend((0..3).map(|i| field(0x18 + i * 4, FieldKind::U32, None)));
+
+        let merged = merge_fields(fields);
+        assert_eq!(
+            merged.iter().map(|f| (f.offset, &f.kind)).collect_vec(),
+            [
+                (0, &FieldKind::U32Array { count: 5 }),
+                (0x14, &FieldKind::U32),
+                (0x18, &FieldKind::U32),
+                (0x1c, &FieldKind::U32),
+                (0x20, &FieldKind::U32)
+            ]
+        );
+        assert!(merged[1].note.is_some());
+    }
+
+    #[test]
+    fn rust_definition() {
+        let layout = InferredStruct {
+            class: 0x80801234,
+            size: 0x40,
+            instance_count: 12,
+            tag_count: 3,
+            fields: vec![
+                field(0, FieldKind::FileSize, None),
+                field(0x8, FieldKind::TagHash, None),
+                field(0xc, FieldKind::U32, Some("values: 1, 2".to_string())),
+                field(0x10, FieldKind::TablePointer { class: 0x80805678 }, None),
+                field(0x20, FieldKind::RelPointer, None),
+                field(0x28, FieldKind::Bytes { count: 8 }, None),
+                field(0x30, FieldKind::Vector4, None),
+            ],
+        };
+
+        assert_eq!(
+            layout.to_rust_for(false),
+            "// D2Class_34128080, inferred from 12 instances in 3 tags
+#[derive(BinRead, Debug)]
+pub struct Unk80801234 {
+    pub file_size: u64,
+    pub unk8: TagHash,
+    pub unkc: u32, // values: 1, 2
+    pub unk10: TablePointer<Unk80805678>,
+    pub unk20: RelPointer,
+    pub unk28: [u8; 8],
+    pub unk30: Vector4,
+}
+"
+        );
+
+        let d1 = layout.to_rust_for(true);
+        assert!(d1.contains("    pub unk10: _TablePointer<i32, u32, Unk80805678>,\n"));
+        assert!(d1.contains("    pub unk20: _RelPointer<i32, ()>,\n"));
+    }
+}
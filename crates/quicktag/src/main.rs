@@ -1,9 +1,16 @@
+mod cli;
+mod diff;
+mod graph;
 mod gui;
+mod inference;
 mod packages;
+mod pathfinding;
 mod references;
 mod scanner;
+mod search;
 mod tagtypes;
 mod text;
+mod traversal;
 mod util;
 
 use std::sync::Arc;
@@ -13,7 +20,7 @@ use destiny_pkg::{PackageManager, PackageVersion};
 use eframe::egui_wgpu::WgpuConfiguration;
 use eframe::{wgpu, IconData};
 use env_logger::Env;
-use log::info;
+use log::{error, info};
 use packages::PACKAGE_MANAGER;
 
 use crate::references::initialize_reference_names;
@@ -28,6 +35,14 @@ struct Args {
     /// Game version for the specified packages directory
     #[arg(short, value_enum)]
     version: PackageVersion,
+
+    /// Print command output as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Run a command without opening a window
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 fn main() -> eframe::Result<()> {
@@ -38,12 +53,27 @@ fn main() -> eframe::Result<()> {
     let args = Args::parse();
 
     info!("Initializing package manager");
-    let pm = PackageManager::new(args.packages_path, args.version).unwrap();
+    let pm = match PackageManager::new(args.packages_path, args.version) {
+        Ok(pm) => pm,
+        Err(e) => {
+            error!("Failed to initialize package manager: {e:?}");
+            std::process::exit(1);
+        }
+    };
 
     *PACKAGE_MANAGER.write() = Some(Arc::new(pm));
 
     initialize_reference_names();
 
+    if let Some(command) = args.command {
+        if let Err(e) = cli::run(command, args.json) {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions {
         icon_data: Some(
             IconData::try_from_png_bytes(include_bytes!("../quicktag.png"))
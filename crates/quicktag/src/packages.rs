@@ -1,7 +1,7 @@
-use destiny_pkg::PackageManager;
+use destiny_pkg::{PackageManager, TagHash};
 use eframe::epaint::mutex::RwLock;
 use lazy_static::lazy_static;
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 lazy_static! {
     pub static ref PACKAGE_MANAGER: RwLock<Option<Arc<PackageManager>>> = RwLock::new(None);
@@ -18,3 +18,38 @@ pub fn package_manager_checked() -> anyhow::Result<Arc<PackageManager>> {
 pub fn package_manager() -> Arc<PackageManager> {
     package_manager_checked().unwrap()
 }
+
+/// File name of the package with the given id, empty if it doesn't exist
+pub fn package_name(pkg_id: u16) -> String {
+    package_manager()
+        .package_paths
+        .get(&pkg_id)
+        .map(|p| {
+            Path::new(p)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses a tag as entered by the user: a 64-bit tag (16 hex digits), a decimal tag or a 32-bit tag in hex.
+/// Returns `TagHash::NONE` if the input is invalid or the 64-bit tag is unknown.
+pub fn parse_tag(input: &str) -> TagHash {
+    let input = input.trim();
+    if input.len() >= 16 {
+        let hash = u64::from_str_radix(input, 16).unwrap_or_default();
+        if let Some(t) = package_manager().hash64_table.get(&u64::from_be(hash)) {
+            t.hash32
+        } else {
+            TagHash::NONE
+        }
+    } else if input.len() > 8 && input.chars().all(char::is_numeric) {
+        let hash = input.parse().unwrap_or_default();
+        TagHash(hash)
+    } else {
+        let hash = u32::from_str_radix(input, 16).unwrap_or_default();
+        TagHash(u32::from_be(hash))
+    }
+}
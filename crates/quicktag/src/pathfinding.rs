@@ -0,0 +1,306 @@
+use std::collections::{HashSet, VecDeque};
+
+use destiny_pkg::TagHash;
+use itertools::Itertools;
+use nohash_hasher::{IntMap, IntSet};
+
+use crate::{
+    graph::outgoing_references, packages::package_manager, scanner::TagCache,
+    traversal::format_tag_entry, util::serialize_display,
+};
+
+/// A tag in a reference path
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct PathHop {
+    #[serde(serialize_with = "serialize_display")]
+    pub tag: TagHash,
+    pub label: String,
+    /// Offsets in the previous tag at which this tag is referenced, empty for the first tag
+    pub offsets: Vec<u64>,
+    /// This tag is referenced from the tag header of the previous tag
+    pub tag_header: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ReferencePath {
+    pub hops: Vec<PathHop>,
+}
+
+impl ReferencePath {
+    /// Number of references followed
+    pub fn reference_count(&self) -> usize {
+        self.hops.len().saturating_sub(1)
+    }
+
+    /// Offsets at which a hop is referenced, formatted for display
+    pub fn offset_label(hop: &PathHop) -> String {
+        hop.offsets
+            .iter()
+            .map(|o| format!("0x{o:X}"))
+            .chain(hop.tag_header.then(|| "TagHeader reference".to_string()))
+            .join(", ")
+    }
+
+    pub fn to_text(&self) -> String {
+        self.hops
+            .iter()
+            .enumerate()
+            .map(|(i, hop)| {
+                if i == 0 {
+                    hop.label.clone()
+                } else {
+                    format!(
+                        "{}└ @ {} → {}",
+                        "  ".repeat(i - 1),
+                        Self::offset_label(hop),
+                        hop.label
+                    )
+                }
+            })
+            .join("\n")
+    }
+}
+
+/// Finds up to `count` of the shortest chains of references leading from `from` to `to`, shortest first.
+/// Paths never visit a tag twice.
+pub fn find_reference_paths(
+    cache: &TagCache,
+    from: TagHash,
+    to: TagHash,
+    count: usize,
+) -> Vec<ReferencePath> {
+    let mut finder = PathFinder {
+        cache,
+        neighbours: Default::default(),
+    };
+
+    finder
+        .k_shortest_paths(from, to, count)
+        .into_iter()
+        .map(|path| finder.describe(&path))
+        .collect()
+}
+
+struct PathFinder<'a> {
+    cache: &'a TagCache,
+    /// Outgoing references of every tag visited so far, along with their offsets
+    neighbours: IntMap<TagHash, Vec<(TagHash, Vec<u64>)>>,
+}
+
+impl PathFinder<'_> {
+    fn neighbours(&mut self, tag: TagHash) -> &[(TagHash, Vec<u64>)] {
+        let cache = self.cache;
+        self.neighbours.entry(tag).or_insert_with(|| {
+            let Some(scan) = cache.hashes.get(&tag) else {
+                return vec![];
+            };
+
+            let mut targets: Vec<(TagHash, Vec<u64>)> = vec![];
+            for (target, offset) in outgoing_references(scan) {
+                match targets.iter_mut().find(|(t, _)| *t == target) {
+                    Some((_, offsets)) => offsets.push(offset),
+                    None => targets.push((target, vec![offset])),
+                }
+            }
+
+            targets
+        })
+    }
+
+    /// Breadth-first search that avoids the given tags and references
+    fn shortest_path(
+        &mut self,
+        from: TagHash,
+        to: TagHash,
+        blocked_tags: &IntSet<TagHash>,
+        blocked_references: &HashSet<(TagHash, TagHash)>,
+    ) -> Option<Vec<TagHash>> {
+        let mut previous: IntMap<TagHash, TagHash> = Default::default();
+        let mut queue = VecDeque::from([from]);
+        let mut seen: IntSet<TagHash> = Default::default();
+        seen.insert(from);
+
+        while let Some(tag) = queue.pop_front() {
+            if tag == to {
+                let mut path = vec![to];
+                while let Some(&p) = previous.get(path.last().unwrap()) {
+                    path.push(p);
+                }
+                path.reverse();
+
+                return Some(path);
+            }
+
+            let targets = self.neighbours(tag).iter().map(|(t, _)| *t).collect_vec();
+            for target in targets {
+                if blocked_tags.contains(&target)
+                    || blocked_references.contains(&(tag, target))
+                    || !seen.insert(target)
+                {
+                    continue;
+                }
+
+                previous.insert(target, tag);
+                queue.push_back(target);
+            }
+        }
+
+        None
+    }
+
+    /// Yen's algorithm, on top of breadth-first search since every reference has the same cost
+    fn k_shortest_paths(&mut self, from: TagHash, to: TagHash, count: usize) -> Vec<Vec<TagHash>> {
+        let mut paths: Vec<Vec<TagHash>> = vec![];
+        if count == 0 {
+            return paths;
+        }
+
+        let Some(shortest) = self.shortest_path(from, to, &Default::default(), &Default::default())
+        else {
+            return paths;
+        };
+        paths.push(shortest);
+
+        let mut candidates: Vec<Vec<TagHash>> = vec![];
+        while paths.len() < count {
+            let last = paths.last().unwrap().clone();
+
+            for i in 0..last.len() - 1 {
+                let spur_tag = last[i];
+                let root = &last[..=i];
+
+                // Block the next reference of every known path sharing this root, so the spur path has to deviate
+                let blocked_references: HashSet<(TagHash, TagHash)> = paths
+                    .iter()
+                    .filter(|p| p.len() > i + 1 && p[..=i] == *root)
+                    .map(|p| (p[i], p[i + 1]))
+                    .collect();
+                let blocked_tags: IntSet<TagHash> = root[..i].iter().copied().collect();
+
+                if let Some(spur_path) =
+                    self.shortest_path(spur_tag, to, &blocked_tags, &blocked_references)
+                {
+                    let path = root[..i].iter().copied().chain(spur_path).collect_vec();
+                    if !paths.contains(&path) && !candidates.contains(&path) {
+                        candidates.push(path);
+                    }
+                }
+            }
+
+            let Some(shortest) = candidates
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| p.len())
+                .map(|(i, _)| i)
+            else {
+                break;
+            };
+            paths.push(candidates.remove(shortest));
+        }
+
+        paths
+    }
+
+    fn describe(&mut self, path: &[TagHash]) -> ReferencePath {
+        let pm = package_manager();
+
+        let mut hops = vec![];
+        for (i, &tag) in path.iter().enumerate() {
+            let offsets = if i == 0 {
+                vec![]
+            } else {
+                self.neighbours(path[i - 1])
+                    .iter()
+                    .find(|(t, _)| *t == tag)
+                    .map(|(_, offsets)| offsets.clone())
+                    .unwrap_or_default()
+            };
+
+            hops.push(PathHop {
+                tag,
+                label: format_tag_entry(tag, pm.get_entry(tag).as_ref()),
+                tag_header: offsets.contains(&u64::MAX),
+                offsets: offsets.into_iter().filter(|&o| o != u64::MAX).collect(),
+            });
+        }
+
+        ReferencePath { hops }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(entry: u16) -> TagHash {
+        TagHash::new(0x100, entry)
+    }
+
+    /// Path finder over a fixed graph, without looking anything up in the cache
+    fn finder<'a>(cache: &'a TagCache, edges: &[(u16, u16)]) -> PathFinder<'a> {
+        let mut neighbours: IntMap<TagHash, Vec<(TagHash, Vec<u64>)>> = Default::default();
+        for (i, &(from, to)) in edges.iter().enumerate() {
+            neighbours
+                .entry(tag(from))
+                .or_default()
+                .push((tag(to), vec![i as u64 * 4]));
+        }
+
+        PathFinder { cache, neighbours }
+    }
+
+    fn paths(finder: &mut PathFinder, from: u16, to: u16, count: usize) -> Vec<Vec<u16>> {
+        finder
+            .k_shortest_paths(tag(from), tag(to), count)
+            .into_iter()
+            .map(|p| p.into_iter().map(|t| t.entry_index()).collect())
+            .collect()
+    }
+
+    const GRAPH: &[(u16, u16)] = &[
+        (1, 2),
+        (1, 3),
+        (1, 5),
+        (2, 4),
+        (2, 3),
+        (3, 4),
+        (5, 6),
+        (6, 4),
+        // Cycle back to the start
+        (4, 1),
+    ];
+
+    #[test]
+    fn shortest_first() {
+        let cache = TagCache::default();
+        let mut finder = finder(&cache, GRAPH);
+
+        let found = paths(&mut finder, 1, 4, 4);
+        assert_eq!(found[..2], [vec![1, 2, 4], vec![1, 3, 4]]);
+
+        let mut longer = found[2..].to_vec();
+        longer.sort();
+        assert_eq!(longer, [vec![1, 2, 3, 4], vec![1, 5, 6, 4]]);
+    }
+
+    #[test]
+    fn exhausts_simple_paths() {
+        let cache = TagCache::default();
+        let mut finder = finder(&cache, GRAPH);
+
+        let found = paths(&mut finder, 1, 4, 100);
+        assert_eq!(found.len(), 4);
+        assert!(found.windows(2).all(|w| w[0].len() <= w[1].len()));
+        assert!(found.iter().all(|p| p.iter().all_unique()));
+    }
+
+    #[test]
+    fn no_path() {
+        let cache = TagCache::default();
+        let mut finder = finder(&cache, GRAPH);
+
+        assert!(paths(&mut finder, 4, 7, 3).is_empty());
+        assert!(paths(&mut finder, 1, 4, 0).is_empty());
+        assert_eq!(paths(&mut finder, 2, 2, 3), [vec![2]]);
+    }
+}
@@ -1,10 +1,20 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
 use eframe::epaint::mutex::RwLock;
-use log::warn;
+use itertools::Itertools;
+use log::{info, warn};
 use nohash_hasher::IntMap;
 
-use crate::packages::{package_manager, package_manager_checked};
+use crate::{
+    packages::{package_manager, package_manager_checked},
+    scanner::exe_relative_path,
+};
 
-// TODO(cohae): User-defined references
 lazy_static::lazy_static! {
     pub static ref REFERENCE_MAP_BASE_PRIMITIVES: IntMap<u32, &'static str> = IntMap::from_iter([
         (0x80800000, "SBungieScript"),
@@ -72,32 +82,391 @@ lazy_static::lazy_static! {
         (0x80809B06, "SEntityResource")
     ]);
 
-    pub static ref REFERENCE_NAMES: RwLock<IntMap<u32, &'static str>> = RwLock::new(Default::default());
+    pub static ref REFERENCE_NAMES: RwLock<IntMap<u32, String>> = RwLock::new(Default::default());
+
+    /// Notes on the fields of a class, by offset
+    pub static ref REFERENCE_FIELD_NOTES: RwLock<IntMap<u32, IntMap<u64, String>>> = RwLock::new(Default::default());
 }
 
-pub fn initialize_reference_names() {
-    if package_manager_checked().is_err() {
-        panic!("Called initialize_reference_names, but package manager is not initialized!")
+/// Class names and field notes, as stored in the user reference file
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug)]
+pub struct ReferenceDatabase {
+    pub references: Vec<ReferenceDefinition>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ReferenceDefinition {
+    /// Class hash as displayed by quicktag (eg. 83988080)
+    #[serde(with = "class_hex")]
+    pub class: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldNote>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct FieldNote {
+    /// Offset of the field from the start of the structure (eg. 0x10)
+    #[serde(with = "offset_hex")]
+    pub offset: u64,
+    pub note: String,
+}
+
+impl ReferenceDatabase {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(f))
+            .with_context(|| format!("Failed to parse {}", path.display()))
     }
 
-    let mut references: IntMap<u32, &'static str> = REFERENCE_MAP_BASE_PRIMITIVES.clone();
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let f =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(f);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer
+            .flush()
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
 
-    let version_specific = match package_manager().version {
-        destiny_pkg::PackageVersion::DestinyTheTakenKing => REFERENCE_MAP_TTK.clone(),
-        destiny_pkg::PackageVersion::Destiny2Shadowkeep => REFERENCE_MAP_SK.clone(),
-        destiny_pkg::PackageVersion::Destiny2BeyondLight
-        | destiny_pkg::PackageVersion::Destiny2WitchQueen
-        | destiny_pkg::PackageVersion::Destiny2Lightfall => REFERENCE_MAP_BL.clone(),
-        _ => {
-            warn!(
-                "No reference table found for {:?}",
-                package_manager().version
+    /// Adds the definitions from `other`, replacing the names of classes that are already defined. Field notes are
+    /// merged per offset.
+    pub fn merge(&mut self, other: ReferenceDatabase) {
+        for definition in other.references {
+            match self
+                .references
+                .iter_mut()
+                .find(|d| d.class == definition.class)
+            {
+                Some(existing) => {
+                    existing.name = definition.name;
+                    for field in definition.fields {
+                        match existing
+                            .fields
+                            .iter_mut()
+                            .find(|f| f.offset == field.offset)
+                        {
+                            Some(f) => f.note = field.note,
+                            None => existing.fields.push(field),
+                        }
+                    }
+                    existing.fields.sort_by_key(|f| f.offset);
+                }
+                None => self.references.push(definition),
+            }
+        }
+
+        self.references.sort_by_key(|d| d.class.to_be());
+    }
+
+    /// The built-in names for the current game version
+    pub fn builtin() -> Self {
+        let mut references: IntMap<u32, &'static str> = REFERENCE_MAP_BASE_PRIMITIVES.clone();
+
+        let version_specific = match package_manager().version {
+            destiny_pkg::PackageVersion::DestinyTheTakenKing => REFERENCE_MAP_TTK.clone(),
+            destiny_pkg::PackageVersion::Destiny2Shadowkeep => REFERENCE_MAP_SK.clone(),
+            destiny_pkg::PackageVersion::Destiny2BeyondLight
+            | destiny_pkg::PackageVersion::Destiny2WitchQueen
+            | destiny_pkg::PackageVersion::Destiny2Lightfall => REFERENCE_MAP_BL.clone(),
+            _ => {
+                warn!(
+                    "No reference table found for {:?}",
+                    package_manager().version
+                );
+                Default::default()
+            }
+        };
+
+        references.extend(version_specific);
+
+        Self {
+            references: references
+                .into_iter()
+                .sorted_by_key(|(class, _)| class.to_be())
+                .map(|(class, name)| ReferenceDefinition {
+                    class,
+                    name: name.to_string(),
+                    fields: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    /// The built-in names with the user reference file merged over them
+    pub fn current() -> Self {
+        let mut database = Self::builtin();
+        database.merge(load_user_references());
+        database
+    }
+}
+
+/// Path of the user reference file for the current game version, next to the executable
+pub fn user_references_path() -> PathBuf {
+    exe_relative_path(format!(
+        "references_{}.json",
+        package_manager().version.id()
+    ))
+}
+
+fn load_user_references() -> ReferenceDatabase {
+    let path = user_references_path();
+    if !path.exists() {
+        return Default::default();
+    }
+
+    match ReferenceDatabase::load(&path) {
+        Ok(database) => {
+            info!(
+                "Loaded {} user references from {}",
+                database.references.len(),
+                path.display()
             );
+            database
+        }
+        Err(e) => {
+            warn!("Failed to load user references: {e:?}");
             Default::default()
         }
+    }
+}
+
+/// Merges a reference file into the user reference file and reloads the reference names.
+/// Returns the number of definitions imported.
+pub fn import_references(path: &Path) -> anyhow::Result<usize> {
+    let count = merge_reference_file(path, &user_references_path())?;
+    initialize_reference_names();
+
+    Ok(count)
+}
+
+/// Merges the reference file at `path` into the one at `target`, creating it if it doesn't exist.
+/// Fails without writing anything if `target` can't be read, so a broken file isn't replaced.
+fn merge_reference_file(path: &Path, target: &Path) -> anyhow::Result<usize> {
+    let imported = ReferenceDatabase::load(path)?;
+    let count = imported.references.len();
+
+    let mut database = if target.exists() {
+        ReferenceDatabase::load(target)?
+    } else {
+        ReferenceDatabase::default()
     };
 
-    references.extend(version_specific);
+    database.merge(imported);
+    database.save(target)?;
+
+    Ok(count)
+}
+
+/// Writes every known class name and field note, or only the user-defined ones, to a reference file
+pub fn export_references(path: &Path, user_only: bool) -> anyhow::Result<usize> {
+    let database = if user_only {
+        load_user_references()
+    } else {
+        ReferenceDatabase::current()
+    };
+
+    database.save(path)?;
+
+    Ok(database.references.len())
+}
+
+/// Loads the built-in reference names for the current game version, with the user reference file merged over them
+pub fn initialize_reference_names() {
+    if package_manager_checked().is_err() {
+        panic!("Called initialize_reference_names, but package manager is not initialized!")
+    }
+
+    let database = ReferenceDatabase::current();
+
+    *REFERENCE_FIELD_NOTES.write() = database
+        .references
+        .iter()
+        .filter(|d| !d.fields.is_empty())
+        .map(|d| {
+            (
+                d.class,
+                d.fields
+                    .iter()
+                    .map(|f| (f.offset, f.note.clone()))
+                    .collect(),
+            )
+        })
+        .collect();
 
-    *REFERENCE_NAMES.write() = references;
+    *REFERENCE_NAMES.write() = database
+        .references
+        .into_iter()
+        .map(|d| (d.class, d.name))
+        .collect();
+}
+
+mod class_hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(class: &u32, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("{:08X}", class.to_be()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+        let s = String::deserialize(d)?;
+        u32::from_str_radix(s.trim().trim_start_matches("D2Class_"), 16)
+            .map(u32::from_be)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+mod offset_hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(offset: &u64, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("0x{offset:X}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        let s = String::deserialize(d)?;
+        let s = s.trim();
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(class: u32, name: &str, fields: &[(u64, &str)]) -> ReferenceDefinition {
+        ReferenceDefinition {
+            class,
+            name: name.to_string(),
+            fields: fields
+                .iter()
+                .map(|&(offset, note)| FieldNote {
+                    offset,
+                    note: note.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn merge_definitions() {
+        let mut database = ReferenceDatabase {
+            references: vec![
+                definition(0x80809AD8, "SEntity", &[(0x8, "old"), (0x20, "kept")]),
+                definition(0x80800014, "SMaterialHash", &[]),
+            ],
+        };
+
+        database.merge(ReferenceDatabase {
+            references: vec![
+                definition(
+                    0x80809AD8,
+                    "SEntityRenamed",
+                    &[(0x10, "added"), (0x8, "new")],
+                ),
+                definition(0x80801234, "SNew", &[]),
+            ],
+        });
+
+        // Sorted by class as displayed
+        assert_eq!(
+            database
+                .references
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect_vec(),
+            ["SMaterialHash", "SNew", "SEntityRenamed"]
+        );
+
+        let fields = database.references[2]
+            .fields
+            .iter()
+            .map(|f| (f.offset, f.note.as_str()))
+            .collect_vec();
+        assert_eq!(fields, [(0x8, "new"), (0x10, "added"), (0x20, "kept")]);
+    }
+
+    #[test]
+    fn hex_serialization() {
+        let json =
+            serde_json::to_value(definition(0x80809AD8, "SEntity", &[(0x1C, "note")])).unwrap();
+        assert_eq!(json["class"], "D89A8080");
+        assert_eq!(json["fields"][0]["offset"], "0x1C");
+
+        // Fields are left out when there are none
+        let json = serde_json::to_value(definition(0x80809AD8, "SEntity", &[])).unwrap();
+        assert!(json.get("fields").is_none());
+    }
+
+    #[test]
+    fn hex_deserialization() {
+        let definition: ReferenceDefinition = serde_json::from_str(
+            r#"{"class": " D2Class_D89A8080", "name": "SEntity", "fields": [
+                {"offset": "0x1c", "note": "hex"},
+                {"offset": "0X20", "note": "upper"},
+                {"offset": "48", "note": "decimal"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(definition.class, 0x80809AD8);
+        assert_eq!(
+            definition.fields.iter().map(|f| f.offset).collect_vec(),
+            [0x1C, 0x20, 48]
+        );
+
+        assert!(serde_json::from_str::<ReferenceDefinition>(
+            r#"{"class": "not hex", "name": "SEntity"}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<FieldNote>(r#"{"offset": "0xZZ", "note": ""}"#).is_err());
+    }
+
+    #[test]
+    fn merge_into_file() {
+        let dir = std::env::temp_dir().join(format!("quicktag_references_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (imported, target) = (dir.join("imported.json"), dir.join("user.json"));
+
+        ReferenceDatabase {
+            references: vec![definition(0x80801234, "SNew", &[])],
+        }
+        .save(&imported)
+        .unwrap();
+
+        // Created if it doesn't exist yet
+        std::fs::remove_file(&target).ok();
+        assert_eq!(merge_reference_file(&imported, &target).unwrap(), 1);
+        assert_eq!(
+            ReferenceDatabase::load(&target).unwrap().references.len(),
+            1
+        );
+
+        ReferenceDatabase {
+            references: vec![definition(0x80809AD8, "SEntity", &[])],
+        }
+        .save(&target)
+        .unwrap();
+        assert_eq!(merge_reference_file(&imported, &target).unwrap(), 1);
+        assert_eq!(
+            ReferenceDatabase::load(&target)
+                .unwrap()
+                .references
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect_vec(),
+            ["SNew", "SEntity"]
+        );
+
+        // A file that can't be parsed is left alone
+        std::fs::write(&target, "{ not json").unwrap();
+        assert!(merge_reference_file(&imported, &target).is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "{ not json");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
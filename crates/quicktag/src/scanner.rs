@@ -7,6 +7,7 @@ use std::{
     time::SystemTime,
 };
 
+use anyhow::Context;
 use binrw::{BinReaderExt, Endian};
 use destiny_pkg::{PackageManager, PackageVersion, TagHash, TagHash64};
 use eframe::epaint::mutex::RwLock;
@@ -23,24 +24,99 @@ use crate::{
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct TagCache {
-    /// Timestamp of the packages directory
-    pub timestamp: u64,
-
     pub version: u32,
 
+    /// State of every package file at the time it was scanned
+    pub packages: IntMap<u16, PackageFileState>,
+
     pub hashes: IntMap<TagHash, ScanResult>,
 }
 
+impl TagCache {
+    /// Reads a cache file, only caches made by this version of quicktag can be read
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let cache_file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let cache: TagCache = bincode::deserialize_from(zstd::Decoder::new(cache_file)?)
+            .with_context(|| format!("{} is not a valid tag cache", path.display()))?;
+
+        anyhow::ensure!(
+            cache.version == TagCache::default().version,
+            "{} is a v{} cache, expected v{}",
+            path.display(),
+            cache.version,
+            TagCache::default().version
+        );
+
+        Ok(cache.without_missing_tags())
+    }
+
+    /// Whether the tag exists in the packages the cache was built from
+    pub fn has_tag(&self, tag: TagHash) -> bool {
+        has_tag(&self.packages, tag)
+    }
+
+    /// Drops references to tags that don't exist. The cache file keeps every value that looks like a tag hash, so
+    /// references to tags that are added later can be resolved without rescanning the tags referencing them.
+    fn without_missing_tags(mut self) -> Self {
+        for scan in self.hashes.values_mut() {
+            scan.file_hashes.retain(|h| has_tag(&self.packages, h.hash));
+        }
+
+        self
+    }
+}
+
+fn has_tag(packages: &IntMap<u16, PackageFileState>, tag: TagHash) -> bool {
+    packages
+        .get(&tag.pkg_id())
+        .is_some_and(|p| (tag.entry_index() as usize) < p.entry_count)
+}
+
 impl Default for TagCache {
     fn default() -> Self {
         Self {
-            timestamp: 0,
-            version: 3,
+            version: 9,
+            packages: Default::default(),
             hashes: Default::default(),
         }
     }
 }
 
+/// Used to detect packages that changed since the cache was built
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct PackageFileState {
+    pub path: String,
+    /// Modification time in seconds since the unix epoch
+    pub modified: u64,
+    pub size: u64,
+    pub entry_count: usize,
+}
+
+impl PackageFileState {
+    pub fn read(path: &str, entry_count: usize) -> Self {
+        let metadata = std::fs::metadata(path).ok();
+
+        Self {
+            path: path.to_string(),
+            entry_count,
+            modified: metadata
+                .as_ref()
+                .and_then(|m| {
+                    Some(
+                        m.modified()
+                            .ok()?
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .ok()?
+                            .as_secs(),
+                    )
+                })
+                .unwrap_or(0),
+            size: metadata.map(|m| m.len()).unwrap_or(0),
+        }
+    }
+}
+
 // Shareable read-only context
 pub struct ScannerContext {
     pub valid_file_hashes: IntSet<TagHash>,
@@ -53,11 +129,17 @@ pub struct ScannerContext {
 pub struct ScanResult {
     /// Were we able to read the tag data?
     pub successful: bool,
+    /// Hash of the tag data, `None` for tags that weren't scanned
+    pub content_hash: Option<u64>,
 
+    /// Every value that looks like a tag hash, see [`ScanResult::retain_existing_tags`]
     pub file_hashes: Vec<ScannedHash<TagHash>>,
     pub file_hashes64: Vec<ScannedHash<TagHash64>>,
     pub string_hashes: Vec<ScannedHash<u32>>,
     pub raw_strings: Vec<String>,
+    pub arrays: Vec<ScannedArray>,
+    /// Relative pointers to arrays and raw string blobs, not including the ones in table pointers
+    pub relative_pointers: Vec<ScannedPointer>,
 
     /// References from other files
     pub references: Vec<TagHash>,
@@ -67,28 +149,117 @@ impl Default for ScanResult {
     fn default() -> Self {
         ScanResult {
             successful: true,
+            content_hash: None,
             file_hashes: Default::default(),
             file_hashes64: Default::default(),
             string_hashes: Default::default(),
             raw_strings: Default::default(),
+            arrays: Default::default(),
+            relative_pointers: Default::default(),
             references: Default::default(),
         }
     }
 }
 
+impl ScanResult {
+    /// Drops references to tags that don't exist in `context`
+    pub fn retain_existing_tags(&mut self, context: &ScannerContext) {
+        self.file_hashes
+            .retain(|h| context.valid_file_hashes.contains(&h.hash));
+    }
+}
+
 #[derive(Clone, serde::Deserialize, serde::Serialize, Debug)]
 pub struct ScannedHash<T: Sized> {
     pub offset: u64,
     pub hash: T,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug)]
 pub struct ScannedArray {
+    /// Offset of the array header, which starts with the element count
     pub offset: u64,
+    /// Size of the array header, 8 bytes for Destiny 1 and 16 bytes for Destiny 2
+    pub header_size: u64,
     pub count: usize,
+    /// Reference class of the elements
     pub class: u32,
+    /// Offsets of the table pointers (count followed by a relative offset) referencing this array
+    pub references: Vec<u64>,
 }
 
+impl ScannedArray {
+    /// Offset of the first element
+    pub fn data_offset(&self) -> u64 {
+        self.offset + self.header_size
+    }
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug)]
+pub struct ScannedPointer {
+    pub offset: u64,
+    pub target: u64,
+}
+
+/// A part of a tag's data, either the root structure or the elements of an array
+#[derive(Clone, Debug)]
+pub struct TagRegion {
+    pub start: u64,
+    pub end: u64,
+    /// Element count and class for arrays, `None` for the root structure
+    pub array: Option<(usize, u32)>,
+}
+
+impl TagRegion {
+    /// Estimated size of a single element, the data of every array is padded to 16 bytes so this isn't exact
+    pub fn element_size(&self) -> Option<u64> {
+        let (count, _) = self.array?;
+        (count != 0).then(|| (self.end - self.start) / count as u64)
+    }
+
+    /// Size of a single element, if the estimate can be trusted. With at least 16 elements, the padding is less
+    /// than a byte per element and gets rounded away.
+    pub fn exact_element_size(&self) -> Option<u64> {
+        let (count, _) = self.array?;
+        self.element_size().filter(|_| count >= 16)
+    }
+}
+
+/// Splits tag data into the root structure and the data of every array, based on where the array headers are
+pub fn tag_regions(data_len: u64, arrays: &[ScannedArray]) -> Vec<TagRegion> {
+    let arrays = arrays.iter().sorted_by_key(|a| a.offset).collect_vec();
+
+    // Array headers are preceded by their class
+    let region_end = |i: usize| {
+        arrays
+            .get(i)
+            .map(|a| a.offset.saturating_sub(4))
+            .unwrap_or(data_len)
+            .min(data_len)
+    };
+
+    let mut regions = vec![TagRegion {
+        start: 0,
+        end: region_end(0),
+        array: None,
+    }];
+
+    for (i, array) in arrays.iter().enumerate() {
+        let start = array.data_offset().min(data_len);
+        regions.push(TagRegion {
+            start,
+            end: region_end(i + 1).max(start),
+            array: Some((array.count, array.class)),
+        });
+    }
+
+    regions
+}
+
+/// Class of array headers, the header itself starts right after
+pub const ARRAY_HEADER_CLASSES: [u32; 2] = [0x80809fb8, 0x80800184];
+pub const RAW_STRING_BLOB_CLASS: u32 = 0x80800065;
+
 pub const FNV1_BASE: u32 = 0x811c9dc5;
 pub const FNV1_PRIME: u32 = 0x01000193;
 pub fn fnv1(data: &[u8]) -> u32 {
@@ -97,8 +268,18 @@ pub fn fnv1(data: &[u8]) -> u32 {
     })
 }
 
+/// 64-bit FNV-1a, used to detect changed tag data
+pub fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |acc, b| {
+        (acc ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub fn scan_file(context: &ScannerContext, data: &[u8]) -> ScanResult {
-    let mut r = ScanResult::default();
+    let mut r = ScanResult {
+        content_hash: Some(content_hash(data)),
+        ..Default::default()
+    };
 
     for (i, v) in data.chunks_exact(4).enumerate() {
         let m: [u8; 4] = v.try_into().unwrap();
@@ -107,7 +288,7 @@ pub fn scan_file(context: &ScannerContext, data: &[u8]) -> ScanResult {
         let offset = (i * 4) as u64;
         let hash = TagHash(value);
 
-        if hash.is_pkg_file() && context.valid_file_hashes.contains(&hash) {
+        if hash.is_pkg_file() {
             r.file_hashes.push(ScannedHash { offset, hash });
         }
 
@@ -118,7 +299,7 @@ pub fn scan_file(context: &ScannerContext, data: &[u8]) -> ScanResult {
         //     });
         // }
 
-        if value == 0x80800065 {
+        if value == RAW_STRING_BLOB_CLASS {
             r.raw_strings.extend(
                 read_raw_string_blob(data, offset)
                     .into_iter()
@@ -145,25 +326,105 @@ pub fn scan_file(context: &ScannerContext, data: &[u8]) -> ScanResult {
         }
     }
 
-    // let mut cur = Cursor::new(data);
-    // for c in &r.classes {
-    //     if c.hash == 0x80809fb8 {
-    //         cur.seek(SeekFrom::Start(c.offset + 4)).unwrap();
+    scan_arrays(context, data, &mut r);
 
-    //         let mut count_bytes = [0; 8];
-    //         cur.read_exact(&mut count_bytes).unwrap();
-    //         let mut class_bytes = [0; 4];
-    //         cur.read_exact(&mut class_bytes).unwrap();
+    r
+}
 
-    //         r.arrays.push(ScannedArray {
-    //             offset: c.offset + 4,
-    //             count: u64::from_le_bytes(count_bytes) as usize,
-    //             class: u32::from_le_bytes(class_bytes),
-    //         });
-    //     }
-    // }
+/// Finds array headers, along with the table pointers and relative pointers referencing them
+fn scan_arrays(context: &ScannerContext, data: &[u8], r: &mut ScanResult) {
+    // Destiny 1 uses 32-bit counts and offsets
+    let word_size = if context.endian == Endian::Big { 4 } else { 8 };
+    let read_word = |offset: u64| -> Option<u64> {
+        let bytes = data.get(offset as usize..(offset + word_size) as usize)?;
+        Some(if word_size == 4 {
+            u32_from_endian(context.endian, bytes.try_into().unwrap()) as u64
+        } else {
+            u64_from_endian(context.endian, bytes.try_into().unwrap())
+        })
+    };
+    let read_u32 = |offset: u64| -> Option<u32> {
+        let bytes = data.get(offset as usize..offset as usize + 4)?;
+        Some(u32_from_endian(context.endian, bytes.try_into().unwrap()))
+    };
 
-    r
+    let mut string_blobs = vec![];
+    for (i, v) in data.chunks_exact(4).enumerate() {
+        let value = u32_from_endian(context.endian, v.try_into().unwrap());
+        let offset = i as u64 * 4;
+
+        if ARRAY_HEADER_CLASSES.contains(&value) {
+            let header = offset + 4;
+            let (Some(count), Some(class)) = (read_word(header), read_u32(header + word_size))
+            else {
+                continue;
+            };
+
+            // Every element takes up at least a byte
+            if count > data.len() as u64 {
+                continue;
+            }
+
+            r.arrays.push(ScannedArray {
+                offset: header,
+                // Count and class, each padded to the word size
+                header_size: word_size * 2,
+                count: count as usize,
+                class,
+                references: vec![],
+            });
+        }
+
+        if value == RAW_STRING_BLOB_CLASS {
+            string_blobs.push(offset + 4);
+        }
+    }
+
+    if r.arrays.is_empty() && string_blobs.is_empty() {
+        return;
+    }
+
+    let headers: IntMap<u64, usize> = r
+        .arrays
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (a.offset, i))
+        .collect();
+
+    // Table pointers are a count followed by an offset relative to the offset field
+    let mut table_pointer_offsets = IntSet::default();
+    for offset in (0..data.len() as u64).step_by(word_size as usize) {
+        let (Some(count), Some(relative)) = (read_word(offset), read_word(offset + word_size))
+        else {
+            break;
+        };
+
+        let target = (offset + word_size).wrapping_add(relative);
+        if let Some(&i) = headers.get(&target) {
+            if r.arrays[i].count as u64 == count {
+                r.arrays[i].references.push(offset);
+                table_pointer_offsets.insert(offset + word_size);
+            }
+        }
+    }
+
+    let targets: IntSet<u64> = r
+        .arrays
+        .iter()
+        .flat_map(|a| [a.offset, a.data_offset()])
+        .chain(string_blobs)
+        .collect();
+
+    for offset in (0..data.len() as u64).step_by(word_size as usize) {
+        let Some(relative) = read_word(offset) else {
+            break;
+        };
+
+        let target = offset.wrapping_add(relative);
+        if relative != 0 && targets.contains(&target) && !table_pointer_offsets.contains(&offset) {
+            r.relative_pointers.push(ScannedPointer { offset, target });
+        }
+    }
 }
 
 pub fn read_raw_string_blob(data: &[u8], offset: u64) -> Vec<(u64, String)> {
@@ -292,10 +553,39 @@ pub fn scanner_progress() -> ScanStatus {
     *SCANNER_PROGRESS.read()
 }
 
-pub fn load_tag_cache(version: PackageVersion) -> TagCache {
+/// Returned by [`load_tag_cache`] when the cache file was written by a newer version of quicktag
+#[derive(Debug)]
+pub struct FutureCacheError {
+    pub cache_name: String,
+    pub cache_version: u32,
+}
+
+impl Display for FutureCacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cache file {} is newer than this build of quicktag (cache: v{}, expected: v{})",
+            self.cache_name,
+            self.cache_version,
+            TagCache::default().version
+        )
+    }
+}
+
+impl std::error::Error for FutureCacheError {}
+
+/// Loads the tag cache for the current package manager, rebuilding it if it's missing or out of date.
+/// Fails with [`FutureCacheError`] if the cache was written by a newer version of quicktag.
+///
+/// Only packages that changed since the cache was written are rescanned, the results for the other packages are
+/// taken from the existing cache. Tag hashes are cached whether the tag exists or not, so references from unchanged
+/// packages to tags added by the changed packages are still found. That doesn't work for 64-bit tag hashes and string
+/// hashes, references to ones added since the cache was written are only found in rescanned packages.
+pub fn load_tag_cache(version: PackageVersion) -> anyhow::Result<TagCache> {
     let cache_name = format!("tags_{}.cache", version.id());
     let cache_file_path = exe_relative_path(&cache_name);
 
+    let mut previous_cache = None;
     if let Ok(cache_file) = File::open(&cache_file_path) {
         info!("Existing cache file found, loading");
         *SCANNER_PROGRESS.write() = ScanStatus::LoadingCache;
@@ -305,40 +595,7 @@ pub fn load_tag_cache(version: PackageVersion) -> TagCache {
                 if let Ok(cache) = bincode::deserialize_from::<_, TagCache>(zstd_decoder) {
                     match cache.version.cmp(&TagCache::default().version) {
                         std::cmp::Ordering::Equal => {
-                            let current_pkg_timestamp =
-                                std::fs::metadata(&package_manager().package_dir)
-                                    .ok()
-                                    .and_then(|m| {
-                                        Some(
-                                            m.modified()
-                                                .ok()?
-                                                .duration_since(SystemTime::UNIX_EPOCH)
-                                                .ok()?
-                                                .as_secs(),
-                                        )
-                                    })
-                                    .unwrap_or(0);
-
-                            if cache.timestamp < current_pkg_timestamp {
-                                info!(
-                                    "Cache is out of date, rebuilding (cache: {}, package dir: {})",
-                                    chrono::NaiveDateTime::from_timestamp_opt(
-                                        cache.timestamp as i64,
-                                        0
-                                    )
-                                    .unwrap()
-                                    .format("%Y-%m-%d"),
-                                    chrono::NaiveDateTime::from_timestamp_opt(
-                                        current_pkg_timestamp as i64,
-                                        0
-                                    )
-                                    .unwrap()
-                                    .format("%Y-%m-%d"),
-                                );
-                            } else {
-                                *SCANNER_PROGRESS.write() = ScanStatus::None;
-                                return cache;
-                            }
+                            previous_cache = Some(cache);
                         }
                         std::cmp::Ordering::Less => {
                             info!(
@@ -348,19 +605,12 @@ pub fn load_tag_cache(version: PackageVersion) -> TagCache {
                             );
                         }
                         std::cmp::Ordering::Greater => {
-                            error!("Tried to open a future version cache with an old quicktag version (cache: {}, quicktag: {})",
-                                cache.version,
-                                TagCache::default().version
-                            );
-
-                            native_dialog::MessageDialog::new()
-                                .set_type(native_dialog::MessageType::Error)
-                                .set_title("Future cache")
-                                .set_text(&format!("Your cache file ({cache_name}) is newer than this build of quicktag\n\nCache version: v{}\nExpected version: v{}", cache.version, TagCache::default().version))
-                                .show_alert()
-                                .unwrap();
-
-                            std::process::exit(21);
+                            *SCANNER_PROGRESS.write() = ScanStatus::None;
+                            return Err(FutureCacheError {
+                                cache_name,
+                                cache_version: cache.version,
+                            }
+                            .into());
                         }
                     }
                 } else {
@@ -371,21 +621,52 @@ pub fn load_tag_cache(version: PackageVersion) -> TagCache {
         }
     }
 
-    *SCANNER_PROGRESS.write() = ScanStatus::CreatingScanner;
-    let scanner_context = Arc::new(
-        create_scanner_context(&package_manager()).expect("Failed to create scanner context"),
+    let pm = package_manager();
+    let package_states: IntMap<u16, PackageFileState> = pm
+        .package_paths
+        .iter()
+        .map(|(&pkg_id, path)| {
+            let entry_count = pm.package_entry_index.get(&pkg_id).map_or(0, |e| e.len());
+            (pkg_id, PackageFileState::read(path, entry_count))
+        })
+        .collect();
+
+    let (changed_packages, removed_packages) = package_changes(
+        previous_cache.as_ref().map(|c| &c.packages),
+        &package_states,
     );
 
-    let all_pkgs = package_manager()
-        .package_paths
-        .values()
-        .cloned()
+    if previous_cache.is_some() {
+        if changed_packages.is_empty() && removed_packages.is_empty() {
+            *SCANNER_PROGRESS.write() = ScanStatus::None;
+            return Ok(previous_cache.unwrap().without_missing_tags());
+        }
+
+        info!(
+            "Cache is out of date, rescanning {} changed packages ({} removed)",
+            changed_packages.len(),
+            removed_packages.len()
+        );
+    }
+
+    *SCANNER_PROGRESS.write() = ScanStatus::CreatingScanner;
+    let scanner_context =
+        Arc::new(create_scanner_context(&pm).context("Failed to create scanner context")?);
+
+    let stale_packages: IntSet<u16> = changed_packages.union(&removed_packages).copied().collect();
+    let mut cache = previous_cache
+        .map(|c| untransform_tag_cache(c, &scanner_context, &stale_packages))
+        .unwrap_or_default();
+
+    let changed_pkgs = changed_packages
+        .iter()
+        .map(|pkg_id| package_states[pkg_id].path.clone())
         .collect_vec();
 
-    let package_count = all_pkgs.len();
-    let cache: IntMap<TagHash, ScanResult> = all_pkgs
+    let package_count = changed_pkgs.len();
+    let scanned: IntMap<TagHash, ScanResult> = changed_pkgs
         .par_iter()
-        .map_with(scanner_context, |context, path| {
+        .map_with(scanner_context.clone(), |context, path| {
             let current_package = {
                 let mut p = SCANNER_PROGRESS.write();
                 let current_package = if let ScanStatus::Scanning {
@@ -405,100 +686,189 @@ pub fn load_tag_cache(version: PackageVersion) -> TagCache {
                 current_package
             };
             info!("Opening pkg {path} ({}/{package_count})", current_package);
-            let pkg = version.open(path).unwrap();
+            scan_package(context, version, path)
+        })
+        .flatten()
+        .collect();
 
-            let mut all_tags = if version.is_d1() {
-                [pkg.get_all_by_type(0, None)].concat()
-            } else {
-                pkg.get_all_by_type(8, None)
-                    .iter()
-                    .chain(pkg.get_all_by_type(16, None).iter())
-                    .cloned()
-                    .collect_vec()
-            };
+    cache.extend(scanned);
 
-            // Sort tags by entry index to optimize sequential block reads
-            all_tags.sort_by_key(|v| v.0);
-
-            let mut results = IntMap::default();
-            for (t, _) in all_tags {
-                let hash = TagHash::new(pkg.pkg_id(), t as u16);
-
-                let data = match pkg.read_entry(t) {
-                    Ok(d) => d,
-                    Err(e) => {
-                        error!("Failed to read entry {path}:{t}: {e}");
-                        results.insert(
-                            hash,
-                            ScanResult {
-                                successful: false,
-                                ..Default::default()
-                            },
-                        );
-                        continue;
-                    }
-                };
+    let cache = transform_tag_cache(cache, package_states);
 
-                let mut scan_result = scan_file(context, &data);
-                if version.is_d1() {
-                    if let Some(entry) = pkg.entry(t) {
-                        let ref_tag = TagHash(entry.reference);
-                        if context.valid_file_hashes.contains(&ref_tag) {
-                            scan_result.file_hashes.insert(
-                                0,
-                                ScannedHash {
-                                    offset: u64::MAX,
-                                    hash: ref_tag,
-                                },
-                            );
-                        }
-                    }
-                }
-                results.insert(hash, scan_result);
-            }
+    *SCANNER_PROGRESS.write() = ScanStatus::WritingCache;
+    let result = write_tag_cache(&cache, &cache_file_path);
+    *SCANNER_PROGRESS.write() = ScanStatus::None;
+    result.with_context(|| format!("Failed to write {}", cache_file_path.display()))?;
 
-            results
-        })
-        .flatten()
+    Ok(cache.without_missing_tags())
+}
+
+/// Compares the package states stored in a cache with the current ones, returning the packages that are new or
+/// changed and the packages that were removed. Without a cache every package counts as changed.
+fn package_changes(
+    cached: Option<&IntMap<u16, PackageFileState>>,
+    current: &IntMap<u16, PackageFileState>,
+) -> (IntSet<u16>, IntSet<u16>) {
+    let changed = current
+        .iter()
+        .filter(|(pkg_id, state)| cached.and_then(|c| c.get(*pkg_id)) != Some(*state))
+        .map(|(&pkg_id, _)| pkg_id)
         .collect();
 
-    // panic!("{:?}", cache[&TagHash(u32::from_be(0x00408180))]);
+    let removed = cached
+        .into_iter()
+        .flat_map(|c| c.keys())
+        .filter(|pkg_id| !current.contains_key(*pkg_id))
+        .copied()
+        .collect();
 
-    let cache = transform_tag_cache(cache);
+    (changed, removed)
+}
 
-    *SCANNER_PROGRESS.write() = ScanStatus::WritingCache;
+fn write_tag_cache(cache: &TagCache, path: &Path) -> anyhow::Result<()> {
     info!("Serializing tag cache...");
-    let cache_bincode = bincode::serialize(&cache).unwrap();
+    let cache_bincode = bincode::serialize(cache)?;
     info!("Compressing tag cache...");
-    let mut writer = zstd::Encoder::new(File::create(cache_file_path).unwrap(), 5).unwrap();
-    writer.write_all(&cache_bincode).unwrap();
-    writer.finish().unwrap();
-    *SCANNER_PROGRESS.write() = ScanStatus::None;
+    let mut writer = zstd::Encoder::new(File::create(path)?, 5)?;
+    writer.write_all(&cache_bincode)?;
+    writer.finish()?;
 
-    // for (t, r) in &cache {
-    //     if matches!(t.pkg_id(), 0x3ac | 0x3da | 0x3db) {
-    //         println!(
-    //             "{} {t} {}",
-    //             package_manager().package_paths.get(&t.pkg_id()).unwrap(),
-    //             r.references.iter().map(TagHash::to_string).join(", ")
-    //         );
-    //     }
-    // }
+    Ok(())
+}
+
+/// Scans every structure tag in a package
+fn scan_package(
+    context: &ScannerContext,
+    version: PackageVersion,
+    path: &str,
+) -> IntMap<TagHash, ScanResult> {
+    let mut results = IntMap::default();
+    let result = read_package_tags(version, path, false, |hash, data| {
+        let data = match data {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Failed to read entry {path}:{}: {e}", hash.entry_index());
+                results.insert(
+                    hash,
+                    ScanResult {
+                        successful: false,
+                        ..Default::default()
+                    },
+                );
+                return;
+            }
+        };
 
+        let mut scan_result = scan_file(context, &data);
+        if version.is_d1() {
+            if let Some(entry) = package_manager().get_entry(hash) {
+                let ref_tag = TagHash(entry.reference);
+                if ref_tag.is_pkg_file() {
+                    scan_result.file_hashes.insert(
+                        0,
+                        ScannedHash {
+                            offset: u64::MAX,
+                            hash: ref_tag,
+                        },
+                    );
+                }
+            }
+        }
+        results.insert(hash, scan_result);
+    });
+
+    if let Err(e) = result {
+        error!("Skipping package: {e:?}");
+    }
+
+    results
+}
+
+/// Reads the data of every structure tag in a package, or every entry if `all_tags` is set, in the order they're
+/// stored in. Fails if the package can't be opened, errors reading a single tag are passed on to `f`.
+pub fn read_package_tags(
+    version: PackageVersion,
+    path: &str,
+    all_tags: bool,
+    mut f: impl FnMut(TagHash, anyhow::Result<Vec<u8>>),
+) -> anyhow::Result<()> {
+    let pkg = version
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open package {path}: {e}"))?;
+
+    let mut entries = if all_tags {
+        (0..pkg.entries().len()).collect_vec()
+    } else if version.is_d1() {
+        pkg.get_all_by_type(0, None)
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect_vec()
+    } else {
+        pkg.get_all_by_type(8, None)
+            .into_iter()
+            .chain(pkg.get_all_by_type(16, None))
+            .map(|(t, _)| t)
+            .collect_vec()
+    };
+
+    // Sort tags by entry index to optimize sequential block reads
+    entries.sort_unstable();
+
+    for t in entries {
+        let hash = TagHash::new(pkg.pkg_id(), t as u16);
+        f(hash, pkg.read_entry(t).map_err(|e| anyhow::anyhow!("{e}")));
+    }
+
+    Ok(())
+}
+
+/// Takes the scan results of unchanged packages back out of a transformed cache, so they can be merged with the
+/// results of the rescanned packages.
+///
+/// Results from `stale_packages` are dropped, as are the entries added by [`transform_tag_cache`] for tags that
+/// weren't scanned. 64-bit tag hashes and string hashes that are no longer valid in `context` are dropped, tag hashes
+/// are kept as they are and filtered when the cache is loaded.
+fn untransform_tag_cache(
+    cache: TagCache,
+    context: &ScannerContext,
+    stale_packages: &IntSet<u16>,
+) -> IntMap<TagHash, ScanResult> {
     cache
+        .hashes
+        .into_iter()
+        .filter(|(tag, scan)| {
+            !stale_packages.contains(&tag.pkg_id())
+                && (scan.content_hash.is_some() || !scan.successful)
+        })
+        .map(|(tag, mut scan)| {
+            scan.references.clear();
+            scan.file_hashes64
+                .retain(|h| context.valid_file_hashes64.contains(&h.hash));
+            scan.string_hashes
+                .retain(|h| context.known_string_hashes.contains(&h.hash));
+
+            (tag, scan)
+        })
+        .collect()
 }
 
-/// Transforms the tag cache to include reference lookup tables
-fn transform_tag_cache(cache: IntMap<TagHash, ScanResult>) -> TagCache {
+/// Transforms the tag cache to include reference lookup tables, only counting references to tags in `packages`
+fn transform_tag_cache(
+    cache: IntMap<TagHash, ScanResult>,
+    packages: IntMap<u16, PackageFileState>,
+) -> TagCache {
     info!("Transforming tag cache...");
 
-    let mut new_cache: TagCache = Default::default();
+    let mut new_cache = TagCache {
+        packages,
+        ..Default::default()
+    };
 
     *SCANNER_PROGRESS.write() = ScanStatus::TransformGathering;
     info!("\t- Gathering references");
     let mut direct_reference_cache: IntMap<TagHash, Vec<TagHash>> = Default::default();
     for (k2, v2) in &cache {
-        for t32 in &v2.file_hashes {
+        for t32 in v2.file_hashes.iter().filter(|h| new_cache.has_tag(h.hash)) {
             match direct_reference_cache.entry(t32.hash) {
                 std::collections::hash_map::Entry::Occupied(mut o) => {
                     o.get_mut().push(*k2);
@@ -548,21 +918,6 @@ fn transform_tag_cache(cache: IntMap<TagHash, ScanResult>) -> TagCache {
         }
     }
 
-    let timestamp = std::fs::metadata(&package_manager().package_dir)
-        .ok()
-        .and_then(|m| {
-            Some(
-                m.modified()
-                    .ok()?
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .ok()?
-                    .as_secs(),
-            )
-        })
-        .unwrap_or(0);
-
-    new_cache.timestamp = timestamp;
-
     new_cache
 }
 
@@ -574,6 +929,226 @@ fn exe_directory() -> PathBuf {
         .to_path_buf()
 }
 
-fn exe_relative_path<P: AsRef<Path>>(path: P) -> PathBuf {
+pub fn exe_relative_path<P: AsRef<Path>>(path: P) -> PathBuf {
     exe_directory().join(path.as_ref())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(offset: u64, header_size: u64, count: usize) -> ScannedArray {
+        ScannedArray {
+            offset,
+            header_size,
+            count,
+            class: 0x80801234,
+            references: vec![],
+        }
+    }
+
+    fn bounds(regions: &[TagRegion]) -> Vec<(u64, u64)> {
+        regions.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn regions_destiny2() {
+        // Out of order, like they could come from the cache
+        let arrays = [array(0x84, 16, 2), array(0x44, 16, 4)];
+        let regions = tag_regions(0xc0, &arrays);
+
+        assert_eq!(bounds(&regions), [(0, 0x40), (0x54, 0x80), (0x94, 0xc0)]);
+        assert_eq!(regions[0].array, None);
+        assert_eq!(regions[1].array, Some((4, 0x80801234)));
+        assert_eq!(regions[2].element_size(), Some(0x16));
+        assert_eq!(regions[2].exact_element_size(), None);
+    }
+
+    #[test]
+    fn exact_element_size() {
+        // 16 elements of 0xC bytes, padded to 16 bytes
+        let arrays = [array(0x4, 16, 16)];
+        let regions = tag_regions(0x14 + 0xc0 + 0x4, &arrays);
+
+        assert_eq!(regions[1].element_size(), Some(0xc));
+        assert_eq!(regions[1].exact_element_size(), Some(0xc));
+    }
+
+    #[test]
+    fn regions_destiny1() {
+        let arrays = [array(0x24, 8, 3)];
+        let regions = tag_regions(0x38, &arrays);
+
+        assert_eq!(bounds(&regions), [(0, 0x20), (0x2c, 0x38)]);
+        assert_eq!(regions[1].element_size(), Some(4));
+    }
+
+    #[test]
+    fn regions_clamped() {
+        // Header right at the end of the data, and an empty array
+        let arrays = [array(0x1c, 16, 0)];
+        let regions = tag_regions(0x24, &arrays);
+
+        assert_eq!(bounds(&regions), [(0, 0x18), (0x24, 0x24)]);
+        assert_eq!(regions[1].element_size(), None);
+        assert_eq!(bounds(&tag_regions(0x10, &[])), [(0, 0x10)]);
+    }
+
+    fn state(pkg_id: u16, modified: u64, entry_count: usize) -> (u16, PackageFileState) {
+        (
+            pkg_id,
+            PackageFileState {
+                path: format!("w64_{pkg_id:04x}.pkg"),
+                modified,
+                size: 0x1000,
+                entry_count,
+            },
+        )
+    }
+
+    fn scanned(references: &[TagHash]) -> ScanResult {
+        ScanResult {
+            content_hash: Some(0),
+            file_hashes: references
+                .iter()
+                .enumerate()
+                .map(|(i, &hash)| ScannedHash {
+                    offset: i as u64 * 4,
+                    hash,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn sorted(packages: IntSet<u16>) -> Vec<u16> {
+        packages.into_iter().sorted().collect()
+    }
+
+    #[test]
+    fn changed_packages() {
+        let cached: IntMap<_, _> = [state(1, 10, 4), state(2, 10, 4), state(3, 10, 4)]
+            .into_iter()
+            .collect();
+        let current: IntMap<_, _> = [state(1, 10, 4), state(2, 11, 4), state(4, 10, 4)]
+            .into_iter()
+            .collect();
+
+        let (changed, removed) = package_changes(Some(&cached), &current);
+        assert_eq!(sorted(changed), [2, 4]);
+        assert_eq!(sorted(removed), [3]);
+
+        let (changed, removed) = package_changes(Some(&current), &current);
+        assert!(changed.is_empty() && removed.is_empty());
+
+        let (changed, removed) = package_changes(None, &current);
+        assert_eq!(sorted(changed), [1, 2, 4]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn untransform() {
+        let context = ScannerContext {
+            valid_file_hashes: Default::default(),
+            valid_file_hashes64: [TagHash64(0x1111)].into_iter().collect(),
+            known_string_hashes: [0x2222].into_iter().collect(),
+            endian: Endian::Little,
+        };
+
+        let mut scan = scanned(&[TagHash::new(2, 0), TagHash::new(5, 9)]);
+        scan.references = vec![TagHash::new(2, 1)];
+        scan.file_hashes64 = [0x1111, 0x3333]
+            .map(|h| ScannedHash {
+                offset: 0,
+                hash: TagHash64(h),
+            })
+            .into();
+        scan.string_hashes = [0x2222, 0x4444]
+            .map(|hash| ScannedHash { offset: 0, hash })
+            .into();
+
+        let failed = ScanResult {
+            successful: false,
+            ..Default::default()
+        };
+        let not_scanned = ScanResult {
+            references: vec![TagHash::new(1, 0)],
+            ..Default::default()
+        };
+
+        let cache = TagCache {
+            hashes: [
+                (TagHash::new(1, 0), scan),
+                (TagHash::new(1, 1), failed),
+                (TagHash::new(1, 2), not_scanned),
+                (TagHash::new(2, 0), scanned(&[])),
+                (TagHash::new(3, 0), scanned(&[])),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        let hashes = untransform_tag_cache(cache, &context, &[2, 3].into_iter().collect());
+        assert_eq!(
+            hashes.keys().sorted_by_key(|t| t.0).collect_vec(),
+            [&TagHash::new(1, 0), &TagHash::new(1, 1)]
+        );
+
+        let scan = &hashes[&TagHash::new(1, 0)];
+        assert!(scan.references.is_empty());
+        // Tag hashes are only filtered when the cache is loaded
+        assert_eq!(scan.file_hashes.len(), 2);
+        assert_eq!(scan.file_hashes64.len(), 1);
+        assert_eq!(scan.file_hashes64[0].hash.0, 0x1111);
+        assert_eq!(scan.string_hashes.len(), 1);
+        assert_eq!(scan.string_hashes[0].hash, 0x2222);
+        assert!(!hashes[&TagHash::new(1, 1)].successful);
+    }
+
+    #[test]
+    fn references_to_missing_tags() {
+        let packages = [state(1, 0, 2), state(2, 0, 1)].into_iter().collect();
+        let hashes = [
+            (
+                TagHash::new(1, 0),
+                scanned(&[TagHash::new(1, 1), TagHash::new(2, 1), TagHash::new(2, 0)]),
+            ),
+            (TagHash::new(1, 1), scanned(&[TagHash::new(3, 0)])),
+        ]
+        .into_iter()
+        .collect();
+
+        let cache = transform_tag_cache(hashes, packages);
+        assert!(cache.has_tag(TagHash::new(2, 0)));
+        assert!(!cache.has_tag(TagHash::new(2, 1)));
+
+        // Added for the referenced non-structure tag, but not for the ones that don't exist
+        assert_eq!(cache.hashes.len(), 3);
+        assert_eq!(
+            cache.hashes[&TagHash::new(2, 0)].references,
+            [TagHash::new(1, 0)]
+        );
+        assert_eq!(
+            cache.hashes[&TagHash::new(1, 1)].references,
+            [TagHash::new(1, 0)]
+        );
+
+        // Kept in the cache file, so they can be resolved once the tags are added
+        assert_eq!(cache.hashes[&TagHash::new(1, 0)].file_hashes.len(), 3);
+
+        let cache = cache.without_missing_tags();
+        let references = |tag: TagHash| {
+            cache.hashes[&tag]
+                .file_hashes
+                .iter()
+                .map(|h| h.hash)
+                .collect_vec()
+        };
+        assert_eq!(
+            references(TagHash::new(1, 0)),
+            [TagHash::new(1, 1), TagHash::new(2, 0)]
+        );
+        assert!(references(TagHash::new(1, 1)).is_empty());
+    }
+}
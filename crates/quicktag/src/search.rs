@@ -0,0 +1,285 @@
+use binrw::Endian;
+use destiny_pkg::TagHash;
+use itertools::Itertools;
+use log::{error, info};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    packages::package_manager,
+    scanner::read_package_tags,
+    traversal::format_tag_entry,
+    util::{serialize_display, u32_from_endian, u64_from_endian},
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchKind {
+    F32,
+    /// Four comma-separated floats
+    Vec4,
+    U32,
+    U64,
+    /// Hex bytes, with ?? as a wildcard (eg. "DE AD ?? EF")
+    Hex,
+}
+
+#[derive(Clone, Debug)]
+pub enum SearchQuery {
+    F32 { value: f32, tolerance: f32 },
+    Vec4 { value: [f32; 4], tolerance: f32 },
+    U32(u32),
+    U64(u64),
+    Bytes(Vec<Option<u8>>),
+}
+
+impl SearchQuery {
+    pub fn parse(kind: SearchKind, input: &str, tolerance: f32) -> anyhow::Result<Self> {
+        let input = input.trim();
+        Ok(match kind {
+            SearchKind::F32 => SearchQuery::F32 {
+                value: input.parse()?,
+                tolerance,
+            },
+            SearchKind::Vec4 => {
+                let values: Vec<f32> = input
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.parse())
+                    .collect::<Result<_, _>>()?;
+
+                SearchQuery::Vec4 {
+                    value: values
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("A vec4 needs exactly 4 values"))?,
+                    tolerance,
+                }
+            }
+            SearchKind::U32 => SearchQuery::U32(match input.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16)?,
+                None if input.starts_with('-') => input.parse::<i32>()? as u32,
+                None => input.parse()?,
+            }),
+            SearchKind::U64 => SearchQuery::U64(match input.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16)?,
+                None if input.starts_with('-') => input.parse::<i64>()? as u64,
+                None => input.parse()?,
+            }),
+            SearchKind::Hex => {
+                let digits = input.replace(char::is_whitespace, "");
+                anyhow::ensure!(
+                    !digits.is_empty() && digits.len() % 2 == 0,
+                    "Hex patterns need an even number of digits"
+                );
+
+                let pattern: Vec<Option<u8>> = digits
+                    .as_bytes()
+                    .chunks_exact(2)
+                    .map(|b| -> anyhow::Result<Option<u8>> {
+                        let b = std::str::from_utf8(b)?;
+                        if b == "??" {
+                            Ok(None)
+                        } else {
+                            Ok(Some(u8::from_str_radix(b, 16)?))
+                        }
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+
+                anyhow::ensure!(
+                    pattern.iter().any(Option::is_some),
+                    "Hex patterns can't consist of only wildcards"
+                );
+
+                SearchQuery::Bytes(pattern)
+            }
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            SearchQuery::F32 { .. } | SearchQuery::U32(_) => 4,
+            SearchQuery::Vec4 { .. } => 16,
+            SearchQuery::U64(_) => 8,
+            SearchQuery::Bytes(pattern) => pattern.len(),
+        }
+    }
+
+    /// Values are only matched at 4-byte aligned offsets, byte patterns are matched anywhere
+    fn alignment(&self) -> usize {
+        match self {
+            SearchQuery::Bytes(_) => 1,
+            _ => 4,
+        }
+    }
+
+    fn matches(&self, data: &[u8], endian: Endian) -> bool {
+        let f32_at =
+            |o: usize| f32::from_bits(u32_from_endian(endian, data[o..o + 4].try_into().unwrap()));
+        let approx = |v: f32, expected: f32, tolerance: f32| (v - expected).abs() <= tolerance;
+
+        match self {
+            SearchQuery::F32 { value, tolerance } => approx(f32_at(0), *value, *tolerance),
+            SearchQuery::Vec4 { value, tolerance } => value
+                .iter()
+                .enumerate()
+                .all(|(i, v)| approx(f32_at(i * 4), *v, *tolerance)),
+            SearchQuery::U32(value) => {
+                u32_from_endian(endian, data[..4].try_into().unwrap()) == *value
+            }
+            SearchQuery::U64(value) => {
+                u64_from_endian(endian, data[..8].try_into().unwrap()) == *value
+            }
+            SearchQuery::Bytes(pattern) => pattern
+                .iter()
+                .zip(data)
+                .all(|(p, b)| p.map(|p| p == *b).unwrap_or(true)),
+        }
+    }
+
+    /// Offsets of every match in the given data
+    pub fn find(&self, data: &[u8], endian: Endian) -> Vec<u64> {
+        let size = self.size();
+        if data.len() < size {
+            return vec![];
+        }
+
+        (0..=data.len() - size)
+            .step_by(self.alignment())
+            .filter(|&o| self.matches(&data[o..o + size], endian))
+            .map(|o| o as u64)
+            .collect()
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchMatch {
+    #[serde(serialize_with = "serialize_display")]
+    pub tag: TagHash,
+    pub label: String,
+    pub offsets: Vec<u64>,
+}
+
+/// Searches the data of every structure tag in every package, or every tag if `all_tags` is set, one package per
+/// thread. Packages that can't be opened are skipped.
+pub fn search_tags(query: &SearchQuery, all_tags: bool) -> Vec<SearchMatch> {
+    let pm = package_manager();
+    let version = pm.version;
+    let endian = version.endian();
+
+    let package_paths = pm.package_paths.values().cloned().collect_vec();
+    info!("Searching {} packages", package_paths.len());
+
+    let matches: Vec<(TagHash, Vec<u64>)> = package_paths
+        .par_iter()
+        .flat_map_iter(|path| {
+            let mut matches = vec![];
+            let result = read_package_tags(version, path, all_tags, |tag, data| match data {
+                Ok(data) => {
+                    let offsets = query.find(&data, endian);
+                    if !offsets.is_empty() {
+                        matches.push((tag, offsets));
+                    }
+                }
+                Err(e) => error!("Failed to read tag {tag}: {e}"),
+            });
+
+            if let Err(e) = result {
+                error!("Skipping package: {e:?}");
+            }
+
+            matches
+        })
+        .collect();
+
+    matches
+        .into_iter()
+        .sorted_by_key(|(tag, _)| tag.0)
+        .map(|(tag, offsets)| SearchMatch {
+            tag,
+            label: format_tag_entry(tag, pm.get_entry(tag).as_ref()),
+            offsets,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_integers() {
+        let parse = |kind, input| SearchQuery::parse(kind, input, 0.0).unwrap();
+
+        assert!(matches!(
+            parse(SearchKind::U32, "0x80809AD8"),
+            SearchQuery::U32(0x80809AD8)
+        ));
+        assert!(matches!(
+            parse(SearchKind::U32, " 1234 "),
+            SearchQuery::U32(1234)
+        ));
+        assert!(matches!(
+            parse(SearchKind::U32, "-1"),
+            SearchQuery::U32(u32::MAX)
+        ));
+        assert!(matches!(parse(SearchKind::U64, "-2"), SearchQuery::U64(v) if v == u64::MAX - 1));
+        assert!(SearchQuery::parse(SearchKind::U32, "0x100000000", 0.0).is_err());
+        assert!(SearchQuery::parse(SearchKind::U32, "abc", 0.0).is_err());
+    }
+
+    #[test]
+    fn parse_floats() {
+        let query = SearchQuery::parse(SearchKind::Vec4, "1, 2.5 -3,4", 0.1).unwrap();
+        assert!(matches!(
+            query,
+            SearchQuery::Vec4 { value, tolerance } if value == [1.0, 2.5, -3.0, 4.0] && tolerance == 0.1
+        ));
+
+        assert!(SearchQuery::parse(SearchKind::Vec4, "1, 2, 3", 0.0).is_err());
+        assert!(SearchQuery::parse(SearchKind::F32, "one", 0.0).is_err());
+    }
+
+    #[test]
+    fn parse_hex() {
+        let query = SearchQuery::parse(SearchKind::Hex, "DE ad ?? EF", 0.0).unwrap();
+        assert!(matches!(
+            query,
+            SearchQuery::Bytes(ref p) if p == &[Some(0xDE), Some(0xAD), None, Some(0xEF)]
+        ));
+
+        assert!(SearchQuery::parse(SearchKind::Hex, "ABC", 0.0).is_err());
+        assert!(SearchQuery::parse(SearchKind::Hex, "?? ??", 0.0).is_err());
+        assert!(SearchQuery::parse(SearchKind::Hex, "GG", 0.0).is_err());
+        assert!(SearchQuery::parse(SearchKind::Hex, "", 0.0).is_err());
+    }
+
+    #[test]
+    fn find_values() {
+        let mut data = vec![0u8; 32];
+        data[4..8].copy_from_slice(&1.0f32.to_le_bytes());
+        data[16..20].copy_from_slice(&1.00005f32.to_le_bytes());
+        // Unaligned, never matched as a value
+        data[25..29].copy_from_slice(&1.0f32.to_le_bytes());
+
+        let query = SearchQuery::F32 {
+            value: 1.0,
+            tolerance: 0.0001,
+        };
+        assert_eq!(query.find(&data, Endian::Little), [4, 16]);
+        assert!(query.find(&data, Endian::Big).is_empty());
+
+        let query = SearchQuery::U64(0x3f80_0000_0000_0000);
+        assert_eq!(query.find(&data, Endian::Little), [0]);
+
+        assert!(SearchQuery::U32(1)
+            .find(&data[..3], Endian::Little)
+            .is_empty());
+    }
+
+    #[test]
+    fn find_bytes() {
+        let data = [0x00, 0xDE, 0xAD, 0x12, 0xEF, 0xDE, 0xAD, 0x34, 0xEF, 0xDE];
+        let query = SearchQuery::parse(SearchKind::Hex, "DEAD??EF", 0.0).unwrap();
+
+        // Byte patterns match at any offset, but never past the end of the data
+        assert_eq!(query.find(&data, Endian::Little), [1, 5]);
+    }
+}
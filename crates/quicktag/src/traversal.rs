@@ -0,0 +1,476 @@
+use std::{fmt::Display, fmt::Write};
+
+use destiny_pkg::{package::UEntryHeader, PackageManager, TagHash, TagHash64};
+use itertools::Itertools;
+use nohash_hasher::IntSet;
+
+use crate::{
+    packages::package_manager,
+    references::REFERENCE_NAMES,
+    scanner::{read_raw_string_blob, ScanResult, TagCache},
+    tagtypes::TagType,
+    util::serialize_display,
+};
+
+pub enum ExtendedTagHash {
+    Hash32(TagHash),
+    Hash64(TagHash64),
+}
+
+impl ExtendedTagHash {
+    pub fn hash32(&self) -> TagHash {
+        match self {
+            ExtendedTagHash::Hash32(h) => *h,
+            ExtendedTagHash::Hash64(h) => package_manager()
+                .hash64_table
+                .get(&h.0)
+                .map(|v| v.hash32)
+                .unwrap_or(TagHash::NONE),
+        }
+    }
+}
+
+impl Display for ExtendedTagHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendedTagHash::Hash32(h) => h.fmt(f),
+            ExtendedTagHash::Hash64(h) => h.fmt(f),
+        }
+    }
+}
+
+pub struct ExtendedScanResult {
+    pub successful: bool,
+    pub file_hashes: Vec<ScannedHashWithEntry<ExtendedTagHash>>,
+
+    /// References from other files
+    pub references: Vec<(TagHash, UEntryHeader)>,
+}
+
+impl ExtendedScanResult {
+    pub fn from_scanresult(s: ScanResult) -> ExtendedScanResult {
+        let mut file_hashes_combined = vec![];
+
+        file_hashes_combined.extend(s.file_hashes.into_iter().map(|s| ScannedHashWithEntry {
+            offset: s.offset,
+            hash: ExtendedTagHash::Hash32(s.hash),
+            entry: package_manager().get_entry(s.hash),
+        }));
+
+        file_hashes_combined.extend(s.file_hashes64.into_iter().map(|s| {
+            ScannedHashWithEntry {
+                offset: s.offset,
+                hash: ExtendedTagHash::Hash64(s.hash),
+                entry: package_manager()
+                    .hash64_table
+                    .get(&s.hash.0)
+                    .and_then(|v| package_manager().get_entry(v.hash32)),
+            }
+        }));
+
+        file_hashes_combined.sort_unstable_by_key(|v| v.offset);
+
+        ExtendedScanResult {
+            successful: s.successful,
+            file_hashes: file_hashes_combined,
+            references: s
+                .references
+                .into_iter()
+                // Skip references from tags that are no longer in the packages (outdated cache)
+                .filter_map(|t| Some((t, package_manager().get_entry(t)?)))
+                .collect(),
+        }
+    }
+}
+
+pub struct ScannedHashWithEntry<T: Sized> {
+    pub offset: u64,
+    pub hash: T,
+    pub entry: Option<UEntryHeader>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraversalNodeKind {
+    /// Children of the tag are listed
+    Traversed,
+    /// The tag is at the maximum depth, children were not visited
+    DepthLimitReached,
+    /// A non-structure tag that was already listed
+    Seen,
+    Parent,
+    SelfReference,
+    AlreadyTraversed,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TraversalNode {
+    #[serde(serialize_with = "serialize_display")]
+    pub tag: TagHash,
+    pub label: String,
+    /// Offset of the reference in the parent tag, `None` for references from the tag header
+    pub offset: Option<u64>,
+    pub kind: TraversalNodeKind,
+
+    /// Raw strings found in the tag, only filled in if requested
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub strings: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TraversalNode>,
+}
+
+/// Hierarchy of tags referenced by a tag, every tag is only traversed once
+#[derive(Debug, serde::Serialize)]
+pub struct TagTraversal {
+    pub depth_limit: usize,
+    pub root: TraversalNode,
+}
+
+impl TagTraversal {
+    pub fn new(
+        starting_tag: TagHash,
+        depth_limit: usize,
+        cache: &TagCache,
+        show_strings: bool,
+    ) -> Self {
+        let mut seen_tags = Default::default();
+
+        Self {
+            depth_limit,
+            root: traverse_tag(
+                starting_tag,
+                TagHash::NONE,
+                Some(0),
+                0,
+                &mut seen_tags,
+                depth_limit,
+                cache,
+                show_strings,
+            ),
+        }
+    }
+
+    /// Renders the traversal as a tree of tags
+    pub fn to_text(&self) -> String {
+        let mut result = String::new();
+        let mut pipe_stack = vec![];
+        self.root
+            .write_text(&mut result, &mut pipe_stack, self.depth_limit);
+
+        result
+    }
+}
+
+impl TraversalNode {
+    fn write_text(&self, out: &mut String, pipe_stack: &mut Vec<char>, depth_limit: usize) {
+        let offset_label = match self.offset {
+            Some(offset) => format!("0x{offset:X}"),
+            None => "TagHeader reference".to_string(),
+        };
+
+        let suffix = match self.kind {
+            TraversalNodeKind::Parent => " (parent)",
+            TraversalNodeKind::SelfReference => " (self reference)",
+            TraversalNodeKind::AlreadyTraversed => " (already traversed)",
+            _ => "",
+        };
+
+        writeln!(out, "{} @ {offset_label}{suffix}", self.label).ok();
+
+        let mut line_header = String::new();
+        for s in pipe_stack.iter() {
+            write!(line_header, "{s}   ").ok();
+        }
+
+        if self.kind == TraversalNodeKind::DepthLimitReached {
+            writeln!(out, "{line_header}└ Depth limit reached ({})", depth_limit).ok();
+            return;
+        }
+
+        if self.children.is_empty() {
+            return;
+        }
+
+        if !self.strings.is_empty() {
+            writeln!(
+                out,
+                "{line_header}├──Strings: [{}]",
+                self.strings.join(", ")
+            )
+            .ok();
+        }
+
+        for (i, child) in self.children.iter().enumerate() {
+            let is_last = i + 1 == self.children.len();
+            let branch = if is_last { "└" } else { "├" };
+
+            // Last tag, add a space instead of a pipe
+            pipe_stack.push(if is_last { ' ' } else { '│' });
+
+            write!(out, "{line_header}{branch}──").ok();
+            child.write_text(out, pipe_stack, depth_limit);
+
+            pipe_stack.pop();
+        }
+
+        writeln!(out, "{line_header}").ok();
+    }
+}
+
+/// Traverses down every tag to make a hierarchy of tags
+pub fn traverse_tags(
+    starting_tag: TagHash,
+    depth_limit: usize,
+    cache: &TagCache,
+    show_strings: bool,
+) -> String {
+    TagTraversal::new(starting_tag, depth_limit, cache, show_strings).to_text()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn traverse_tag(
+    tag: TagHash,
+    parent_tag: TagHash,
+    offset: Option<u64>,
+    depth: usize,
+    seen_tags: &mut IntSet<TagHash>,
+    depth_limit: usize,
+    cache: &TagCache,
+    show_strings: bool,
+) -> TraversalNode {
+    let pm = package_manager();
+
+    seen_tags.insert(tag);
+
+    let entry = pm.get_entry(tag);
+    let mut node = TraversalNode {
+        tag,
+        label: format_tag_entry(tag, entry.as_ref()),
+        offset,
+        kind: TraversalNodeKind::Traversed,
+        strings: vec![],
+        children: vec![],
+    };
+
+    if depth >= depth_limit {
+        node.kind = TraversalNodeKind::DepthLimitReached;
+        return node;
+    }
+
+    let Some(scan_result) = cache.hashes.get(&tag).cloned() else {
+        return node;
+    };
+
+    let scan = ExtendedScanResult::from_scanresult(scan_result);
+
+    let all_hashes = scan
+        .file_hashes
+        .iter()
+        .map(|v| (v.hash.hash32(), v.offset))
+        .collect_vec();
+
+    if all_hashes.is_empty() {
+        return node;
+    }
+
+    if show_strings {
+        if let Ok(tag_data) = pm.read_tag(tag) {
+            for (i, b) in tag_data.chunks_exact(4).enumerate() {
+                let v: [u8; 4] = b.try_into().unwrap();
+                let hash = u32::from_le_bytes(v);
+
+                if hash == 0x80800065 {
+                    node.strings.extend(
+                        read_raw_string_blob(&tag_data, i as u64 * 4)
+                            .into_iter()
+                            .map(|(_, string)| string),
+                    );
+                }
+            }
+        }
+    }
+
+    for (t, offset) in all_hashes {
+        let offset = (offset != u64::MAX).then_some(offset);
+
+        if seen_tags.contains(&t) {
+            let entry = pm.get_entry(t);
+
+            let kind = if entry
+                .as_ref()
+                .map(|e| e.file_type != 8 && e.file_subtype != 16)
+                .unwrap_or_default()
+            {
+                TraversalNodeKind::Seen
+            } else if t == parent_tag {
+                TraversalNodeKind::Parent
+            } else if t == tag {
+                TraversalNodeKind::SelfReference
+            } else {
+                TraversalNodeKind::AlreadyTraversed
+            };
+
+            node.children.push(TraversalNode {
+                tag: t,
+                label: format_tag_entry(t, entry.as_ref()),
+                offset,
+                kind,
+                strings: vec![],
+                children: vec![],
+            });
+        } else {
+            node.children.push(traverse_tag(
+                t,
+                tag,
+                offset,
+                depth + 1,
+                seen_tags,
+                depth_limit,
+                cache,
+                show_strings,
+            ));
+        }
+    }
+
+    node
+}
+
+pub fn format_tag_entry(tag: TagHash, entry: Option<&UEntryHeader>) -> String {
+    format_tag_entry_in(&package_manager(), tag, entry)
+}
+
+/// Like [`format_tag_entry`], with tag names taken from the given package manager
+pub fn format_tag_entry_in(
+    pm: &PackageManager,
+    tag: TagHash,
+    entry: Option<&UEntryHeader>,
+) -> String {
+    if let Some(entry) = entry {
+        let named_tag = pm
+            .named_tags
+            .iter()
+            .find(|v| v.hash == tag)
+            .map(|v| format!("{} ", v.name))
+            .unwrap_or_default();
+
+        let ref_label = REFERENCE_NAMES
+            .read()
+            .get(&entry.reference)
+            .map(|s| format!(" ({s})"))
+            .unwrap_or_default();
+
+        format!(
+            "{named_tag}{tag} {}{ref_label} ({}+{}, ref {})",
+            TagType::from_type_subtype(entry.file_type, entry.file_subtype),
+            entry.file_type,
+            entry.file_subtype,
+            TagHash(entry.reference),
+        )
+    } else {
+        format!("{} (pkg entry not found)", tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(
+        entry: u16,
+        offset: Option<u64>,
+        kind: TraversalNodeKind,
+        children: Vec<TraversalNode>,
+    ) -> TraversalNode {
+        TraversalNode {
+            tag: TagHash::new(0x100, entry),
+            label: format!("tag{entry}"),
+            offset,
+            kind,
+            strings: vec![],
+            children,
+        }
+    }
+
+    fn traversal() -> TagTraversal {
+        let mut root = node(
+            0,
+            Some(0),
+            TraversalNodeKind::Traversed,
+            vec![
+                node(
+                    1,
+                    Some(0x10),
+                    TraversalNodeKind::Traversed,
+                    vec![node(
+                        2,
+                        Some(8),
+                        TraversalNodeKind::DepthLimitReached,
+                        vec![],
+                    )],
+                ),
+                node(1, None, TraversalNodeKind::AlreadyTraversed, vec![]),
+            ],
+        );
+        root.strings = vec!["hello".to_string()];
+
+        TagTraversal {
+            depth_limit: 2,
+            root,
+        }
+    }
+
+    #[test]
+    fn text_tree() {
+        let expected = [
+            "tag0 @ 0x0",
+            "├──Strings: [hello]",
+            "├──tag1 @ 0x10",
+            "│   └──tag2 @ 0x8",
+            "│       └ Depth limit reached (2)",
+            "│   ",
+            "└──tag1 @ TagHeader reference (already traversed)",
+            "",
+            "",
+        ];
+
+        assert_eq!(traversal().to_text(), expected.join("\n"));
+    }
+
+    #[test]
+    fn text_suffixes() {
+        for (kind, suffix) in [
+            (TraversalNodeKind::Parent, " (parent)"),
+            (TraversalNodeKind::SelfReference, " (self reference)"),
+            (TraversalNodeKind::AlreadyTraversed, " (already traversed)"),
+            (TraversalNodeKind::Seen, ""),
+        ] {
+            let traversal = TagTraversal {
+                depth_limit: 1,
+                root: node(3, Some(0x24), kind, vec![]),
+            };
+
+            assert_eq!(traversal.to_text(), format!("tag3 @ 0x24{suffix}\n"));
+        }
+    }
+
+    #[test]
+    fn json() {
+        let traversal = traversal();
+        let value = serde_json::to_value(&traversal).unwrap();
+
+        assert_eq!(value["depth_limit"], 2);
+        assert_eq!(value["root"]["tag"], traversal.root.tag.to_string());
+        assert_eq!(value["root"]["strings"], serde_json::json!(["hello"]));
+
+        let children = value["root"]["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0]["offset"], 0x10);
+        assert_eq!(children[0]["children"][0]["kind"], "depth_limit_reached");
+        assert_eq!(children[1]["offset"], serde_json::Value::Null);
+        assert_eq!(children[1]["kind"], "already_traversed");
+
+        // Empty strings and children are left out
+        assert!(children[1].get("strings").is_none());
+        assert!(children[1].get("children").is_none());
+    }
+}
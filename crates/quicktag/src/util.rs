@@ -13,3 +13,22 @@ pub fn u32_from_endian(endian: Endian, bytes: [u8; 4]) -> u32 {
         Endian::Little => u32::from_le_bytes(bytes),
     }
 }
+
+/// Serializes a value as its `Display` representation, used for tag hashes in JSON output
+pub fn serialize_display<T: std::fmt::Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// Like [`serialize_display`], for optional values
+pub fn serialize_display_option<T: std::fmt::Display, S: serde::Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
@@ -1279,7 +1279,7 @@ fn load_datatable_into_scene<R: Read + Seek>(
                     let (havok_debugshape, new_transform) =
                         if let Ok(havok_data) = package_manager().read_tag(d.unk0.havok_file) {
                             let mut cur = Cursor::new(&havok_data);
-                            match destiny_havok::shape_collection::read_shape_collection(&mut cur) {
+                            match destiny_havok::shape_collection::read_shape_collection(&mut cur, None) {
                                 Ok(o) => {
                                     if (d.unk0.shape_index as usize) < o.len() {
                                         let mut shape = o[d.unk0.shape_index as usize].clone();
@@ -1328,7 +1328,7 @@ fn load_datatable_into_scene<R: Read + Seek>(
                     let havok_debugshape =
                         if let Ok(havok_data) = package_manager().read_tag(d.unk0.havok_file) {
                             let mut cur = Cursor::new(&havok_data);
-                            match destiny_havok::shape_collection::read_shape_collection(&mut cur) {
+                            match destiny_havok::shape_collection::read_shape_collection(&mut cur, None) {
                                 Ok(o) => {
                                     if (d.unk0.shape_index as usize) < o.len() {
                                         CustomDebugShape::from_havok_shape(
@@ -1391,7 +1391,7 @@ fn load_datatable_into_scene<R: Read + Seek>(
                         package_manager().read_tag(d.unk10.havok_file)
                     {
                         let mut cur = Cursor::new(&havok_data);
-                        match destiny_havok::shape_collection::read_shape_collection(&mut cur) {
+                        match destiny_havok::shape_collection::read_shape_collection(&mut cur, None) {
                             Ok(shapes) => {
                                 let mut final_shape =
                                     destiny_havok::shape_collection::Shape::default();
@@ -1463,7 +1463,7 @@ fn load_datatable_into_scene<R: Read + Seek>(
                     match package_manager().read_tag(d.unk10.havok_file) {
                         Ok(havok_data) => {
                             let mut cur = Cursor::new(&havok_data);
-                            match destiny_havok::shape_collection::read_shape_collection(&mut cur) {
+                            match destiny_havok::shape_collection::read_shape_collection(&mut cur, None) {
                                 Ok(shapes) => {
                                     for t in &d.unk10.unk10 {
                                         if t.shape_index as usize >= shapes.len() {
@@ -1535,7 +1535,7 @@ fn load_datatable_into_scene<R: Read + Seek>(
                     match package_manager().read_tag(d.unk10.havok_file) {
                         Ok(havok_data) => {
                             let mut cur = Cursor::new(&havok_data);
-                            match destiny_havok::shape_collection::read_shape_collection(&mut cur) {
+                            match destiny_havok::shape_collection::read_shape_collection(&mut cur, None) {
                                 Ok(shapes) => {
                                     if let Some(t) = d.unk10.unk10.get(d.array_index as usize) {
                                         if t.shape_index as usize >= shapes.len() {
@@ -1597,7 +1597,7 @@ fn load_datatable_into_scene<R: Read + Seek>(
                     let (havok_debugshape, new_transform) =
                         if let Ok(havok_data) = package_manager().read_tag(d.havok_file) {
                             let mut cur = Cursor::new(&havok_data);
-                            match destiny_havok::shape_collection::read_shape_collection(&mut cur) {
+                            match destiny_havok::shape_collection::read_shape_collection(&mut cur, None) {
                                 Ok(o) => {
                                     if (d.shape_index as usize) < o.len() {
                                         let mut shape = o[d.shape_index as usize].clone();
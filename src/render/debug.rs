@@ -823,7 +823,7 @@ impl CustomDebugShape {
         shape: &destiny_havok::shape_collection::Shape,
     ) -> anyhow::Result<Self> {
         let vertices_vec4 = shape.vertices.iter().map(|v| v.extend(1.0)).collect_vec();
-        Self::new(dcs, &vertices_vec4, &shape.indices)
+        Self::new(dcs, &vertices_vec4, &shape.indices_u16()?)
     }
 }
 